@@ -0,0 +1,77 @@
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use networked_kv_store::KvStore;
+use std::hint::black_box;
+use tempfile::TempDir;
+
+/// Number of keys in the stores read from
+const KEYS: usize = 1000;
+
+fn key(i: usize) -> String {
+    format!("key{i:06}")
+}
+
+fn set(c: &mut Criterion) {
+    let mut group = c.benchmark_group("set");
+    for size in [16, 1024] {
+        let value = "v".repeat(size);
+        group.bench_function(format!("{size}B"), |b| {
+            let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+            let mut store = KvStore::open(temp_dir.path()).unwrap();
+            let mut i = 0;
+            b.iter(|| {
+                store.set(key(i % KEYS), value.clone()).unwrap();
+                i += 1;
+            });
+        });
+    }
+    group.finish();
+}
+
+fn get(c: &mut Criterion) {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path()).unwrap();
+    for i in 0..KEYS {
+        store.set(key(i), "v".repeat(100)).unwrap();
+    }
+    let mut i = 0;
+    c.bench_function("get", |b| {
+        b.iter(|| {
+            black_box(store.get(key(i % KEYS)).unwrap());
+            i += 1;
+        });
+    });
+}
+
+// Compacts a store holding about 1 MiB of stale records.
+fn compaction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compaction");
+    group.sample_size(10);
+    group.bench_function("1MiB stale", |b| {
+        b.iter_batched(
+            || {
+                let temp_dir =
+                    TempDir::new().expect("unable to create temporary working directory");
+                let mut store = KvStore::open(temp_dir.path()).unwrap();
+                store.settings().set_compaction_threshold(u64::MAX);
+                let value = "v".repeat(1024);
+                for _ in 0..2 {
+                    for i in 0..KEYS {
+                        store.set(key(i), value.clone()).unwrap();
+                    }
+                }
+                (temp_dir, store)
+            },
+            // the next write finds the log past the threshold and compacts it
+            |(temp_dir, mut store)| {
+                store.settings().set_compaction_threshold(1);
+                store.set(key(0), "v".to_owned()).unwrap();
+                (temp_dir, store)
+            },
+            BatchSize::PerIteration,
+        );
+    });
+    group.finish();
+}
+
+criterion_group!(benches, set, get, compaction);
+criterion_main!(benches);
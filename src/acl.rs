@@ -0,0 +1,412 @@
+use crate::common::{Request, Response};
+use crate::{KvsError, Result};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{hmac, pbkdf2};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::BufReader;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// How long a verified token is trusted before it is hashed again
+const TOKEN_TTL: Duration = Duration::from_secs(60);
+/// Most verified tokens remembered at once
+const MAX_CACHED_TOKENS: usize = 1024;
+
+/// What a grant allows on the keys under its prefix, each level
+/// includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// get, scan and watch
+    Read,
+    /// set and remove
+    Write,
+    /// replicate and shut the server down, when granted on every key
+    Admin,
+}
+
+/// A permission on every key starting with `prefix`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grant {
+    /// keys the grant applies to, the empty prefix matches every key
+    pub prefix: String,
+    /// what the grant allows
+    pub permission: Permission,
+}
+
+/// A user of the server, authenticating with a password, a token or both
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    /// name the user logs in with
+    pub name: String,
+    /// salted hash of the password, as made by `hash_secret`
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    /// identifies the token of the user, which reads `<token_id>.<secret>`
+    #[serde(default)]
+    pub token_id: Option<String>,
+    /// salted hash of the whole token, as made by `hash_secret`
+    #[serde(default)]
+    pub token_hash: Option<String>,
+    /// what the user may do
+    #[serde(default)]
+    pub grants: Vec<Grant>,
+}
+
+/// The users of a server and what they may do
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Acl {
+    /// known users, anybody else is turned away
+    pub users: Vec<User>,
+}
+
+impl Acl {
+    /// Reads an ACL from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let acl: Acl = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        acl.validate()?;
+        Ok(acl)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut names: Vec<_> = self.users.iter().map(|user| &user.name).collect();
+        names.sort();
+        if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(KvsError::InvalidAcl(format!("duplicate user {}", pair[0])));
+        }
+        for user in &self.users {
+            let hashes = [&user.password_hash, &user.token_hash];
+            if hashes.iter().all(|hash| hash.is_none()) {
+                return Err(KvsError::InvalidAcl(format!(
+                    "user {} has neither a password nor a token",
+                    user.name
+                )));
+            }
+            for hash in hashes.into_iter().flatten() {
+                if SecretHash::parse(hash).is_none() {
+                    return Err(KvsError::InvalidAcl(format!(
+                        "user {} has a malformed hash",
+                        user.name
+                    )));
+                }
+            }
+            let valid_id = match (&user.token_id, &user.token_hash) {
+                (Some(id), Some(_)) => !id.is_empty() && !id.contains('.'),
+                (None, None) => true,
+                _ => false,
+            };
+            if !valid_id {
+                return Err(KvsError::InvalidAcl(format!(
+                    "user {} needs a token_id without dots along with its token_hash",
+                    user.name
+                )));
+            }
+        }
+        let mut ids: Vec<_> = self.users.iter().flat_map(|user| &user.token_id).collect();
+        ids.sort();
+        if let Some(pair) = ids.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(KvsError::InvalidAcl(format!(
+                "duplicate token_id {}",
+                pair[0]
+            )));
+        }
+        Ok(())
+    }
+
+    fn user(&self, name: &str) -> Option<&User> {
+        self.users.iter().find(|user| user.name == name)
+    }
+
+    /// The user holding `token`, only its hash is checked
+    fn token_user(&self, token: &str) -> Option<&User> {
+        let (id, _) = token.split_once('.')?;
+        self.users
+            .iter()
+            .find(|user| user.token_id.as_deref() == Some(id))
+            .filter(|user| matches_hash(user.token_hash.as_deref(), token))
+    }
+}
+
+/// How a client proves who it is
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Credentials {
+    /// a user name and its password
+    Password {
+        /// name of the user
+        user: String,
+        /// password of the user
+        password: String,
+    },
+    /// a token standing for a user
+    Token(String),
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Password { user, .. } => f
+                .debug_struct("Password")
+                .field("user", user)
+                .finish_non_exhaustive(),
+            Credentials::Token(_) => f.write_str("Token(..)"),
+        }
+    }
+}
+
+/// The ACL a server enforces, shared so it can be replaced while the
+/// server runs
+///
+/// Without an ACL every client may do anything. Replacing the ACL applies
+/// to connections already authenticated from their next request on.
+///
+/// Tokens verified lately are remembered for `TOKEN_TTL`, clients sending
+/// one on every request, as over HTTP, only pay for hashing it once.
+#[derive(Clone, Default)]
+pub struct AccessControl {
+    acl: Arc<RwLock<Option<Acl>>>,
+    tokens: Arc<Mutex<TokenCache>>,
+}
+
+impl AccessControl {
+    /// Enforces `acl` from now on
+    pub fn reload(&self, acl: Acl) {
+        let mut current = self.acl.write().expect("acl lock poisoned");
+        *current = Some(acl);
+        // tokens may have been revoked
+        self.tokens().clear();
+    }
+
+    /// Returns the name of the user `credentials` belong to
+    pub(crate) fn authenticate(&self, credentials: &Credentials) -> Result<Option<String>> {
+        let acl = self.acl.read().expect("acl lock poisoned");
+        let Some(acl) = acl.as_ref() else {
+            return Ok(None);
+        };
+        let user = match credentials {
+            Credentials::Password { user, password } => acl
+                .user(user)
+                .filter(|user| matches_hash(user.password_hash.as_deref(), password))
+                .map(|user| user.name.clone()),
+            Credentials::Token(token) => {
+                let cached = self.tokens().get(token);
+                cached.or_else(|| {
+                    let user = acl.token_user(token)?.name.clone();
+                    self.tokens().insert(token, user.clone());
+                    Some(user)
+                })
+            }
+        };
+        user.map(Some).ok_or(KvsError::Unauthenticated)
+    }
+
+    fn tokens(&self) -> std::sync::MutexGuard<'_, TokenCache> {
+        self.tokens.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Hands `request` back when `user` may run it, or the error response
+    /// to send instead
+    pub(crate) fn authorize(
+        &self,
+        user: Option<&str>,
+        request: Request,
+    ) -> std::result::Result<Request, Response> {
+        if let Request::Tagged { id, request } = request {
+            return match self.authorize(user, *request) {
+                Ok(request) => Ok(Request::Tagged {
+                    id,
+                    request: Box::new(request),
+                }),
+                Err(response) => Err(Response::Tagged {
+                    id,
+                    response: Box::new(response),
+                }),
+            };
+        }
+        match self.check(user, &request) {
+            Ok(()) => Ok(request),
+            Err(e) => Err(Response::Err(e.into())),
+        }
+    }
+
+    fn check(&self, user: Option<&str>, request: &Request) -> Result<()> {
+        let acl = self.acl.read().expect("acl lock poisoned");
+        let Some(acl) = acl.as_ref() else {
+            return Ok(());
+        };
+        let user = user
+            .and_then(|name| acl.user(name))
+            .ok_or(KvsError::Unauthenticated)?;
+        let (prefix, needed) = match request {
+            Request::Get { key } => (key, Permission::Read),
+            Request::Scan { prefix } | Request::Watch { prefix, .. } => (prefix, Permission::Read),
+            Request::Set { key, .. } | Request::Remove { key } => (key, Permission::Write),
+            Request::Replicate { .. }
+            | Request::Raft { .. }
+            | Request::Shutdown
+            | Request::SlowLogGet { .. }
+            | Request::SlowLogReset
+            | Request::Checkpoint { .. } => (&String::new(), Permission::Admin),
+            Request::Auth(_) | Request::Tagged { .. } => return Ok(()),
+        };
+        let allowed = user
+            .grants
+            .iter()
+            .any(|grant| prefix.starts_with(&grant.prefix) && grant.permission >= needed);
+        if allowed {
+            Ok(())
+        } else {
+            Err(KvsError::Forbidden)
+        }
+    }
+}
+
+/// Tokens verified lately and the users they stand for
+///
+/// Entries are keyed by an HMAC of the token under a key drawn for the
+/// process, the cache holds nothing a token could be recovered from.
+struct TokenCache {
+    key: hmac::Key,
+    verified: HashMap<Vec<u8>, (String, Instant)>,
+}
+
+impl Default for TokenCache {
+    fn default() -> Self {
+        TokenCache {
+            key: hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                .expect("unable to generate a token cache key"),
+            verified: HashMap::new(),
+        }
+    }
+}
+
+impl TokenCache {
+    fn tag(&self, token: &str) -> Vec<u8> {
+        hmac::sign(&self.key, token.as_bytes()).as_ref().to_vec()
+    }
+
+    /// The user of `token` when it was verified less than `TOKEN_TTL` ago
+    fn get(&self, token: &str) -> Option<String> {
+        self.verified
+            .get(&self.tag(token))
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(user, _)| user.clone())
+    }
+
+    fn insert(&mut self, token: &str, user: String) {
+        if self.verified.len() >= MAX_CACHED_TOKENS {
+            let now = Instant::now();
+            self.verified.retain(|_, (_, expires)| *expires > now);
+        }
+        if self.verified.len() >= MAX_CACHED_TOKENS {
+            self.verified.clear();
+        }
+        let tag = self.tag(token);
+        self.verified
+            .insert(tag, (user, Instant::now() + TOKEN_TTL));
+    }
+
+    fn clear(&mut self) {
+        self.verified.clear();
+    }
+}
+
+/// Hashes a password or a token for the `password_hash` and `token_hash`
+/// of a user
+///
+/// The hash is `pbkdf2-sha256$ITERATIONS$SALT$HASH`, with a random salt and
+/// both it and the hash hex encoded.
+pub fn hash_secret(secret: &str) -> Result<String> {
+    let mut salt = [0; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| KvsError::InvalidAcl("unable to generate a salt".to_owned()))?;
+    let mut hash = [0; HASH_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        HASH_ITERATIONS,
+        &salt,
+        secret.as_bytes(),
+        &mut hash,
+    );
+    Ok(format!(
+        "{HASH_SCHEME}${HASH_ITERATIONS}${}${}",
+        hex(&salt),
+        hex(&hash)
+    ))
+}
+
+/// scheme naming how `hash_secret` hashes
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+/// rounds of PBKDF2 for new hashes, the ones of existing hashes are kept
+const HASH_ITERATIONS: NonZeroU32 = NonZeroU32::new(100_000).unwrap();
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// A hash made by `hash_secret`
+struct SecretHash {
+    iterations: NonZeroU32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl SecretHash {
+    fn parse(text: &str) -> Option<SecretHash> {
+        let mut parts = text.split('$');
+        let (Some(HASH_SCHEME), Some(iterations), Some(salt), Some(hash), None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return None;
+        };
+        let hash = unhex(hash)?;
+        if hash.len() != HASH_LEN {
+            return None;
+        }
+        Some(SecretHash {
+            iterations: iterations.parse().ok()?,
+            salt: unhex(salt)?,
+            hash,
+        })
+    }
+
+    /// Compares in constant time, not to tell how close `secret` is
+    fn matches(&self, secret: &str) -> bool {
+        pbkdf2::verify(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            self.iterations,
+            &self.salt,
+            secret.as_bytes(),
+            &self.hash,
+        )
+        .is_ok()
+    }
+}
+
+fn matches_hash(expected: Option<&str>, secret: &str) -> bool {
+    expected
+        .and_then(SecretHash::parse)
+        .is_some_and(|hash| hash.matches(secret))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(&text[at..at + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,126 @@
+use crate::common::{Request, Response, receive_async, send_async};
+use crate::watch::WatchEvent;
+use crate::{ClientTls, Credentials, KvsError, Result};
+
+use tokio::io::{self, AsyncRead, AsyncWrite, BufReader, BufWriter, ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_rustls::TlsConnector;
+
+/// A connection, in plain text or encrypted
+trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> Connection for S {}
+
+type BoxedConnection = Box<dyn Connection>;
+
+/// A client talking to a KvsServer or an AsyncKvsServer without blocking
+pub struct AsyncKvsClient {
+    reader: BufReader<ReadHalf<BoxedConnection>>,
+    writer: BufWriter<WriteHalf<BoxedConnection>>,
+}
+
+impl AsyncKvsClient {
+    /// Connects to the server at `addr`
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(AsyncKvsClient::from_connection(Box::new(stream)))
+    }
+
+    /// Connects to the server at `addr` over TLS
+    pub async fn connect_tls(addr: impl ToSocketAddrs, tls: &ClientTls) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let stream = TlsConnector::from(tls.config())
+            .connect(tls.server_name(), stream)
+            .await?;
+        Ok(AsyncKvsClient::from_connection(Box::new(stream)))
+    }
+
+    fn from_connection(connection: BoxedConnection) -> Self {
+        let (reader, writer) = io::split(connection);
+        AsyncKvsClient {
+            reader: BufReader::new(reader),
+            writer: BufWriter::new(writer),
+        }
+    }
+
+    /// Identifies the user of the connection to a server enforcing an ACL
+    ///
+    /// Fails with `Unauthenticated` when the credentials are wrong.
+    pub async fn authenticate(&mut self, credentials: Credentials) -> Result<()> {
+        self.request(&Request::Auth(credentials)).await.map(|_| ())
+    }
+
+    /// Gets a value by key
+    pub async fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.request(&Request::Get { key }).await
+    }
+
+    /// Sets a value for a key
+    pub async fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.request(&Request::Set { key, value }).await.map(|_| ())
+    }
+
+    /// Removes a key and its associated value
+    /// Returns an error if the key doesn't exist
+    pub async fn remove(&mut self, key: String) -> Result<()> {
+        self.request(&Request::Remove { key }).await.map(|_| ())
+    }
+
+    /// Returns every live key/value pair starting with `prefix`, ordered by key
+    pub async fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        send_async(&mut self.writer, &Request::Scan { prefix }).await?;
+        match receive_async(&mut self.reader).await? {
+            Some(Response::Entries(entries)) => Ok(entries),
+            Some(Response::Err(e)) => Err(e.into()),
+            Some(_) => Err(KvsError::UnexpectedCommandType),
+            None => Err(connection_closed()),
+        }
+    }
+
+    /// Subscribes to the changes of keys starting with `prefix`,
+    /// replaying the logged changes from sequence number `from` first
+    ///
+    /// The connection is dedicated to the subscription from then on.
+    pub async fn watch(mut self, prefix: String, from: Option<u64>) -> Result<AsyncWatchStream> {
+        send_async(&mut self.writer, &Request::Watch { prefix, from }).await?;
+        Ok(AsyncWatchStream {
+            reader: self.reader,
+        })
+    }
+
+    async fn request(&mut self, request: &Request) -> Result<Option<String>> {
+        send_async(&mut self.writer, request).await?;
+        match receive_async(&mut self.reader).await? {
+            Some(Response::Ok(value)) => Ok(value),
+            Some(Response::Err(e)) => Err(e.into()),
+            Some(_) => Err(KvsError::UnexpectedCommandType),
+            None => Err(connection_closed()),
+        }
+    }
+}
+
+/// Changes streamed by the server for a subscription
+pub struct AsyncWatchStream {
+    reader: BufReader<ReadHalf<BoxedConnection>>,
+}
+
+impl AsyncWatchStream {
+    /// Waits for the next change, `None` once the server closed the connection
+    pub async fn next_event(&mut self) -> Option<Result<WatchEvent>> {
+        match receive_async(&mut self.reader).await {
+            Ok(Some(Response::Event(event))) => Some(Ok(event)),
+            Ok(Some(Response::Err(e))) => Some(Err(e.into())),
+            Ok(Some(_)) => Some(Err(KvsError::UnexpectedCommandType)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn connection_closed() -> KvsError {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "server closed the connection",
+    )
+    .into()
+}
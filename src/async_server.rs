@@ -0,0 +1,376 @@
+use crate::common::{
+    MAX_REQUEST_BYTES, RemoteError, Request, Response, receive_async_limited, write_async,
+};
+use crate::replication::Follower;
+use crate::server::{DRAIN_TIMEOUT, authorize, close_subscriptions, execute, lock, subscribe};
+use crate::{
+    AccessControl, Acl, ClientTls, Credentials, KvStore, KvsError, Result, ServerTls,
+    ShutdownHandle,
+};
+
+use std::io::{BufWriter, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::io::{
+    self, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter as AsyncBufWriter,
+};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::{Semaphore, mpsc};
+use tokio::{task, time};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+/// Responses of a connection waiting to be written before reading stalls
+const PIPELINE_DEPTH: usize = 128;
+
+/// A KvsServer serving connections from an event loop
+///
+/// Idle connections cost no thread, requests run on the blocking pool of
+/// the runtime since the store does synchronous file I/O. Watchers and
+/// followers get a thread of their own for the life of their subscription.
+/// Must be run inside a multi-threaded tokio runtime.
+pub struct AsyncKvsServer {
+    store: Arc<Mutex<KvStore>>,
+    primary: Option<SocketAddr>,
+    shutdown: ShutdownHandle,
+    drain_timeout: Duration,
+    tls: Option<ServerTls>,
+    primary_tls: Option<ClientTls>,
+    primary_credentials: Option<Credentials>,
+    access: AccessControl,
+}
+
+impl AsyncKvsServer {
+    /// Creates a primary server for the given store
+    pub fn new(store: KvStore) -> Self {
+        AsyncKvsServer {
+            store: Arc::new(Mutex::new(store)),
+            primary: None,
+            shutdown: ShutdownHandle::new(),
+            drain_timeout: DRAIN_TIMEOUT,
+            tls: None,
+            primary_tls: None,
+            primary_credentials: None,
+            access: AccessControl::default(),
+        }
+    }
+
+    /// Creates a read-only replica applying the log of the server at `primary`
+    pub fn replica(store: KvStore, primary: SocketAddr) -> Self {
+        AsyncKvsServer {
+            store: Arc::new(Mutex::new(store)),
+            primary: Some(primary),
+            shutdown: ShutdownHandle::new(),
+            drain_timeout: DRAIN_TIMEOUT,
+            tls: None,
+            primary_tls: None,
+            primary_credentials: None,
+            access: AccessControl::default(),
+        }
+    }
+
+    /// Encrypts the connections of clients
+    pub fn with_tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Encrypts the connection of a replica to its primary
+    pub fn with_primary_tls(mut self, tls: ClientTls) -> Self {
+        self.primary_tls = Some(tls);
+        self
+    }
+
+    /// Authenticates a replica to its primary
+    pub fn with_primary_credentials(mut self, credentials: Credentials) -> Self {
+        self.primary_credentials = Some(credentials);
+        self
+    }
+
+    /// Requires clients to authenticate and restricts them to what `acl`
+    /// grants them
+    pub fn with_acl(self, acl: Acl) -> Self {
+        self.access.reload(acl);
+        self
+    }
+
+    /// Returns a handle replacing the ACL while the server runs
+    pub fn access_control(&self) -> AccessControl {
+        self.access.clone()
+    }
+
+    /// Sets how long a shutdown waits for in-flight requests
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Returns a handle shutting the server down
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Listens on `addr` and serves every connection on its own task
+    /// until the server is shut down
+    ///
+    /// Once shut down, idle connections are closed at once, busy ones
+    /// after answering the requests they received, and the store is synced
+    /// to disk. Fails with `DrainTimeout` when requests are still running
+    /// after the drain timeout.
+    pub async fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        info!(
+            addr = %listener.local_addr()?,
+            primary = ?self.primary,
+            tls = self.tls.is_some(),
+            "listening"
+        );
+        let follower = self.primary.map(|primary| {
+            Follower::spawn(
+                Arc::clone(&self.store),
+                primary,
+                self.primary_tls.clone(),
+                self.primary_credentials.clone(),
+                self.shutdown.clone(),
+            )
+        });
+        let read_only = self.primary.is_some();
+        // every connection holds a sender, the channel closes once all are gone
+        let (open, mut closed) = mpsc::channel::<()>(1);
+        // and so does every subscription thread
+        let (subscribed, mut unsubscribed) = mpsc::channel::<()>(1);
+        let connections = open.downgrade();
+        let metrics = lock(&self.store).metrics();
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = self.shutdown.requested() => break,
+            };
+            info!(%peer, "connection opened");
+            let store = Arc::clone(&self.store);
+            let shutdown = self.shutdown.clone();
+            let access = self.access.clone();
+            let open = open.clone();
+            let subscribed = subscribed.clone();
+            let acceptor = self.tls.as_ref().map(|tls| TlsAcceptor::from(tls.config()));
+            let counted = metrics.open_connection();
+            tokio::spawn(async move {
+                let _counted = counted;
+                let served = match acceptor {
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            serve(store, stream, read_only, shutdown, access, open, subscribed)
+                                .await
+                        }
+                        Err(e) => Err(e.into()),
+                    },
+                    None => {
+                        serve(store, stream, read_only, shutdown, access, open, subscribed).await
+                    }
+                };
+                if let Err(e) = served {
+                    warn!(%peer, error = %e, "error serving client");
+                }
+                info!(%peer, "connection closed");
+            });
+        }
+        drop((listener, open));
+        info!(timeout = ?self.drain_timeout, "shutting down, draining connections");
+
+        let drained = time::timeout(self.drain_timeout, closed.recv())
+            .await
+            .is_ok();
+        let busy = if drained {
+            0
+        } else {
+            connections.strong_count()
+        };
+        let store = Arc::clone(&self.store);
+        task::spawn_blocking(move || {
+            if let Some(follower) = follower {
+                follower.stop();
+            }
+            close_subscriptions(&store);
+        })
+        .await
+        .map_err(std::io::Error::from)?;
+        drop(subscribed);
+        let _ = time::timeout(self.drain_timeout, unsubscribed.recv()).await;
+        let store = Arc::clone(&self.store);
+        task::spawn_blocking(move || lock(&store).sync())
+            .await
+            .map_err(std::io::Error::from)??;
+        // the directory can be opened again once run returns, unless
+        // requests are still running
+        drop(self.store);
+        if busy > 0 {
+            warn!(busy, "drain timed out");
+        }
+        match busy {
+            0 => Ok(()),
+            busy => Err(KvsError::DrainTimeout(busy)),
+        }
+    }
+}
+
+/// Answers the requests of a single connection until it is closed
+///
+/// Tagged requests run concurrently, up to `PIPELINE_DEPTH` at a time,
+/// and are answered as they complete, untagged ones are answered in
+/// order. Stops reading requests once the server is shut down. The
+/// connection counts as open for the shutdown until `open` is dropped,
+/// and its subscription, if any, until `subscribed` is.
+async fn serve<S>(
+    store: Arc<Mutex<KvStore>>,
+    stream: S,
+    read_only: bool,
+    shutdown: ShutdownHandle,
+    access: AccessControl,
+    open: mpsc::Sender<()>,
+    subscribed: mpsc::Sender<()>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let (responses, pending) = mpsc::channel(PIPELINE_DEPTH);
+    let writer = tokio::spawn(write_responses(AsyncBufWriter::new(writer), pending));
+    let running = Arc::new(Semaphore::new(PIPELINE_DEPTH));
+    let mut user = None;
+
+    loop {
+        let request = tokio::select! {
+            request = receive_async_limited::<_, Request>(&mut reader, MAX_REQUEST_BYTES) => request,
+            _ = shutdown.requested() => Ok(None),
+        };
+        let request = match request {
+            Err(KvsError::LimitExceeded(limit)) => {
+                // the rest of the line cannot be skipped, the connection is closed
+                warn!(%limit, "closing a connection sending an oversized request");
+                let error = Response::Err(RemoteError::LimitExceeded(limit));
+                let _ = responses.send(error).await;
+                break;
+            }
+            request => request?,
+        };
+        let Some(request) = request else {
+            break;
+        };
+        let request = match authorize(&access, &mut user, request) {
+            Ok(request) => request,
+            Err(response) => {
+                let _ = responses.send(response).await;
+                continue;
+            }
+        };
+        if request.is_subscription() {
+            drop((responses, open));
+            let writer = writer.await.map_err(std::io::Error::from)??;
+            return forward_subscription(store, request, writer, shutdown, subscribed).await;
+        }
+        if let Request::Shutdown = request {
+            let _ = responses.send(Response::Ok(None)).await;
+            shutdown.shutdown();
+            continue;
+        }
+        let tagged = matches!(request, Request::Tagged { .. });
+        // reading stops until one of the running requests is answered
+        let permit = Arc::clone(&running)
+            .acquire_owned()
+            .await
+            .expect("semaphore never closed");
+        let store = Arc::clone(&store);
+        let task = task::spawn_blocking(move || execute(&store, request, read_only));
+        let responses = responses.clone();
+        let answer = async move {
+            let response = task.await.map_err(std::io::Error::from)?;
+            drop(permit);
+            // the writer only goes away after failing, it reports the error
+            let _ = responses.send(response).await;
+            Ok::<_, KvsError>(())
+        };
+        if tagged {
+            tokio::spawn(answer);
+        } else {
+            answer.await?;
+        }
+    }
+    drop(responses);
+    let mut writer = writer.await.map_err(std::io::Error::from)??;
+    // sends a TLS client its close_notify, the client may be gone already
+    let _ = writer.shutdown().await;
+    Ok(())
+}
+
+/// Writes responses as they come, flushing whenever none is waiting,
+/// and hands the writer back once every sender is gone
+async fn write_responses<W>(mut writer: W, mut pending: mpsc::Receiver<Response>) -> Result<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(response) = pending.recv().await {
+        write_async(&mut writer, &response).await?;
+        if pending.is_empty() {
+            writer.flush().await?;
+        }
+    }
+    writer.flush().await?;
+    Ok(writer)
+}
+
+/// Runs a subscription on a thread of its own, since it blocks on the
+/// store, and forwards what it writes to the client until either side
+/// goes away or the server shuts down
+///
+/// The thread holds `subscribed` until the subscription ends.
+async fn forward_subscription<W>(
+    store: Arc<Mutex<KvStore>>,
+    request: Request,
+    mut writer: W,
+    shutdown: ShutdownHandle,
+    subscribed: mpsc::Sender<()>,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let (sink, mut written) = mpsc::channel(PIPELINE_DEPTH);
+    thread::spawn(move || {
+        let _subscribed = subscribed;
+        if let Err(e) = subscribe(&store, request, &mut BufWriter::new(ChannelWriter(sink))) {
+            warn!(error = %e, "error serving subscription");
+        }
+    });
+    loop {
+        let bytes = tokio::select! {
+            bytes = written.recv() => bytes,
+            _ = shutdown.requested() => None,
+        };
+        let Some(bytes) = bytes else {
+            let _ = writer.shutdown().await;
+            return Ok(());
+        };
+        writer.write_all(&bytes).await?;
+        if written.is_empty() {
+            writer.flush().await?;
+        }
+    }
+}
+
+/// Hands the bytes written by a blocking thread over to an async task
+struct ChannelWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| std::io::Error::from(ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
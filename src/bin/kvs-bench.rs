@@ -0,0 +1,406 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, ValueEnum};
+use networked_kv_store::{KvStore, KvsClient, KvsEngine, KvsError, MemoryKvsEngine, Result};
+use serde::Serialize;
+
+#[cfg(feature = "sled")]
+use networked_kv_store::SledKvsEngine;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Engine {
+    /// the log-structured KvStore
+    Kvs,
+    /// the in-memory engine, without snapshots
+    Memory,
+    /// the sled engine, when built with the `sled` feature
+    #[cfg(feature = "sled")]
+    Sled,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Distribution {
+    /// every key equally likely
+    Uniform,
+    /// a few hot keys, the popularity of the key of rank k being proportional to 1/k^s
+    Zipfian,
+}
+
+#[derive(Parser)]
+#[command(name = "kvs-bench", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "A load generator for the key-value store")]
+struct Cli {
+    /// drive the server at this address instead of a local store
+    #[arg(long, conflicts_with_all = ["engine", "dir"])]
+    addr: Option<SocketAddr>,
+    /// engine of the local store
+    #[arg(long, value_enum, default_value = "kvs")]
+    engine: Engine,
+    /// directory of the local store, a temporary directory removed afterwards by default
+    #[arg(long)]
+    dir: Option<PathBuf>,
+    /// share of operations that are reads, the rest are writes
+    #[arg(long, default_value_t = 0.9)]
+    read_ratio: f64,
+    /// number of distinct keys, all written before the run
+    #[arg(long, default_value_t = 10_000)]
+    keys: u64,
+    /// how keys are picked
+    #[arg(long, value_enum, default_value = "uniform")]
+    distribution: Distribution,
+    /// skew of the zipfian distribution
+    #[arg(long, default_value_t = 0.99)]
+    zipf_exponent: f64,
+    /// smallest value written, in bytes
+    #[arg(long, default_value_t = 100)]
+    value_size: usize,
+    /// largest value written, in bytes, values are as large as --value-size by default
+    #[arg(long)]
+    max_value_size: Option<usize>,
+    /// number of concurrent workers, each with its own connection to a server
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+    /// seconds the workload runs for, after loading the keys
+    #[arg(long, default_value_t = 10)]
+    duration: u64,
+    /// seed of the workload, the same seed runs the same operations
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+    /// print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// What a worker sends its operations to
+trait Target: Send {
+    fn get(&mut self, key: String) -> Result<Option<String>>;
+    fn set(&mut self, key: String, value: String) -> Result<()>;
+}
+
+/// A local store shared by the workers, as a server shares it between connections
+struct Local<E>(Arc<Mutex<E>>);
+
+impl<E: KvsEngine> Target for Local<E> {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.0.lock().expect("store lock poisoned").get(key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.0.lock().expect("store lock poisoned").set(key, value)
+    }
+}
+
+impl Target for KvsClient {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        KvsClient::get(self, key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KvsClient::set(self, key, value)
+    }
+}
+
+/// SplitMix64, so runs are reproducible from their seed without a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// uniform in [low, high]
+    fn between(&mut self, low: usize, high: usize) -> usize {
+        low + (self.next_u64() % (high - low + 1) as u64) as usize
+    }
+}
+
+/// Picks key indices from a distribution
+enum Keys {
+    Uniform(u64),
+    /// cumulative probabilities of the keys by rank
+    Zipfian(Arc<Vec<f64>>),
+}
+
+impl Keys {
+    fn new(cli: &Cli) -> Keys {
+        match cli.distribution {
+            Distribution::Uniform => Keys::Uniform(cli.keys),
+            Distribution::Zipfian => {
+                let mut cdf: Vec<f64> = (1..=cli.keys)
+                    .scan(0.0, |sum, rank| {
+                        *sum += 1.0 / (rank as f64).powf(cli.zipf_exponent);
+                        Some(*sum)
+                    })
+                    .collect();
+                let total = cdf.last().copied().unwrap_or(1.0);
+                cdf.iter_mut().for_each(|p| *p /= total);
+                Keys::Zipfian(Arc::new(cdf))
+            }
+        }
+    }
+
+    fn pick(&self, rng: &mut Rng) -> u64 {
+        match self {
+            Keys::Uniform(keys) => rng.next_u64() % keys,
+            Keys::Zipfian(cdf) => {
+                let p = rng.next_f64();
+                cdf.partition_point(|&c| c < p).min(cdf.len() - 1) as u64
+            }
+        }
+    }
+
+    fn share(&self) -> Keys {
+        match self {
+            Keys::Uniform(keys) => Keys::Uniform(*keys),
+            Keys::Zipfian(cdf) => Keys::Zipfian(Arc::clone(cdf)),
+        }
+    }
+}
+
+fn key(i: u64) -> String {
+    format!("key{i:010}")
+}
+
+/// Latencies of one kind of operation, in nanoseconds
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<u64>,
+    errors: u64,
+}
+
+impl Samples {
+    fn record(&mut self, start: Instant, result: Result<()>) {
+        match result {
+            Ok(()) => self.latencies.push(start.elapsed().as_nanos() as u64),
+            Err(_) => self.errors += 1,
+        }
+    }
+
+    fn merge(&mut self, other: Samples) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    target: String,
+    distribution: &'static str,
+    read_ratio: f64,
+    keys: u64,
+    concurrency: usize,
+    seed: u64,
+    elapsed_secs: f64,
+    throughput: f64,
+    reads: Summary,
+    writes: Summary,
+}
+
+/// Operation count, errors and latency percentiles, in microseconds
+#[derive(Serialize)]
+struct Summary {
+    count: usize,
+    errors: u64,
+    p50_us: f64,
+    p90_us: f64,
+    p99_us: f64,
+    p999_us: f64,
+    max_us: f64,
+}
+
+impl Summary {
+    fn of(mut samples: Samples) -> Summary {
+        samples.latencies.sort_unstable();
+        let latencies = &samples.latencies;
+        let percentile = |p: f64| match latencies.len() {
+            0 => 0.0,
+            len => latencies[((len as f64 * p) as usize).min(len - 1)] as f64 / 1000.0,
+        };
+        Summary {
+            count: latencies.len(),
+            errors: samples.errors,
+            p50_us: percentile(0.5),
+            p90_us: percentile(0.9),
+            p99_us: percentile(0.99),
+            p999_us: percentile(0.999),
+            max_us: latencies.last().map_or(0.0, |&max| max as f64 / 1000.0),
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if !(0.0..=1.0).contains(&cli.read_ratio) {
+        return Err(invalid("--read-ratio must be between 0 and 1"));
+    }
+    if cli.keys == 0 || cli.concurrency == 0 {
+        return Err(invalid("--keys and --concurrency must be at least 1"));
+    }
+    if cli.max_value_size.is_some_and(|max| max < cli.value_size) {
+        return Err(invalid("--max-value-size must be at least --value-size"));
+    }
+
+    let report = match cli.addr {
+        Some(addr) => bench(&cli, addr.to_string(), || KvsClient::connect(addr))?,
+        None => bench_local(&cli)?,
+    };
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> KvsError {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into()
+}
+
+/// Runs the workload against a local store of the chosen engine
+fn bench_local(cli: &Cli) -> Result<Report> {
+    let (dir, temporary) = match &cli.dir {
+        Some(dir) => (dir.clone(), false),
+        None => {
+            let dir = std::env::temp_dir().join(format!("kvs-bench-{}", std::process::id()));
+            (dir, true)
+        }
+    };
+    let report = match cli.engine {
+        Engine::Kvs => local(cli, "kvs", KvStore::open(&dir)?),
+        Engine::Memory => local(cli, "memory", MemoryKvsEngine::new()),
+        #[cfg(feature = "sled")]
+        Engine::Sled => local(cli, "sled", SledKvsEngine::open(&dir)?),
+    };
+    if temporary {
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    report
+}
+
+fn local<E: KvsEngine>(cli: &Cli, name: &str, store: E) -> Result<Report> {
+    let store = Arc::new(Mutex::new(store));
+    let report = bench(cli, name.to_owned(), || Ok(Local(Arc::clone(&store))))?;
+    store.lock().expect("store lock poisoned").sync()?;
+    Ok(report)
+}
+
+/// Loads the keys, then runs the workload on `concurrency` workers,
+/// each with the target returned by `connect`
+fn bench<T: Target + 'static>(
+    cli: &Cli,
+    target: String,
+    connect: impl Fn() -> Result<T>,
+) -> Result<Report> {
+    let mut rng = Rng(cli.seed);
+    let value_size = |rng: &mut Rng| match cli.max_value_size {
+        Some(max) => rng.between(cli.value_size, max),
+        None => cli.value_size,
+    };
+    let mut loader = connect()?;
+    for i in 0..cli.keys {
+        let size = value_size(&mut rng);
+        loader.set(key(i), "v".repeat(size))?;
+    }
+    drop(loader);
+
+    let keys = Keys::new(cli);
+    let duration = Duration::from_secs(cli.duration);
+    let start = Arc::new(Barrier::new(cli.concurrency + 1));
+    let mut workers = Vec::new();
+    for worker in 0..cli.concurrency {
+        let mut target = connect()?;
+        let keys = keys.share();
+        let start = Arc::clone(&start);
+        let read_ratio = cli.read_ratio;
+        let (low, high) = (cli.value_size, cli.max_value_size.unwrap_or(cli.value_size));
+        // every worker gets its own stream of operations from the seed
+        let mut rng = Rng(cli.seed ^ (worker as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        workers.push(thread::spawn(move || {
+            let (mut reads, mut writes) = (Samples::default(), Samples::default());
+            start.wait();
+            let began = Instant::now();
+            while began.elapsed() < duration {
+                let key = key(keys.pick(&mut rng));
+                let op = Instant::now();
+                if rng.next_f64() < read_ratio {
+                    reads.record(op, target.get(key).map(|_| ()));
+                } else {
+                    let value = "v".repeat(rng.between(low, high));
+                    writes.record(op, target.set(key, value));
+                }
+            }
+            (reads, writes)
+        }));
+    }
+    start.wait();
+    let began = Instant::now();
+    let (mut reads, mut writes) = (Samples::default(), Samples::default());
+    for worker in workers {
+        let (worker_reads, worker_writes) = worker.join().expect("worker panicked");
+        reads.merge(worker_reads);
+        writes.merge(worker_writes);
+    }
+    let elapsed = began.elapsed().as_secs_f64();
+    let total = reads.latencies.len() + writes.latencies.len();
+
+    Ok(Report {
+        target,
+        distribution: match cli.distribution {
+            Distribution::Uniform => "uniform",
+            Distribution::Zipfian => "zipfian",
+        },
+        read_ratio: cli.read_ratio,
+        keys: cli.keys,
+        concurrency: cli.concurrency,
+        seed: cli.seed,
+        elapsed_secs: elapsed,
+        throughput: total as f64 / elapsed,
+        reads: Summary::of(reads),
+        writes: Summary::of(writes),
+    })
+}
+
+fn print_report(report: &Report) {
+    println!(
+        "{} {} keys {}, read ratio {}, {} workers, seed {}",
+        report.target,
+        report.distribution,
+        report.keys,
+        report.read_ratio,
+        report.concurrency,
+        report.seed
+    );
+    println!(
+        "{:.0} ops/s over {:.1}s",
+        report.throughput, report.elapsed_secs
+    );
+    println!(
+        "{:<7}{:>10}{:>8}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        "", "count", "errors", "p50 us", "p90 us", "p99 us", "p99.9 us", "max us"
+    );
+    for (name, summary) in [("reads", &report.reads), ("writes", &report.writes)] {
+        println!(
+            "{:<7}{:>10}{:>8}{:>10.1}{:>10.1}{:>10.1}{:>10.1}{:>10.1}",
+            name,
+            summary.count,
+            summary.errors,
+            summary.p50_us,
+            summary.p90_us,
+            summary.p99_us,
+            summary.p999_us,
+            summary.max_us
+        );
+    }
+}
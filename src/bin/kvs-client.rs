@@ -0,0 +1,271 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use networked_kv_store::{
+    ClientOptions, ClientTls, Credentials, KvsClient, KvsError, Result, ShardedKvsClient, Shell,
+    Topology, WatchEvent, rebalance_with,
+};
+
+#[derive(Subcommand)]
+enum Command {
+    /// get key
+    Get { key: String },
+    /// set key value
+    Set { key: String, value: String },
+    /// remove key
+    Rm { key: String },
+    /// print changes of keys starting with prefix
+    Watch {
+        prefix: String,
+        /// replay logged changes from this sequence number first
+        #[arg(long)]
+        from: Option<u64>,
+    },
+    /// stop the server once the requests it received are answered
+    Shutdown,
+    /// inspect the operations the server found slow
+    Slowlog {
+        #[command(subcommand)]
+        command: SlowlogCommand,
+    },
+    /// run commands interactively over a single connection
+    Shell,
+    /// write a consistent copy of the store into a directory on the server's machine
+    Backup { dest: PathBuf },
+    /// move keys to their owner after shards were added or removed
+    Rebalance {
+        /// topology the keys are currently placed with
+        #[arg(long)]
+        from: PathBuf,
+        /// topology to place the keys with
+        #[arg(long)]
+        to: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum SlowlogCommand {
+    /// print the slowest recent operations, newest first
+    Get {
+        /// number of entries to print
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+    },
+    /// empty the slow log
+    Reset,
+}
+
+#[derive(Parser)]
+#[command(name = "kvs-client", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "A key-value store client")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// address of the server
+    #[arg(long, global = true, default_value = "127.0.0.1:4000")]
+    addr: SocketAddr,
+    /// seconds to wait for the server before giving up, 0 waits forever
+    #[arg(long, global = true, default_value_t = 5)]
+    timeout: u64,
+    /// PEM authority the server certificate must be signed by, enables TLS
+    #[arg(long, global = true)]
+    tls_ca: Option<PathBuf>,
+    /// name the server certificate must be issued for, defaults to the IP of --addr
+    #[arg(long, global = true, requires = "tls_ca")]
+    tls_server_name: Option<String>,
+    /// PEM certificate chain presented to servers verifying clients
+    #[arg(long, global = true, requires_all = ["tls_ca", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the client certificate
+    #[arg(long, global = true, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// user to authenticate as, with --password
+    #[arg(long, global = true, requires = "password", conflicts_with = "token")]
+    user: Option<String>,
+    /// password of --user
+    #[arg(long, global = true, requires = "user")]
+    password: Option<String>,
+    /// token to authenticate with
+    #[arg(long, global = true)]
+    token: Option<String>,
+    /// route requests to the shards described in this topology file instead of --addr
+    #[arg(long, global = true)]
+    topology: Option<PathBuf>,
+}
+
+// Either a single server or a sharded cluster
+enum Client {
+    Single(Box<KvsClient>),
+    Sharded(Box<ShardedKvsClient>),
+}
+
+impl Client {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        match self {
+            Client::Single(client) => client.get(key),
+            Client::Sharded(client) => client.get(key),
+        }
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        match self {
+            Client::Single(client) => client.set(key, value),
+            Client::Sharded(client) => client.set(key, value),
+        }
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        match self {
+            Client::Single(client) => client.remove(key),
+            Client::Sharded(client) => client.remove(key),
+        }
+    }
+}
+
+fn load_tls(cli: &Cli) -> Result<Option<ClientTls>> {
+    let Some(ca) = &cli.tls_ca else {
+        return Ok(None);
+    };
+    let server_name = match &cli.tls_server_name {
+        Some(name) => name.clone(),
+        None => cli.addr.ip().to_string(),
+    };
+    let tls = ClientTls::load(ca, &server_name)?;
+    match (&cli.tls_cert, &cli.tls_key) {
+        (Some(cert), Some(key)) => Ok(Some(tls.with_identity(cert, key)?)),
+        _ => Ok(Some(tls)),
+    }
+}
+
+fn credentials(cli: &Cli) -> Option<Credentials> {
+    match (&cli.user, &cli.password, &cli.token) {
+        (Some(user), Some(password), _) => Some(Credentials::Password {
+            user: user.clone(),
+            password: password.clone(),
+        }),
+        (_, _, Some(token)) => Some(Credentials::Token(token.clone())),
+        _ => None,
+    }
+}
+
+// Connects to the shards of `topology` when given, to `addr` otherwise
+fn connect(addr: SocketAddr, topology: Option<&Path>, options: &ClientOptions) -> Result<Client> {
+    match topology {
+        Some(path) => Ok(Client::Sharded(Box::new(ShardedKvsClient::with_options(
+            &Topology::load(path)?,
+            options.clone(),
+        )?))),
+        None => Ok(Client::Single(Box::new(KvsClient::connect_with(
+            addr, options,
+        )?))),
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let timeout = (cli.timeout > 0).then(|| Duration::from_secs(cli.timeout));
+    let options = ClientOptions {
+        connect_timeout: timeout,
+        read_timeout: timeout,
+        write_timeout: timeout,
+        tls: load_tls(&cli)?,
+        credentials: credentials(&cli),
+        ..ClientOptions::default()
+    };
+    let client = || connect(cli.addr, cli.topology.as_deref(), &options);
+
+    match cli.command {
+        Command::Get { key } => {
+            if let Some(value) = client()?.get(key)? {
+                println!("{value}");
+            } else {
+                println!("Key not found");
+            }
+        }
+        Command::Set { key, value } => {
+            client()?.set(key, value)?;
+        }
+        Command::Rm { key } => match client()?.remove(key) {
+            Ok(_) => {}
+            Err(KvsError::KeyNotFound) => {
+                eprintln!("Key not found");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        },
+        Command::Shutdown => {
+            let Client::Single(mut client) = client()? else {
+                eprintln!("shutdown needs a single server, use --addr");
+                std::process::exit(1);
+            };
+            client.shutdown()?;
+        }
+        Command::Slowlog { command } => {
+            let Client::Single(mut client) = client()? else {
+                eprintln!("slowlog needs a single server, use --addr");
+                std::process::exit(1);
+            };
+            match command {
+                SlowlogCommand::Get { count } => {
+                    for entry in client.slow_log(count)? {
+                        let mut flags = String::new();
+                        if entry.compacted {
+                            flags.push_str(" compacted");
+                        }
+                        if entry.synced {
+                            flags.push_str(" synced");
+                        }
+                        println!(
+                            "{} {} {} {} {}us{flags}",
+                            entry.id,
+                            entry.timestamp,
+                            entry.op.name(),
+                            entry.key.as_deref().unwrap_or("-"),
+                            entry.duration.as_micros()
+                        );
+                    }
+                }
+                SlowlogCommand::Reset => client.reset_slow_log()?,
+            }
+        }
+        Command::Backup { dest } => {
+            let Client::Single(mut client) = client()? else {
+                eprintln!("backup needs a single server, use --addr");
+                std::process::exit(1);
+            };
+            client.checkpoint(dest)?;
+        }
+        Command::Watch { prefix, from } => {
+            let Client::Single(client) = client()? else {
+                eprintln!("watch needs a single server, use --addr");
+                std::process::exit(1);
+            };
+            for event in client.watch(prefix, from)? {
+                match event? {
+                    WatchEvent::Set { seq, key, value } => println!("{seq} set {key} {value}"),
+                    WatchEvent::Remove { seq, key } => println!("{seq} rm {key}"),
+                }
+            }
+        }
+        Command::Shell => {
+            let Client::Single(client) = client()? else {
+                eprintln!("shell needs a single server, use --addr");
+                std::process::exit(1);
+            };
+            let mut shell = Shell::new(*client);
+            if let Some(home) = std::env::var_os("HOME") {
+                shell = shell.with_history(PathBuf::from(home).join(".kvs_history"));
+            }
+            shell.run(&format!("{}> ", cli.addr))?;
+        }
+        Command::Rebalance { from, to } => {
+            let moved = rebalance_with(&Topology::load(from)?, &Topology::load(to)?, &options)?;
+            eprintln!("moved {moved} keys");
+        }
+    }
+    Ok(())
+}
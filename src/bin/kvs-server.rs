@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+use std::env::current_dir;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use networked_kv_store::raft::RaftNode;
+use networked_kv_store::{
+    AccessControl, Acl, AsyncKvsServer, ClientTls, HttpServer, KvStore, KvsEngine, KvsServer,
+    LogFormat, MemoryKvsEngine, Metrics, MetricsServer, NaiveThreadPool, PeerSection,
+    RayonThreadPool, Result, ServerConfig, ServerMode, ServerTls, SharedQueueThreadPool,
+    ShutdownHandle, StoreSettings, ThreadPool,
+};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use tracing::{error, info, warn};
+
+#[cfg(feature = "sled")]
+use networked_kv_store::SledKvsEngine;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Pool {
+    /// a thread per connection
+    Naive,
+    /// fixed workers sharing a queue
+    SharedQueue,
+    /// fixed work-stealing workers
+    Rayon,
+}
+
+impl From<Pool> for ServerMode {
+    fn from(pool: Pool) -> Self {
+        match pool {
+            Pool::Naive => ServerMode::Naive,
+            Pool::SharedQueue => ServerMode::SharedQueue,
+            Pool::Rayon => ServerMode::Rayon,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogOutput {
+    /// human readable lines
+    Text,
+    /// one JSON object per line
+    Json,
+}
+
+impl From<LogOutput> for LogFormat {
+    fn from(output: LogOutput) -> Self {
+        match output {
+            LogOutput::Text => LogFormat::Text,
+            LogOutput::Json => LogFormat::Json,
+        }
+    }
+}
+
+/// Flags override the settings of the configuration file
+#[derive(Clone, Parser)]
+#[command(name = "kvs-server", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "A key-value store server")]
+struct Cli {
+    /// TOML configuration file, reloaded on SIGHUP
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// address to listen on, 127.0.0.1:4000 by default
+    #[arg(long)]
+    addr: Option<SocketAddr>,
+    /// storage engine, must match the one that created the data directory, kvs by default
+    #[arg(long)]
+    engine: Option<String>,
+    /// follow the primary at this address and serve read-only traffic
+    #[arg(long)]
+    replica_of: Option<SocketAddr>,
+    /// serve connections from an event loop instead of a thread pool
+    #[arg(long = "async", conflicts_with = "pool")]
+    run_async: bool,
+    /// serve a JSON REST API over HTTP instead of the native protocol
+    #[arg(long, conflicts_with_all = ["run_async", "pool", "tls_cert"])]
+    http: bool,
+    /// thread pool serving the connections, naive by default
+    #[arg(long, value_enum)]
+    pool: Option<Pool>,
+    /// number of threads of fixed size pools, defaults to the number of CPUs
+    #[arg(long)]
+    threads: Option<u32>,
+    /// seconds to wait for in-flight requests when shutting down, 10 by default
+    #[arg(long)]
+    drain_timeout: Option<u64>,
+    /// PEM certificate chain presented to clients, enables TLS
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the certificate
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+    /// only accept clients presenting a certificate signed by this PEM authority
+    #[arg(long)]
+    tls_client_ca: Option<PathBuf>,
+    /// PEM authority the certificate of the primary must be signed by,
+    /// replicas present their own certificate to it when they have one
+    #[arg(long)]
+    tls_primary_ca: Option<PathBuf>,
+    /// record operations taking at least this many milliseconds in the slow log
+    #[arg(long)]
+    slowlog_threshold_ms: Option<u64>,
+    /// admin address serving Prometheus metrics on /metrics
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// lowest level logged, or filter directives such as `info,networked_kv_store::kv=debug`,
+    /// info by default
+    #[arg(long)]
+    log_level: Option<String>,
+    /// how log events are written to stderr, text by default
+    #[arg(long, value_enum)]
+    log_format: Option<LogOutput>,
+    /// JSON file of users and the key prefixes they may access, reloaded on SIGHUP
+    #[arg(long)]
+    acl: Option<PathBuf>,
+    /// user to authenticate as to the primary or to the peers of the cluster
+    #[arg(long, requires = "primary_password")]
+    primary_user: Option<String>,
+    /// password of the primary user
+    #[arg(long, requires = "primary_user")]
+    primary_password: Option<String>,
+    /// token to authenticate with to the primary or to the peers of the cluster
+    #[arg(long, conflicts_with = "primary_user")]
+    primary_token: Option<String>,
+    /// identifier of this node, joins the raft cluster of the peers
+    #[arg(long, conflicts_with = "replica_of")]
+    node_id: Option<u64>,
+    /// another founding member of the cluster, as `ID=ADDR`, repeated for each one
+    #[arg(long = "peer", value_parser = parse_peer, requires = "node_id")]
+    peers: Vec<PeerSection>,
+}
+
+/// Parses a `--peer` flag
+fn parse_peer(value: &str) -> std::result::Result<PeerSection, String> {
+    let (id, addr) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected ID=ADDR, got `{value}`"))?;
+    Ok(PeerSection {
+        id: id
+            .parse()
+            .map_err(|e| format!("invalid node id `{id}`: {e}"))?,
+        addr: addr
+            .parse()
+            .map_err(|e| format!("invalid address `{addr}`: {e}"))?,
+    })
+}
+
+/// Settings of the configuration file, if any, overridden by the flags given
+fn resolve(cli: &Cli) -> Result<ServerConfig> {
+    let mut config = match &cli.config {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::default(),
+    };
+    if let Some(addr) = cli.addr {
+        config.addr = addr;
+    }
+    if let Some(engine) = &cli.engine {
+        config.engine.clone_from(engine);
+    }
+    if cli.replica_of.is_some() {
+        config.replica_of = cli.replica_of;
+    }
+    if cli.http {
+        config.server.mode = ServerMode::Http;
+    } else if cli.run_async {
+        config.server.mode = ServerMode::Async;
+    } else if let Some(pool) = cli.pool {
+        config.server.mode = pool.into();
+    }
+    if cli.threads.is_some() {
+        config.server.threads = cli.threads;
+    }
+    if let Some(drain_timeout) = cli.drain_timeout {
+        config.server.drain_timeout_secs = drain_timeout;
+    }
+    if cli.metrics_addr.is_some() {
+        config.server.metrics_addr = cli.metrics_addr;
+    }
+    if cli.slowlog_threshold_ms.is_some() {
+        config.storage.slowlog_threshold_ms = cli.slowlog_threshold_ms;
+    }
+    for (flag, setting) in [
+        (&cli.tls_cert, &mut config.tls.cert),
+        (&cli.tls_key, &mut config.tls.key),
+        (&cli.tls_client_ca, &mut config.tls.client_ca),
+        (&cli.tls_primary_ca, &mut config.tls.primary_ca),
+        (&cli.acl, &mut config.auth.acl),
+    ] {
+        if flag.is_some() {
+            setting.clone_from(flag);
+        }
+    }
+    for (flag, setting) in [
+        (&cli.primary_user, &mut config.auth.primary_user),
+        (&cli.primary_password, &mut config.auth.primary_password),
+        (&cli.primary_token, &mut config.auth.primary_token),
+    ] {
+        if flag.is_some() {
+            setting.clone_from(flag);
+        }
+    }
+    if let Some(level) = &cli.log_level {
+        config.log.level.clone_from(level);
+    }
+    if let Some(format) = cli.log_format {
+        config.log.format = format.into();
+    }
+    if cli.node_id.is_some() {
+        config.cluster.id = cli.node_id;
+    }
+    if !cli.peers.is_empty() {
+        config.cluster.peers.clone_from(&cli.peers);
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+/// TLS settings for clients and for the connection to the primary
+fn load_tls(config: &ServerConfig) -> Result<(Option<ServerTls>, Option<ClientTls>)> {
+    let tls = &config.tls;
+    let server = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => Some(ServerTls::load(cert, key, tls.client_ca.as_deref())?),
+        _ => None,
+    };
+    let primary = match (&tls.primary_ca, config.replica_of) {
+        (Some(ca), Some(primary)) => {
+            let client = ClientTls::load(ca, &primary.ip().to_string())?;
+            match (&tls.cert, &tls.key) {
+                (Some(cert), Some(key)) => Some(client.with_identity(cert, key)?),
+                _ => Some(client),
+            }
+        }
+        _ => None,
+    };
+    Ok((server, primary))
+}
+
+/// Opens the store in the data directory with `open`, applies the configured
+/// settings and serves its metrics if asked to
+fn open_store<E: KvsEngine>(
+    config: &ServerConfig,
+    open: impl FnOnce(PathBuf) -> Result<E>,
+) -> Result<E> {
+    let store = match &config.data_dir {
+        Some(dir) => open(dir.clone())?,
+        None => open(current_dir()?)?,
+    };
+    config.apply(&store.settings());
+    serve_metrics(config, store.metrics());
+    Ok(store)
+}
+
+/// Serves `metrics` on the admin address, if one is configured
+fn serve_metrics(config: &ServerConfig, metrics: Arc<Metrics>) {
+    if let Some(addr) = config.server.metrics_addr {
+        let metrics = MetricsServer::new(metrics);
+        thread::spawn(move || {
+            if let Err(e) = metrics.run(addr) {
+                error!(error = %e, "error serving metrics");
+            }
+        });
+        info!(%addr, "serving metrics");
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let config = resolve(&cli)?;
+    networked_kv_store::init_logging(&config.log.level, config.log.format)?;
+
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        config = ?cli.config,
+        engine = config.engine,
+        addr = %config.addr,
+        mode = config.server.mode.name(),
+        threads = ?config.server.threads,
+        replica_of = ?config.replica_of,
+        tls = config.tls.cert.is_some(),
+        acl = ?config.auth.acl,
+        "starting kvs-server"
+    );
+    let result = match config.server.mode {
+        ServerMode::Naive => run::<NaiveThreadPool>(cli, config),
+        ServerMode::SharedQueue => run::<SharedQueueThreadPool>(cli, config),
+        ServerMode::Rayon => run::<RayonThreadPool>(cli, config),
+        ServerMode::Async => run_async(cli, config),
+        ServerMode::Http => run_http(cli, config),
+    };
+    match &result {
+        Ok(()) => info!("Shut down cleanly"),
+        Err(e) => error!(error = %e, "server failed"),
+    }
+    result
+}
+
+/// Shuts the server down on the first SIGINT or SIGTERM, exits at once on the next one,
+/// and reloads the settings that can change while running on SIGHUP
+fn handle_signals(
+    shutdown: ShutdownHandle,
+    access: AccessControl,
+    settings: StoreSettings,
+    cli: Cli,
+    config: ServerConfig,
+) -> Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                reload(&access, &settings, &cli, &config);
+            } else if shutdown.is_shutdown() {
+                warn!(signal, "received another signal, exiting without draining");
+                std::process::exit(1);
+            } else {
+                info!(signal, "received signal, shutting down");
+                shutdown.shutdown();
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Applies the storage settings, limits and ACL of the configuration as it is now,
+/// warning about changes to `running` that need a restart
+fn reload(access: &AccessControl, settings: &StoreSettings, cli: &Cli, running: &ServerConfig) {
+    let config = match resolve(cli) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!(error = %e, "keeping the current settings");
+            return;
+        }
+    };
+    for key in running.restart_required(&config) {
+        warn!(key, "setting changed, restart to apply it");
+    }
+    config.apply(settings);
+    if cli.config.is_some() {
+        info!("reloaded configuration");
+    }
+    if let Some(path) = &config.auth.acl {
+        match Acl::load(path) {
+            Ok(acl) => {
+                access.reload(acl);
+                info!(path = %path.display(), "reloaded ACL");
+            }
+            Err(e) => warn!(error = %e, "keeping the current ACL"),
+        }
+    }
+}
+
+/// Serves the configured engine from a pool of type `P`
+fn run<P: ThreadPool>(cli: Cli, config: ServerConfig) -> Result<()> {
+    if let Some(id) = config.cluster.id {
+        return run_cluster::<P>(cli, config, id);
+    }
+    #[cfg(feature = "sled")]
+    if config.engine == SledKvsEngine::NAME {
+        let store = open_store(&config, SledKvsEngine::open)?;
+        return serve::<P, _>(cli, config, store);
+    }
+    if config.engine == MemoryKvsEngine::NAME {
+        let store = match config.storage.snapshot_interval_secs {
+            Some(secs) => open_store(&config, |dir| {
+                MemoryKvsEngine::open(dir, Duration::from_secs(secs))
+            })?,
+            None => open_store(&config, |_| Ok(MemoryKvsEngine::new()))?,
+        };
+        return serve::<P, _>(cli, config, store);
+    }
+    let store = open_store(&config, KvStore::open)?;
+    let settings = store.settings();
+    let server = match config.replica_of {
+        Some(primary) => {
+            info!(%primary, "replicating");
+            KvsServer::replica(store, primary)
+        }
+        None => KvsServer::new(store),
+    };
+    serve_with::<P, KvStore>(cli, config, server, settings)
+}
+
+/// Serves an engine other than kvs, which the config only lets be a primary
+fn serve<P: ThreadPool, E: KvsEngine>(cli: Cli, config: ServerConfig, store: E) -> Result<()> {
+    let settings = store.settings();
+    serve_with::<P, E>(cli, config, KvsServer::new(store), settings)
+}
+
+/// Serves the store of the raft node `id` from a pool of type `P`
+fn run_cluster<P: ThreadPool>(cli: Cli, config: ServerConfig, id: u64) -> Result<()> {
+    let dir = match &config.data_dir {
+        Some(dir) => dir.clone(),
+        None => current_dir()?,
+    };
+    let peers: HashMap<_, _> = config
+        .cluster
+        .peers
+        .iter()
+        .map(|peer| (peer.id, peer.addr))
+        .collect();
+    let mut members: Vec<_> = peers.keys().copied().collect();
+    members.push(id);
+    members.sort_unstable();
+    let node = RaftNode::open(id, members, dir)?;
+    let settings = node.settings();
+    config.apply(&settings);
+    serve_metrics(&config, node.metrics());
+    info!(id, peers = ?peers, "joining raft cluster");
+    serve_with::<P, KvStore>(cli, config, KvsServer::cluster(node, peers), settings)
+}
+
+fn serve_with<P: ThreadPool, E: KvsEngine>(
+    cli: Cli,
+    config: ServerConfig,
+    server: KvsServer<NaiveThreadPool, E>,
+    settings: StoreSettings,
+) -> Result<()> {
+    let threads = match config.server.threads {
+        Some(threads) => threads,
+        None => thread::available_parallelism()?.get() as u32,
+    };
+    let pool = P::new(threads)?;
+    let mut server = server
+        .with_pool(pool)
+        .with_drain_timeout(Duration::from_secs(config.server.drain_timeout_secs));
+    let (tls, primary_tls) = load_tls(&config)?;
+    if let Some(tls) = tls {
+        server = server.with_tls(tls);
+    }
+    if let Some(tls) = primary_tls {
+        server = server.with_primary_tls(tls);
+    }
+    if let Some(credentials) = config.auth.primary_credentials() {
+        server = server.with_primary_credentials(credentials);
+    }
+    if let Some(path) = &config.auth.acl {
+        server = server.with_acl(Acl::load(path)?);
+    }
+    let addr = config.addr;
+    handle_signals(
+        server.shutdown_handle(),
+        server.access_control(),
+        settings,
+        cli,
+        config,
+    )?;
+    server.run(addr)
+}
+
+fn run_async(cli: Cli, config: ServerConfig) -> Result<()> {
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = config.server.threads {
+        runtime.worker_threads(threads as usize);
+    }
+    let runtime = runtime.enable_all().build()?;
+    let store = open_store(&config, KvStore::open)?;
+    let settings = store.settings();
+    let server = match config.replica_of {
+        Some(primary) => {
+            info!(%primary, "replicating");
+            AsyncKvsServer::replica(store, primary)
+        }
+        None => AsyncKvsServer::new(store),
+    };
+    let mut server =
+        server.with_drain_timeout(Duration::from_secs(config.server.drain_timeout_secs));
+    let (tls, primary_tls) = load_tls(&config)?;
+    if let Some(tls) = tls {
+        server = server.with_tls(tls);
+    }
+    if let Some(tls) = primary_tls {
+        server = server.with_primary_tls(tls);
+    }
+    if let Some(credentials) = config.auth.primary_credentials() {
+        server = server.with_primary_credentials(credentials);
+    }
+    if let Some(path) = &config.auth.acl {
+        server = server.with_acl(Acl::load(path)?);
+    }
+    let addr = config.addr;
+    handle_signals(
+        server.shutdown_handle(),
+        server.access_control(),
+        settings,
+        cli,
+        config,
+    )?;
+    runtime.block_on(server.run(addr))
+}
+
+fn run_http(cli: Cli, config: ServerConfig) -> Result<()> {
+    let store = open_store(&config, KvStore::open)?;
+    let settings = store.settings();
+    let server = match config.replica_of {
+        Some(primary) => {
+            info!(%primary, "replicating");
+            HttpServer::replica(store, primary)
+        }
+        None => HttpServer::new(store),
+    };
+    let threads = match config.server.threads {
+        Some(threads) => threads as usize,
+        None => thread::available_parallelism()?.get(),
+    };
+    let mut server = server.with_threads(threads);
+    let (_, primary_tls) = load_tls(&config)?;
+    if let Some(tls) = primary_tls {
+        server = server.with_primary_tls(tls);
+    }
+    if let Some(credentials) = config.auth.primary_credentials() {
+        server = server.with_primary_credentials(credentials);
+    }
+    if let Some(path) = &config.auth.acl {
+        server = server.with_acl(Acl::load(path)?);
+    }
+    let addr = config.addr;
+    handle_signals(
+        server.shutdown_handle(),
+        server.access_control(),
+        settings,
+        cli,
+        config,
+    )?;
+    server.run(addr)
+}
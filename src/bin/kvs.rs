@@ -1,9 +1,16 @@
 use std::env::current_dir;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use networked_kv_store::KvStore;
 use networked_kv_store::KvsError;
 use networked_kv_store::Result;
+use networked_kv_store::init_logging;
+use networked_kv_store::{AsOf, DataFormat, KvsClient, LogFormat, Shell, Snapshot, WatchEvent};
+use networked_kv_store::{repair, verify};
 
 #[derive(Subcommand)]
 enum Command {
@@ -13,6 +20,67 @@ enum Command {
     Set { key: String, value: String },
     /// remove key
     Rm { key: String },
+    /// check every log record without modifying the store
+    Verify,
+    /// salvage all readable records into a fresh generation
+    Repair,
+    /// write all live key/value pairs to a file or stdout
+    Export {
+        #[arg(long, value_enum, default_value_t = Format::Jsonl)]
+        format: Format,
+        /// only export keys starting with this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+        /// output file, stdout when omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// export the store as it was after this sequence number
+        #[arg(long, conflicts_with = "as_of_time")]
+        as_of_seq: Option<u64>,
+        /// export the store as it was at this time, in milliseconds since the unix epoch
+        #[arg(long)]
+        as_of_time: Option<u64>,
+    },
+    /// bulk-load key/value pairs from a file or stdin
+    Import {
+        #[arg(long, value_enum, default_value_t = Format::Jsonl)]
+        format: Format,
+        /// input file, stdin when omitted
+        input: Option<PathBuf>,
+    },
+    /// write a consistent copy of the store into another directory
+    Backup { dest: PathBuf },
+    /// restore a backup into the current directory
+    Restore { src: PathBuf },
+    /// print changes of keys starting with prefix, as seen by a running server
+    Watch {
+        prefix: String,
+        /// replay logged changes from this sequence number first
+        #[arg(long)]
+        from: Option<u64>,
+        /// address of the server
+        #[arg(long, default_value = "127.0.0.1:4000")]
+        addr: SocketAddr,
+    },
+    /// run commands interactively against the store, opened once
+    Shell,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// JSON Lines
+    Jsonl,
+    /// comma separated values with a header row
+    Csv,
+}
+
+impl From<Format> for DataFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Jsonl => DataFormat::JsonLines,
+            Format::Csv => DataFormat::Csv,
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -24,6 +92,8 @@ struct Cli {
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    // warnings only, RUST_LOG turns on store events when debugging
+    init_logging("warn", LogFormat::Text)?;
 
     match cli.command {
         Command::Get { key } => {
@@ -58,6 +128,116 @@ fn main() -> Result<()> {
                 },
             }
         }
+        Command::Verify => {
+            let report = verify(current_dir()?)?;
+            println!(
+                "checked {} generations, {} records, {} live keys",
+                report.generations.len(),
+                report.records,
+                report.live_keys
+            );
+            for corrupt in &report.corrupt {
+                println!(
+                    "corrupt: generation {} bytes {}..{}",
+                    corrupt.generation, corrupt.range.start, corrupt.range.end
+                );
+            }
+            if !report.history_generations.is_empty() {
+                println!(
+                    "checked {} retained generations, {} records",
+                    report.history_generations.len(),
+                    report.history_records
+                );
+            }
+            for corrupt in &report.history_corrupt {
+                println!(
+                    "corrupt: retained generation {} bytes {}..{}",
+                    corrupt.generation, corrupt.range.start, corrupt.range.end
+                );
+            }
+            for dangling in &report.dangling {
+                println!(
+                    "dangling: generation {} offset {} key {}",
+                    dangling.generation, dangling.pos, dangling.key
+                );
+            }
+            for file in &report.unexpected_files {
+                println!("unexpected file: {}", file.display());
+            }
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+        }
+        Command::Repair => {
+            let report = repair(current_dir()?)?;
+            println!(
+                "salvaged {} records ({} live keys) into generation {}",
+                report.salvaged_records, report.live_keys, report.generation
+            );
+            for corrupt in &report.dropped {
+                println!(
+                    "dropped: generation {} bytes {}..{}",
+                    corrupt.generation, corrupt.range.start, corrupt.range.end
+                );
+            }
+        }
+        Command::Export {
+            format,
+            prefix,
+            output,
+            as_of_seq,
+            as_of_time,
+        } => {
+            let prefix = prefix.as_deref();
+            let writer: Box<dyn Write> = match output {
+                Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let as_of = as_of_seq
+                .map(AsOf::Sequence)
+                .or(as_of_time.map(AsOf::Timestamp));
+            if let Some(as_of) = as_of {
+                Snapshot::open(current_dir()?, as_of)?.export(writer, format.into(), prefix)?;
+            } else {
+                KvStore::open(current_dir()?)?.export(writer, format.into(), prefix)?;
+            }
+        }
+        Command::Import { format, input } => {
+            let mut store = KvStore::open(current_dir()?)?;
+            let count = match input {
+                Some(input) => store.import(BufReader::new(File::open(input)?), format.into())?,
+                None => store.import(std::io::stdin().lock(), format.into())?,
+            };
+            eprintln!("imported {count} keys");
+        }
+        Command::Backup { dest } => {
+            let mut store = match KvStore::open(current_dir()?) {
+                Err(KvsError::DirectoryLocked(_)) => {
+                    eprintln!("the store is served, back it up with `kvs-client backup` instead");
+                    std::process::exit(1);
+                }
+                store => store?,
+            };
+            store.checkpoint(dest)?;
+        }
+        Command::Restore { src } => {
+            KvStore::restore(src, current_dir()?)?;
+        }
+        Command::Watch { prefix, from, addr } => {
+            for event in KvsClient::connect(addr)?.watch(prefix, from)? {
+                match event? {
+                    WatchEvent::Set { seq, key, value } => println!("{seq} set {key} {value}"),
+                    WatchEvent::Remove { seq, key } => println!("{seq} rm {key}"),
+                }
+            }
+        }
+        Command::Shell => {
+            let mut shell = Shell::new(KvStore::open(current_dir()?)?);
+            if let Some(home) = std::env::var_os("HOME") {
+                shell = shell.with_history(PathBuf::from(home).join(".kvs_history"));
+            }
+            shell.run("kvs> ")?;
+        }
     }
     Ok(())
 }
@@ -0,0 +1,422 @@
+use crate::common::{RemoteError, Request, Response, receive, send};
+use crate::tls::Stream;
+use crate::watch::WatchEvent;
+use crate::{ClientTls, Credentials, KvsError, Result, SlowEntry};
+
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// redirections to the leader of a raft cluster followed for a single request
+const MAX_REDIRECTS: usize = 3;
+/// requests a pipeline sends ahead of the replies it read
+const PIPELINE_WINDOW: usize = 64;
+/// bytes of the requests a pipeline sends ahead of the replies it read,
+/// small enough for the socket buffers to hold them while the server is
+/// blocked writing replies
+const PIPELINE_WINDOW_BYTES: usize = 64 * 1024;
+
+/// Options used when connecting a KvsClient or a KvsClientPool
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// how long to wait for a connection, forever when `None`
+    pub connect_timeout: Option<Duration>,
+    /// how long to wait for a reply, forever when `None`
+    pub read_timeout: Option<Duration>,
+    /// how long to wait for a request to be sent, forever when `None`
+    pub write_timeout: Option<Duration>,
+    /// times a pool retries a failed idempotent request
+    pub retries: u32,
+    /// delay before the first retry, doubled for every following one
+    pub backoff: Duration,
+    /// longest delay between two retries
+    pub max_backoff: Duration,
+    /// idle connections a pool keeps for reuse
+    pub max_idle: usize,
+    /// encrypt connections, plain text when `None`
+    pub tls: Option<ClientTls>,
+    /// credentials sent on every new connection, anonymous when `None`
+    pub credentials: Option<Credentials>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            connect_timeout: Some(Duration::from_secs(5)),
+            read_timeout: Some(Duration::from_secs(5)),
+            write_timeout: Some(Duration::from_secs(5)),
+            retries: 3,
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            max_idle: 8,
+            tls: None,
+            credentials: None,
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Default options waiting on the server as long as it takes
+    pub(crate) fn without_timeouts() -> Self {
+        ClientOptions {
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            ..ClientOptions::default()
+        }
+    }
+}
+
+/// A client talking to a KvsServer
+///
+/// Requests sent to a member of a raft cluster that is not the leader are
+/// sent again to the leader, over a new connection opened with the same
+/// options.
+pub struct KvsClient {
+    reader: BufReader<Stream>,
+    writer: BufWriter<Stream>,
+    next_id: u64,
+    options: ClientOptions,
+    /// requests fully written, redirected ones included
+    requests_sent: u64,
+}
+
+impl KvsClient {
+    /// Connects to the server at `addr`, waiting on it as long as it takes
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr).map_err(classify)?;
+        KvsClient::from_stream(Stream::Plain(stream), ClientOptions::without_timeouts())
+    }
+
+    /// Connects to the server at `addr` with the timeouts, TLS settings
+    /// and credentials of `options`
+    ///
+    /// Every address `addr` resolves to is tried in turn.
+    pub fn connect_with(addr: impl ToSocketAddrs, options: &ClientOptions) -> Result<Self> {
+        let mut last_error = None;
+        for addr in addr.to_socket_addrs()? {
+            let stream = match options.connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(options.read_timeout)?;
+                    stream.set_write_timeout(options.write_timeout)?;
+                    let stream = match &options.tls {
+                        Some(tls) => tls.connect(stream)?,
+                        None => Stream::Plain(stream),
+                    };
+                    let mut client = KvsClient::from_stream(stream, options.clone())?;
+                    if let Some(credentials) = &options.credentials {
+                        client.authenticate(credentials.clone())?;
+                    }
+                    return Ok(client);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.map_or_else(
+            || std::io::Error::new(ErrorKind::InvalidInput, "no address to connect to").into(),
+            classify,
+        ))
+    }
+
+    fn from_stream(stream: Stream, options: ClientOptions) -> Result<Self> {
+        Ok(KvsClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+            next_id: 0,
+            options,
+            requests_sent: 0,
+        })
+    }
+
+    /// Identifies the user of the connection to a server enforcing an ACL
+    ///
+    /// Fails with `Unauthenticated` when the credentials are wrong.
+    pub fn authenticate(&mut self, credentials: Credentials) -> Result<()> {
+        self.request(&Request::Auth(credentials.clone()))?;
+        // connections opened to follow a redirection authenticate too
+        self.options.credentials = Some(credentials);
+        Ok(())
+    }
+
+    /// Gets a value by key
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.request(&Request::Get { key })
+    }
+
+    /// Sets a value for a key
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.request(&Request::Set { key, value }).map(|_| ())
+    }
+
+    /// Removes a key and its associated value
+    /// Returns an error if the key doesn't exist
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.request(&Request::Remove { key }).map(|_| ())
+    }
+
+    /// Returns every live key/value pair starting with `prefix`, ordered by key
+    pub fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        match self.exchange(&Request::Scan { prefix })? {
+            Response::Entries(entries) => Ok(entries),
+            Response::Err(e) => Err(e.into()),
+            _ => Err(KvsError::UnexpectedCommandType),
+        }
+    }
+
+    /// Asks the server to shut down once the requests it received are answered
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.request(&Request::Shutdown).map(|_| ())
+    }
+
+    /// Returns up to `count` of the slowest recent operations of the server, newest first
+    pub fn slow_log(&mut self, count: usize) -> Result<Vec<SlowEntry>> {
+        match self.exchange(&Request::SlowLogGet { count })? {
+            Response::SlowLog(entries) => Ok(entries),
+            Response::Err(e) => Err(e.into()),
+            _ => Err(KvsError::UnexpectedCommandType),
+        }
+    }
+
+    /// Empties the slow log of the server
+    pub fn reset_slow_log(&mut self) -> Result<()> {
+        self.request(&Request::SlowLogReset).map(|_| ())
+    }
+
+    /// Asks the server to write a consistent copy of its store into `dest`,
+    /// a directory on the machine of the server, while it keeps serving
+    pub fn checkpoint(&mut self, dest: impl Into<PathBuf>) -> Result<()> {
+        let dest = dest.into();
+        self.request(&Request::Checkpoint { dest }).map(|_| ())
+    }
+
+    /// Starts a batch of requests sent without waiting for each other's reply
+    pub fn pipeline(&mut self) -> Pipeline<'_> {
+        Pipeline {
+            client: self,
+            requests: Vec::new(),
+        }
+    }
+
+    /// Gets the values of `keys` in a single round trip
+    pub fn get_many(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        let mut pipeline = self.pipeline();
+        for key in keys {
+            pipeline = pipeline.get(key);
+        }
+        pipeline.send()?.into_iter().collect()
+    }
+
+    /// Subscribes to the changes of keys starting with `prefix`,
+    /// replaying the logged changes from sequence number `from` first
+    ///
+    /// The connection is dedicated to the subscription from then on,
+    /// waiting for changes never times out.
+    pub fn watch(mut self, prefix: String, from: Option<u64>) -> Result<WatchStream> {
+        send(&mut self.writer, &Request::Watch { prefix, from }).map_err(classify)?;
+        self.reader.get_ref().set_read_timeout(None)?;
+        Ok(WatchStream {
+            reader: self.reader,
+        })
+    }
+
+    fn request(&mut self, request: &Request) -> Result<Option<String>> {
+        match self.exchange(request)? {
+            Response::Ok(value) => Ok(value),
+            Response::Err(e) => Err(e.into()),
+            _ => Err(KvsError::UnexpectedCommandType),
+        }
+    }
+
+    /// Address of the server the client is connected to
+    pub(crate) fn peer_addr(&self) -> Result<SocketAddr> {
+        self.writer.get_ref().peer_addr()
+    }
+
+    /// Number of requests fully written by this client, a request that
+    /// failed before that point never reached the server
+    pub(crate) fn requests_sent(&self) -> u64 {
+        self.requests_sent
+    }
+
+    /// Sends `request` and returns the reply, following the redirections
+    /// of cluster members to their leader
+    fn exchange(&mut self, request: &Request) -> Result<Response> {
+        let mut redirects = 0;
+        loop {
+            send(&mut self.writer, request).map_err(classify)?;
+            self.requests_sent += 1;
+            match receive(&mut self.reader).map_err(classify)? {
+                Some(Response::Err(RemoteError::Redirect(leader))) if redirects < MAX_REDIRECTS => {
+                    let requests_sent = self.requests_sent;
+                    *self = KvsClient::connect_with(leader, &self.options)?;
+                    self.requests_sent = requests_sent;
+                    redirects += 1;
+                }
+                Some(response) => return Ok(response),
+                None => return Err(connection_closed()),
+            }
+        }
+    }
+}
+
+/// Requests queued on a client, sent together by `send`
+pub struct Pipeline<'a> {
+    client: &'a mut KvsClient,
+    requests: Vec<Request>,
+}
+
+impl Pipeline<'_> {
+    /// Queues getting a value by key
+    pub fn get(mut self, key: String) -> Self {
+        self.requests.push(Request::Get { key });
+        self
+    }
+
+    /// Queues setting a value for a key
+    pub fn set(mut self, key: String, value: String) -> Self {
+        self.requests.push(Request::Set { key, value });
+        self
+    }
+
+    /// Queues removing a key and its associated value
+    pub fn remove(mut self, key: String) -> Self {
+        self.requests.push(Request::Remove { key });
+        self
+    }
+
+    /// Sends the queued requests without waiting for each other's reply,
+    /// and waits for all the replies
+    ///
+    /// Returns the outcome of each request in the order they were queued,
+    /// gets carry their value and other requests `None`. The server may run
+    /// the requests concurrently, queue requests touching the same key in
+    /// separate pipelines when their order matters. Replies are read while
+    /// the requests are sent, so that neither side blocks writing to the
+    /// other, however long the pipeline.
+    pub fn send(self) -> Result<Vec<Result<Option<String>>>> {
+        let client = self.client;
+        let first = client.next_id;
+        let count = self.requests.len();
+        client.next_id += count as u64;
+
+        let mut replies: Vec<Option<Result<Option<String>>>> = (0..count).map(|_| None).collect();
+        let mut sizes = vec![0; count];
+        let (mut in_flight, mut in_flight_bytes) = (0, 0);
+        for (id, request) in (first..).zip(self.requests) {
+            let request = Box::new(request);
+            let mut line = serde_json::to_vec(&Request::Tagged { id, request })?;
+            line.push(b'\n');
+            while in_flight > 0
+                && (in_flight >= PIPELINE_WINDOW
+                    || in_flight_bytes + line.len() > PIPELINE_WINDOW_BYTES)
+            {
+                let position = receive_reply(client, first, &mut replies)?;
+                in_flight -= 1;
+                in_flight_bytes -= sizes[position];
+            }
+            client
+                .writer
+                .write_all(&line)
+                .map_err(KvsError::from)
+                .map_err(classify)?;
+            sizes[(id - first) as usize] = line.len();
+            in_flight += 1;
+            in_flight_bytes += line.len();
+        }
+        for _ in 0..in_flight {
+            receive_reply(client, first, &mut replies)?;
+        }
+        replies
+            .into_iter()
+            .map(|reply| reply.ok_or(KvsError::UnexpectedCommandType))
+            .collect()
+    }
+}
+
+/// Flushes the requests written so far and reads the next reply of a
+/// pipeline into `replies`, returns the position of its request
+fn receive_reply(
+    client: &mut KvsClient,
+    first: u64,
+    replies: &mut [Option<Result<Option<String>>>],
+) -> Result<usize> {
+    client
+        .writer
+        .flush()
+        .map_err(KvsError::from)
+        .map_err(classify)?;
+    let (id, response) = match receive(&mut client.reader).map_err(classify)? {
+        Some(Response::Tagged { id, response }) => (id, *response),
+        Some(_) => return Err(KvsError::UnexpectedCommandType),
+        None => return Err(connection_closed()),
+    };
+    let position = id
+        .checked_sub(first)
+        .map(|position| position as usize)
+        .filter(|&position| replies.get(position).is_some_and(Option::is_none))
+        .ok_or(KvsError::UnexpectedCommandType)?;
+    replies[position] = Some(match response {
+        Response::Ok(value) => Ok(value),
+        Response::Err(e) => Err(e.into()),
+        _ => Err(KvsError::UnexpectedCommandType),
+    });
+    Ok(position)
+}
+
+/// Changes streamed by the server for a subscription
+pub struct WatchStream {
+    reader: BufReader<Stream>,
+}
+
+impl Iterator for WatchStream {
+    type Item = Result<WatchEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match receive(&mut self.reader) {
+            Ok(Some(Response::Event(event))) => Some(Ok(event)),
+            Ok(Some(Response::Err(e))) => Some(Err(e.into())),
+            Ok(Some(_)) => Some(Err(KvsError::UnexpectedCommandType)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Tells timeouts and refused connections apart from other I/O errors
+fn classify(error: impl Into<KvsError>) -> KvsError {
+    match error.into() {
+        KvsError::IoError(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+            KvsError::Timeout
+        }
+        KvsError::IoError(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            KvsError::ConnectionRefused
+        }
+        error => error,
+    }
+}
+
+/// Whether `error` leaves the connection in an unknown state, so it must
+/// not be used again, rather than coming from a reply of the server
+pub(crate) fn is_connection_error(error: &KvsError) -> bool {
+    matches!(
+        error,
+        KvsError::IoError(_)
+            | KvsError::SerdeError(_)
+            | KvsError::Timeout
+            | KvsError::ConnectionRefused
+            | KvsError::UnexpectedCommandType
+    )
+}
+
+fn connection_closed() -> KvsError {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "server closed the connection",
+    )
+    .into()
+}
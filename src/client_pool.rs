@@ -0,0 +1,109 @@
+use crate::client::{ClientOptions, is_connection_error};
+use crate::{KvsClient, KvsError, Result};
+
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+
+/// A thread-safe client keeping connections to a server open for reuse
+///
+/// Requests check a connection out of the pool, or open a new one, and
+/// put it back once answered. Connections failing mid-request are dropped.
+/// Requests failing on a timeout, a refused connection or another I/O
+/// error are retried with exponential backoff when that is safe: gets,
+/// sets and scans always, removes only when the request was never sent.
+pub struct KvsClientPool {
+    addrs: Vec<SocketAddr>,
+    options: ClientOptions,
+    idle: Mutex<Vec<KvsClient>>,
+}
+
+impl KvsClientPool {
+    /// Creates a pool of connections to the server at `addr`,
+    /// connections are opened on first use
+    pub fn new(addr: impl ToSocketAddrs, options: ClientOptions) -> Result<Self> {
+        Ok(KvsClientPool {
+            addrs: addr.to_socket_addrs()?.collect(),
+            options,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Gets a value by key
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        self.with_client(true, |client| client.get(key.clone()))
+    }
+
+    /// Sets a value for a key
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        self.with_client(true, |client| client.set(key.clone(), value.clone()))
+    }
+
+    /// Removes a key and its associated value
+    /// Returns an error if the key doesn't exist
+    pub fn remove(&self, key: String) -> Result<()> {
+        self.with_client(false, |client| client.remove(key.clone()))
+    }
+
+    /// Returns every live key/value pair starting with `prefix`, ordered by key
+    pub fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        self.with_client(true, |client| client.scan(prefix.clone()))
+    }
+
+    /// Number of connections waiting for reuse
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().expect("pool lock poisoned").len()
+    }
+
+    fn with_client<T>(
+        &self,
+        idempotent: bool,
+        mut request: impl FnMut(&mut KvsClient) -> Result<T>,
+    ) -> Result<T> {
+        let mut backoff = self.options.backoff;
+        let mut attempt = 0;
+        loop {
+            let (sent, result) = match self.checkout() {
+                Ok(mut client) => {
+                    let before = client.requests_sent();
+                    let result = request(&mut client);
+                    let sent = client.requests_sent() > before;
+                    if !matches!(&result, Err(e) if is_connection_error(e)) {
+                        self.checkin(client);
+                    }
+                    (sent, result)
+                }
+                Err(e) => (false, Err(e)),
+            };
+            match result {
+                Err(e) if attempt < self.options.retries && retryable(&e, idempotent || !sent) => {
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.options.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn checkout(&self) -> Result<KvsClient> {
+        if let Some(client) = self.idle.lock().expect("pool lock poisoned").pop() {
+            return Ok(client);
+        }
+        KvsClient::connect_with(&self.addrs[..], &self.options)
+    }
+
+    fn checkin(&self, client: KvsClient) {
+        let mut idle = self.idle.lock().expect("pool lock poisoned");
+        if idle.len() < self.options.max_idle {
+            idle.push(client);
+        }
+    }
+}
+
+fn retryable(error: &KvsError, safe: bool) -> bool {
+    safe && matches!(
+        error,
+        KvsError::Timeout | KvsError::ConnectionRefused | KvsError::IoError(_)
+    )
+}
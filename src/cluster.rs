@@ -0,0 +1,262 @@
+use crate::common::{Request, Response, receive, send};
+use crate::raft::{Command, Message, NodeId, RaftNode};
+use crate::server::execute;
+use crate::tls::Stream;
+use crate::{ClientTls, Credentials, KvsEngine, KvsError, Result, ShutdownHandle};
+
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// how often the logical clock of a node advances
+const TICK: Duration = Duration::from_millis(50);
+/// how long a write waits to be committed before failing with `Timeout`
+const COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// how long connecting or sending to a peer may take
+const PEER_TIMEOUT: Duration = Duration::from_secs(1);
+/// delay before a peer that could not be reached is tried again
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// messages queued for a peer before new ones are dropped
+const PEER_QUEUE: usize = 256;
+
+/// A raft node answering the requests of a server, with the threads driving
+/// its clock and carrying its messages to the other nodes
+///
+/// Writes go through the raft log and are answered once applied, reads are
+/// answered by the leader. Other nodes answer with the address of the leader.
+pub(crate) struct Cluster {
+    id: NodeId,
+    node: Mutex<RaftNode>,
+    /// notified whenever the node stepped, its entries may have been applied
+    progress: Condvar,
+    peers: HashMap<NodeId, SocketAddr>,
+    outboxes: Mutex<HashMap<NodeId, SyncSender<Message>>>,
+    tls: Option<ClientTls>,
+    credentials: Option<Credentials>,
+}
+
+impl Cluster {
+    /// Serves `node`, reaching the other members at the addresses of `peers`
+    pub(crate) fn new(node: RaftNode, peers: HashMap<NodeId, SocketAddr>) -> Self {
+        Cluster {
+            id: node.id(),
+            node: Mutex::new(node),
+            progress: Condvar::new(),
+            peers,
+            outboxes: Mutex::new(HashMap::new()),
+            tls: None,
+            credentials: None,
+        }
+    }
+
+    /// Connects to the peers with `tls` and authenticates with `credentials`
+    pub(crate) fn with_peer_settings(
+        mut self,
+        tls: Option<ClientTls>,
+        credentials: Option<Credentials>,
+    ) -> Self {
+        self.tls = tls;
+        self.credentials = credentials;
+        self
+    }
+
+    /// Ticks the node until the server is shut down
+    pub(crate) fn drive(&self, shutdown: &ShutdownHandle) {
+        while !shutdown.is_shutdown() {
+            thread::sleep(TICK);
+            if let Err(e) = self.with_node(RaftNode::tick) {
+                warn!(error = %e, "raft tick failed");
+            }
+        }
+    }
+
+    /// Runs a request answered with a single response, through the raft log
+    /// for the ones touching keys
+    pub(crate) fn execute<E: KvsEngine>(&self, store: &Mutex<E>, request: Request) -> Response {
+        let response = match request {
+            Request::Tagged { id, request } => {
+                let response = Box::new(self.execute(store, *request));
+                return Response::Tagged { id, response };
+            }
+            Request::Raft { from, message } => self
+                .with_node(|node| node.step(from, message))
+                .map(|()| Response::Ok(None)),
+            Request::Get { key } => self.with_node(|node| node.get(key)).map(Response::Ok),
+            Request::Scan { prefix } => self
+                .with_node(|node| node.scan(&prefix))
+                .map(Response::Entries),
+            Request::Set { key, value } => self
+                .commit(Command::Set { key, value })
+                .map(|()| Response::Ok(None)),
+            Request::Remove { key } => self
+                .commit(Command::Remove { key })
+                .map(|()| Response::Ok(None)),
+            request => return execute(store, request, false),
+        };
+        response.unwrap_or_else(|e| Response::Err(self.redirect(e).into()))
+    }
+
+    /// Proposes `command` and waits until the node applied it
+    ///
+    /// A leader losing its term before then cannot tell whether the command
+    /// will be committed, the client is redirected to retry it.
+    fn commit(&self, command: Command) -> Result<()> {
+        let (index, term) = self.with_node(|node| Ok((node.propose(command)?, node.term())))?;
+        let node = self.node.lock().expect("raft lock poisoned");
+        let (node, waited) = self
+            .progress
+            .wait_timeout_while(node, COMMIT_TIMEOUT, |node| {
+                node.applied_index() < index && node.term() == term
+            })
+            .expect("raft lock poisoned");
+        if node.applied_index() >= index && node.term() == term {
+            Ok(())
+        } else if waited.timed_out() {
+            Err(KvsError::Timeout)
+        } else {
+            Err(KvsError::NotLeader(node.leader()))
+        }
+    }
+
+    /// Runs `f` on the node and delivers the messages it sent
+    fn with_node<T>(&self, f: impl FnOnce(&mut RaftNode) -> Result<T>) -> Result<T> {
+        let mut node = self.node.lock().expect("raft lock poisoned");
+        let result = f(&mut node);
+        let messages = node.take_messages();
+        drop(node);
+        self.progress.notify_all();
+        for (to, message) in messages {
+            self.deliver(to, message);
+        }
+        result
+    }
+
+    /// Queues `message` on the connection to `to`, opened on first use
+    ///
+    /// A peer too slow to keep up loses the messages that do not fit in
+    /// its queue, raft sends them again.
+    fn deliver(&self, to: NodeId, message: Message) {
+        let mut outboxes = self.outboxes.lock().expect("outboxes lock poisoned");
+        let outbox = match outboxes.get(&to) {
+            Some(outbox) => outbox,
+            None => {
+                let Some(&addr) = self.peers.get(&to) else {
+                    warn!(peer = to, "no address for raft peer");
+                    return;
+                };
+                let (sender, receiver) = mpsc::sync_channel(PEER_QUEUE);
+                let peer = Peer {
+                    from: self.id,
+                    addr,
+                    tls: self.tls.clone(),
+                    credentials: self.credentials.clone(),
+                };
+                thread::spawn(move || peer.run(receiver));
+                outboxes.entry(to).or_insert(sender)
+            }
+        };
+        // the thread only stops once the cluster is dropped
+        if let Err(TrySendError::Full(_)) = outbox.try_send(message) {
+            warn!(peer = to, "raft peer falling behind, dropping a message");
+        }
+    }
+
+    /// Replaces a `NotLeader` naming a known node with the address to retry at
+    fn redirect(&self, error: KvsError) -> KvsError {
+        match error {
+            KvsError::NotLeader(Some(leader)) => match self.peers.get(&leader) {
+                Some(&addr) => KvsError::Redirect(addr),
+                None => KvsError::NotLeader(Some(leader)),
+            },
+            error => error,
+        }
+    }
+}
+
+/// The connection of a node to one of its peers
+struct Peer {
+    from: NodeId,
+    addr: SocketAddr,
+    tls: Option<ClientTls>,
+    credentials: Option<Credentials>,
+}
+
+type Connection = (BufReader<Stream>, BufWriter<Stream>);
+
+impl Peer {
+    /// Sends the queued messages until the cluster is dropped
+    ///
+    /// Messages are dropped while the peer cannot be reached, raft
+    /// sends them again.
+    fn run(self, messages: Receiver<Message>) {
+        let mut connection = None;
+        let mut retry_at = Instant::now();
+        for message in messages {
+            if connection.is_none() && Instant::now() >= retry_at {
+                match self.connect() {
+                    Ok(opened) => connection = Some(opened),
+                    Err(e) => {
+                        warn!(peer = %self.addr, error = %e, "unable to reach raft peer");
+                        retry_at = Instant::now() + RECONNECT_DELAY;
+                    }
+                }
+            }
+            let Some((reader, writer)) = &mut connection else {
+                continue;
+            };
+            if let Err(e) = self.send(reader, writer, message) {
+                warn!(peer = %self.addr, error = %e, "raft peer connection lost");
+                connection = None;
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        let stream = TcpStream::connect_timeout(&self.addr, PEER_TIMEOUT)?;
+        stream.set_read_timeout(Some(PEER_TIMEOUT))?;
+        stream.set_write_timeout(Some(PEER_TIMEOUT))?;
+        let stream = match &self.tls {
+            Some(tls) => tls.connect(stream)?,
+            None => Stream::Plain(stream),
+        };
+        let mut connection = (BufReader::new(stream.try_clone()?), BufWriter::new(stream));
+        if let Some(credentials) = &self.credentials {
+            let (reader, writer) = &mut connection;
+            exchange(reader, writer, &Request::Auth(credentials.clone()))?;
+        }
+        Ok(connection)
+    }
+
+    fn send(
+        &self,
+        reader: &mut BufReader<Stream>,
+        writer: &mut BufWriter<Stream>,
+        message: Message,
+    ) -> Result<()> {
+        let request = Request::Raft {
+            from: self.from,
+            message,
+        };
+        exchange(reader, writer, &request)
+    }
+}
+
+/// Sends `request` and waits for the server to accept it
+fn exchange(
+    reader: &mut BufReader<Stream>,
+    writer: &mut BufWriter<Stream>,
+    request: &Request,
+) -> Result<()> {
+    send(writer, request)?;
+    match receive(reader)? {
+        Some(Response::Ok(_)) => Ok(()),
+        Some(Response::Err(e)) => Err(e.into()),
+        Some(_) => Err(KvsError::UnexpectedCommandType),
+        None => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+    }
+}
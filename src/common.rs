@@ -0,0 +1,241 @@
+use crate::acl::Credentials;
+use crate::kv::LogEntry;
+use crate::raft::{Message, NodeId};
+use crate::slowlog::SlowEntry;
+use crate::watch::WatchEvent;
+use crate::{KvsError, Result};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// a request sent by a client, one JSON document per line
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum Request {
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        value: String,
+    },
+    Remove {
+        key: String,
+    },
+    Scan {
+        prefix: String,
+    },
+    Watch {
+        prefix: String,
+        from: Option<u64>,
+    },
+    Replicate {
+        from: u64,
+    },
+    /// `request` answered with a `Response::Tagged` of the same id,
+    /// the server may answer tagged requests out of order
+    Tagged {
+        id: u64,
+        request: Box<Request>,
+    },
+    /// stops the server once the requests it received are answered
+    Shutdown,
+    /// returns up to `count` entries of the slow log, newest first
+    SlowLogGet {
+        count: usize,
+    },
+    /// empties the slow log
+    SlowLogReset,
+    /// writes a consistent copy of the store into `dest`, a directory on
+    /// the machine of the server
+    Checkpoint {
+        dest: PathBuf,
+    },
+    /// identifies the client, answered with an error when the credentials
+    /// are wrong, the server keeps the connection open for another try
+    Auth(Credentials),
+    /// a message of the raft node `from` to the node of the server
+    Raft {
+        from: NodeId,
+        message: Message,
+    },
+}
+
+impl Request {
+    /// whether the request turns the connection into a stream of responses
+    pub(crate) fn is_subscription(&self) -> bool {
+        matches!(self, Request::Watch { .. } | Request::Replicate { .. })
+    }
+}
+
+/// a reply sent by the server, one JSON document per line
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum Response {
+    Ok(Option<String>),
+    Entries(Vec<(String, String)>),
+    Err(RemoteError),
+    Event(WatchEvent),
+    Snapshot {
+        seq: u64,
+        entries: Vec<(String, String)>,
+    },
+    Record(LogEntry),
+    Tagged {
+        id: u64,
+        response: Box<Response>,
+    },
+    SlowLog(Vec<SlowEntry>),
+}
+
+/// an error reported by the server
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum RemoteError {
+    KeyNotFound,
+    ReadOnly,
+    Unauthenticated,
+    Forbidden,
+    LimitExceeded(String),
+    /// the node is not the leader of its cluster, the leader is at this address
+    Redirect(SocketAddr),
+    Other(String),
+}
+
+impl From<KvsError> for RemoteError {
+    fn from(error: KvsError) -> Self {
+        match error {
+            KvsError::KeyNotFound => RemoteError::KeyNotFound,
+            KvsError::ReadOnly => RemoteError::ReadOnly,
+            KvsError::Unauthenticated => RemoteError::Unauthenticated,
+            KvsError::Forbidden => RemoteError::Forbidden,
+            KvsError::LimitExceeded(e) => RemoteError::LimitExceeded(e),
+            KvsError::Redirect(leader) => RemoteError::Redirect(leader),
+            error => RemoteError::Other(error.to_string()),
+        }
+    }
+}
+
+impl From<RemoteError> for KvsError {
+    fn from(error: RemoteError) -> Self {
+        match error {
+            RemoteError::KeyNotFound => KvsError::KeyNotFound,
+            RemoteError::ReadOnly => KvsError::ReadOnly,
+            RemoteError::Unauthenticated => KvsError::Unauthenticated,
+            RemoteError::Forbidden => KvsError::Forbidden,
+            RemoteError::LimitExceeded(e) => KvsError::LimitExceeded(e),
+            RemoteError::Redirect(leader) => KvsError::Redirect(leader),
+            RemoteError::Other(message) => KvsError::ServerError(message),
+        }
+    }
+}
+
+/// Writes `message` as a single line and flushes it
+pub(crate) fn send<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<()> {
+    write(writer, message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes `message` as a single line, leaving it in the buffers of `writer`
+pub(crate) fn write<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<()> {
+    // a failed write is reported as such, not as a serialization error
+    serde_json::to_writer(&mut *writer, message).map_err(|e| match e.is_io() {
+        true => KvsError::IoError(e.into()),
+        false => e.into(),
+    })?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Longest request line a server reads, raft snapshots sent to a peer included
+pub(crate) const MAX_REQUEST_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Reads the next line as a message, `None` when the peer closed the connection
+pub(crate) fn receive<R: BufRead, T: DeserializeOwned>(reader: &mut R) -> Result<Option<T>> {
+    receive_limited(reader, u64::MAX)
+}
+
+/// Reads the next line as a message of at most `limit` bytes, `None` when
+/// the peer closed the connection
+///
+/// Fails with `LimitExceeded` on a longer line, whose rest is left unread.
+pub(crate) fn receive_limited<R: BufRead, T: DeserializeOwned>(
+    reader: &mut R,
+    limit: u64,
+) -> Result<Option<T>> {
+    let mut line = String::new();
+    let read = reader
+        .by_ref()
+        .take(limit.saturating_add(1))
+        .read_line(&mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    check_line(read, limit)?;
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+fn check_line(read: usize, limit: u64) -> Result<()> {
+    match read as u64 > limit {
+        true => Err(KvsError::LimitExceeded(format!(
+            "messages are at most {limit} bytes"
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Writes `message` as a single line and flushes it, on an async stream
+pub(crate) async fn send_async<W, T>(writer: &mut W, message: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    write_async(writer, message).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Writes `message` as a single line on an async stream,
+/// leaving it in the buffers of `writer`
+pub(crate) async fn write_async<W, T>(writer: &mut W, message: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+/// Reads the next line as a message from an async stream,
+/// `None` when the peer closed the connection
+pub(crate) async fn receive_async<R, T>(reader: &mut R) -> Result<Option<T>>
+where
+    R: AsyncBufRead + Unpin,
+    T: DeserializeOwned,
+{
+    receive_async_limited(reader, u64::MAX).await
+}
+
+/// Reads the next line as a message of at most `limit` bytes from an
+/// async stream, as `receive_limited` does
+pub(crate) async fn receive_async_limited<R, T>(reader: &mut R, limit: u64) -> Result<Option<T>>
+where
+    R: AsyncBufRead + Unpin,
+    T: DeserializeOwned,
+{
+    let mut line = String::new();
+    let mut reader = reader.take(limit.saturating_add(1));
+    let read = match reader.read_line(&mut line).await {
+        Ok(0) => return Ok(None),
+        // TLS peers may close without a close_notify between messages
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof && line.is_empty() => return Ok(None),
+        Err(e) => return Err(e.into()),
+        Ok(read) => read,
+    };
+    check_line(read, limit)?;
+    Ok(Some(serde_json::from_str(&line)?))
+}
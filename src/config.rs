@@ -0,0 +1,466 @@
+use crate::engine::{ENGINES, KVS_ENGINE};
+use crate::logging::LogFormat;
+use crate::raft::NodeId;
+use crate::settings::{DEFAULT_COMPACTION_THRESHOLD, Durability, StoreSettings};
+use crate::{Credentials, KvsError, Result};
+
+use serde::Deserialize;
+use std::fmt::{self, Debug};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How a server serves its connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServerMode {
+    /// a thread per connection
+    #[default]
+    Naive,
+    /// fixed workers sharing a queue
+    SharedQueue,
+    /// fixed work-stealing workers
+    Rayon,
+    /// an event loop
+    Async,
+    /// a JSON REST API over HTTP instead of the native protocol
+    Http,
+}
+
+impl ServerMode {
+    /// Returns the name of the mode in configuration files
+    pub fn name(self) -> &'static str {
+        match self {
+            ServerMode::Naive => "naive",
+            ServerMode::SharedQueue => "shared-queue",
+            ServerMode::Rayon => "rayon",
+            ServerMode::Async => "async",
+            ServerMode::Http => "http",
+        }
+    }
+}
+
+/// Settings of kvs-server, usually read from a TOML file
+///
+/// Every key is optional. Top-level keys come first, the rest are grouped
+/// in tables:
+///
+/// ```toml
+/// addr = "127.0.0.1:4000"
+/// data_dir = "/var/lib/kvs"
+/// engine = "kvs"
+///
+/// [server]
+/// mode = "rayon"
+/// threads = 8
+///
+/// [storage]
+/// durability = "sync"
+/// compaction_threshold_bytes = 4194304
+///
+/// [limits]
+/// max_value_bytes = 65536
+///
+/// [cluster]
+/// id = 1
+///
+/// [[cluster.peers]]
+/// id = 2
+/// addr = "127.0.0.1:4001"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// address to listen on
+    pub addr: SocketAddr,
+    /// directory of the store, the working directory when unset
+    pub data_dir: Option<PathBuf>,
+    /// storage engine, `kvs`, `memory` or, when built with the `sled` feature, `sled`
+    pub engine: String,
+    /// primary to follow, serving read-only traffic
+    pub replica_of: Option<SocketAddr>,
+    /// how connections are served
+    pub server: ServerSection,
+    /// how the store writes and compacts its log
+    pub storage: StorageSection,
+    /// certificates of the server and of its primary
+    pub tls: TlsSection,
+    /// who may access the store
+    pub auth: AuthSection,
+    /// largest requests accepted
+    pub limits: LimitsSection,
+    /// what is logged and how
+    pub log: LogSection,
+    /// the raft cluster the server is a member of
+    pub cluster: ClusterSection,
+}
+
+/// The `[server]` table
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    /// how connections are served
+    pub mode: ServerMode,
+    /// number of threads of fixed size pools, defaults to the number of CPUs
+    pub threads: Option<u32>,
+    /// seconds to wait for in-flight requests when shutting down
+    pub drain_timeout_secs: u64,
+    /// admin address serving Prometheus metrics on /metrics
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+/// The `[storage]` table
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSection {
+    /// how far writes go before they are acknowledged
+    pub durability: Durability,
+    /// stale bytes in the log that trigger a compaction
+    pub compaction_threshold_bytes: u64,
+    /// operations taking at least this many milliseconds go to the slow log
+    pub slowlog_threshold_ms: Option<u64>,
+    /// seconds between snapshots of the memory engine to the data directory,
+    /// which keeps nothing on disk when unset
+    pub snapshot_interval_secs: Option<u64>,
+}
+
+/// The `[tls]` table
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    /// PEM certificate chain presented to clients, enables TLS
+    pub cert: Option<PathBuf>,
+    /// PEM private key of the certificate
+    pub key: Option<PathBuf>,
+    /// only accept clients presenting a certificate signed by this PEM authority
+    pub client_ca: Option<PathBuf>,
+    /// PEM authority the certificate of the primary must be signed by
+    pub primary_ca: Option<PathBuf>,
+}
+
+/// The `[auth]` table
+#[derive(Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    /// JSON file of users and the key prefixes they may access
+    pub acl: Option<PathBuf>,
+    /// user replicas authenticate as to their primary, and members of a
+    /// cluster to their peers
+    pub primary_user: Option<String>,
+    /// password of `primary_user`
+    pub primary_password: Option<String>,
+    /// token to authenticate with instead of a user and password
+    pub primary_token: Option<String>,
+}
+
+impl AuthSection {
+    /// Returns the credentials to present to the primary or to the peers
+    pub fn primary_credentials(&self) -> Option<Credentials> {
+        match (
+            &self.primary_user,
+            &self.primary_password,
+            &self.primary_token,
+        ) {
+            (Some(user), Some(password), _) => Some(Credentials::Password {
+                user: user.clone(),
+                password: password.clone(),
+            }),
+            (_, _, Some(token)) => Some(Credentials::Token(token.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl Debug for AuthSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthSection")
+            .field("acl", &self.acl)
+            .field("primary_credentials", &self.primary_credentials())
+            .finish()
+    }
+}
+
+/// The `[limits]` table
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    /// longest key accepted
+    pub max_key_bytes: Option<u64>,
+    /// longest value accepted
+    pub max_value_bytes: Option<u64>,
+}
+
+/// The `[cluster]` table
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusterSection {
+    /// identifier of the node, the server is a raft member when set
+    pub id: Option<NodeId>,
+    /// the other founding members of the cluster
+    pub peers: Vec<PeerSection>,
+}
+
+/// An entry of the `[[cluster.peers]]` array
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerSection {
+    /// identifier of the peer
+    pub id: NodeId,
+    /// address the peer listens on
+    pub addr: SocketAddr,
+}
+
+/// The `[log]` table
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSection {
+    /// lowest level logged, or filter directives
+    pub level: String,
+    /// how events are written to stderr
+    pub format: LogFormat,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addr: SocketAddr::from(([127, 0, 0, 1], 4000)),
+            data_dir: None,
+            engine: KVS_ENGINE.to_owned(),
+            replica_of: None,
+            server: ServerSection::default(),
+            storage: StorageSection::default(),
+            tls: TlsSection::default(),
+            auth: AuthSection::default(),
+            limits: LimitsSection::default(),
+            log: LogSection::default(),
+            cluster: ClusterSection::default(),
+        }
+    }
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        ServerSection {
+            mode: ServerMode::default(),
+            threads: None,
+            drain_timeout_secs: 10,
+            metrics_addr: None,
+        }
+    }
+}
+
+impl Default for StorageSection {
+    fn default() -> Self {
+        StorageSection {
+            durability: Durability::default(),
+            compaction_threshold_bytes: DEFAULT_COMPACTION_THRESHOLD,
+            slowlog_threshold_ms: None,
+            snapshot_interval_secs: None,
+        }
+    }
+}
+
+impl Default for LogSection {
+    fn default() -> Self {
+        LogSection {
+            level: "info".to_owned(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads and validates the configuration file at `path`
+    ///
+    /// Errors name the offending key, with its line for syntax and type errors.
+    pub fn load(path: impl AsRef<Path>) -> Result<ServerConfig> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let invalid =
+            |e: &dyn std::fmt::Display| KvsError::InvalidConfig(format!("{}: {e}", path.display()));
+        let config: ServerConfig = toml::from_str(&text).map_err(|e| invalid(&e))?;
+        match config.problem() {
+            Some(problem) => Err(invalid(&problem)),
+            None => Ok(config),
+        }
+    }
+
+    /// Checks the settings are consistent, naming the offending key when not
+    pub fn validate(&self) -> Result<()> {
+        match self.problem() {
+            Some(problem) => Err(KvsError::InvalidConfig(problem)),
+            None => Ok(()),
+        }
+    }
+
+    /// The first inconsistency found, starting with the key it is about
+    fn problem(&self) -> Option<String> {
+        if !ENGINES.contains(&self.engine.as_str()) {
+            return Some(format!(
+                "engine: unknown engine `{}`, expected one of {}",
+                self.engine,
+                ENGINES.join(", ")
+            ));
+        }
+        if self.engine != KVS_ENGINE && self.replica_of.is_some() {
+            return Some(format!(
+                "replica_of: only the {KVS_ENGINE} engine replicates, not {}",
+                self.engine
+            ));
+        }
+        if self.engine != KVS_ENGINE
+            && matches!(self.server.mode, ServerMode::Async | ServerMode::Http)
+        {
+            return Some(format!(
+                "server.mode: the {} engine is only served by thread pools",
+                self.engine
+            ));
+        }
+        if self.server.threads == Some(0) {
+            return Some("server.threads: must be at least 1".to_owned());
+        }
+        if self.storage.compaction_threshold_bytes == 0 {
+            return Some("storage.compaction_threshold_bytes: must be at least 1".to_owned());
+        }
+        if let Some(interval) = self.storage.snapshot_interval_secs {
+            if self.engine != "memory" {
+                return Some(format!(
+                    "storage.snapshot_interval_secs: only used by the memory engine, not {}",
+                    self.engine
+                ));
+            }
+            if interval == 0 {
+                return Some("storage.snapshot_interval_secs: must be at least 1".to_owned());
+            }
+        }
+        if self.limits.max_key_bytes == Some(0) {
+            return Some("limits.max_key_bytes: must be at least 1".to_owned());
+        }
+        if self.tls.cert.is_some() != self.tls.key.is_some() {
+            let missing = if self.tls.cert.is_some() {
+                "key"
+            } else {
+                "cert"
+            };
+            return Some(format!("tls.{missing}: required with the other one"));
+        }
+        if self.tls.client_ca.is_some() && self.tls.cert.is_none() {
+            return Some("tls.client_ca: requires tls.cert".to_owned());
+        }
+        if self.tls.primary_ca.is_some() && self.replica_of.is_none() {
+            return Some("tls.primary_ca: requires replica_of".to_owned());
+        }
+        let auth = &self.auth;
+        if auth.primary_user.is_some() != auth.primary_password.is_some() {
+            let missing = if auth.primary_user.is_some() {
+                "primary_password"
+            } else {
+                "primary_user"
+            };
+            return Some(format!("auth.{missing}: required with the other one"));
+        }
+        if auth.primary_token.is_some() && auth.primary_user.is_some() {
+            return Some("auth.primary_token: conflicts with auth.primary_user".to_owned());
+        }
+        if auth.primary_credentials().is_some()
+            && self.replica_of.is_none()
+            && self.cluster.id.is_none()
+        {
+            let key = if auth.primary_token.is_some() {
+                "primary_token"
+            } else {
+                "primary_user"
+            };
+            return Some(format!("auth.{key}: requires replica_of or cluster.id"));
+        }
+        if self.tls.cert.is_some() && self.server.mode == ServerMode::Http {
+            return Some("tls.cert: not supported in http mode".to_owned());
+        }
+        if let Some(id) = self.cluster.id {
+            if self.engine != KVS_ENGINE {
+                return Some(format!(
+                    "cluster.id: only the {KVS_ENGINE} engine joins a cluster, not {}",
+                    self.engine
+                ));
+            }
+            if self.replica_of.is_some() {
+                return Some("cluster.id: a cluster member cannot follow a primary".to_owned());
+            }
+            if matches!(self.server.mode, ServerMode::Async | ServerMode::Http) {
+                return Some("cluster.id: only served by thread pools".to_owned());
+            }
+            let mut ids = vec![id];
+            for peer in &self.cluster.peers {
+                if ids.contains(&peer.id) {
+                    return Some(format!("cluster.peers: node {} listed twice", peer.id));
+                }
+                ids.push(peer.id);
+            }
+        } else if !self.cluster.peers.is_empty() {
+            return Some("cluster.peers: requires cluster.id".to_owned());
+        }
+        None
+    }
+
+    /// Applies the settings of a running store, those of the `[storage]`
+    /// and `[limits]` tables
+    pub fn apply(&self, settings: &StoreSettings) {
+        settings.set_durability(self.storage.durability);
+        settings.set_compaction_threshold(self.storage.compaction_threshold_bytes);
+        settings.set_slow_threshold(self.storage.slowlog_threshold_ms.map(Duration::from_millis));
+        settings.set_max_key_bytes(self.limits.max_key_bytes);
+        settings.set_max_value_bytes(self.limits.max_value_bytes);
+    }
+
+    /// Returns the keys changed in `new` that only take effect after a restart
+    ///
+    /// The `[storage]` and `[limits]` tables are applied with `apply`, but for
+    /// the snapshot interval, and
+    /// `auth.acl` is reloaded in place, everything else is read at startup.
+    pub fn restart_required(&self, new: &ServerConfig) -> Vec<&'static str> {
+        let changes = [
+            ("addr", self.addr != new.addr),
+            ("data_dir", self.data_dir != new.data_dir),
+            ("engine", self.engine != new.engine),
+            ("replica_of", self.replica_of != new.replica_of),
+            ("server.mode", self.server.mode != new.server.mode),
+            ("server.threads", self.server.threads != new.server.threads),
+            (
+                "server.drain_timeout_secs",
+                self.server.drain_timeout_secs != new.server.drain_timeout_secs,
+            ),
+            (
+                "server.metrics_addr",
+                self.server.metrics_addr != new.server.metrics_addr,
+            ),
+            (
+                "storage.snapshot_interval_secs",
+                self.storage.snapshot_interval_secs != new.storage.snapshot_interval_secs,
+            ),
+            ("tls", self.tls != new.tls),
+            (
+                "auth.acl",
+                self.auth.acl.is_some() != new.auth.acl.is_some(),
+            ),
+            (
+                "auth.primary_user",
+                self.auth.primary_user != new.auth.primary_user,
+            ),
+            (
+                "auth.primary_password",
+                self.auth.primary_password != new.auth.primary_password,
+            ),
+            (
+                "auth.primary_token",
+                self.auth.primary_token != new.auth.primary_token,
+            ),
+            ("log", self.log != new.log),
+            ("cluster", self.cluster != new.cluster),
+        ];
+        changes
+            .into_iter()
+            .filter(|&(_, changed)| changed)
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
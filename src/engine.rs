@@ -0,0 +1,116 @@
+use crate::kv::sorted_generation_list;
+use crate::metrics::Metrics;
+use crate::settings::StoreSettings;
+use crate::{KvStore, KvsError, Result};
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Name of the log-structured engine of `KvStore`
+pub(crate) const KVS_ENGINE: &str = "kvs";
+
+/// Engines compiled into this build
+pub(crate) const ENGINES: &[&str] = &[
+    KVS_ENGINE,
+    "memory",
+    #[cfg(feature = "sled")]
+    "sled",
+];
+
+/// File in a data directory naming the engine that owns it
+const ENGINE_FILE: &str = "engine";
+
+pub(crate) fn engine_path(dir: &Path) -> PathBuf {
+    dir.join(ENGINE_FILE)
+}
+
+/// Returns the engine owning `dir`, `None` when no engine has written to it
+///
+/// Directories written before the marker existed hold `kvs` log files only.
+fn owner(dir: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(engine_path(dir)) {
+        Ok(name) => Ok(Some(name.trim().to_owned())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            match sorted_generation_list(dir)?.is_empty() {
+                true => Ok(None),
+                false => Ok(Some(KVS_ENGINE.to_owned())),
+            }
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Fails with `EngineMismatch` when `dir` belongs to another engine than `engine`
+pub(crate) fn check_engine(dir: &Path, engine: &str) -> Result<()> {
+    match owner(dir)? {
+        Some(found) if found != engine => Err(KvsError::EngineMismatch {
+            found,
+            requested: engine.to_owned(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Records `engine` as the owner of `dir` unless the marker is already there,
+/// call it with the directory locked after `check_engine`
+pub(crate) fn claim_directory(dir: &Path, engine: &str) -> Result<()> {
+    let path = engine_path(dir);
+    if !path.exists() {
+        std::fs::write(&path, format!("{engine}\n"))?;
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// A storage engine served by `KvsServer`
+///
+/// `KvStore` has every feature of the server. Other engines answer the
+/// basic operations, and fail with `Unsupported` on watches, replication
+/// and the slow log.
+pub trait KvsEngine: Send + 'static {
+    /// Name of the engine, recorded in the data directories it owns
+    const NAME: &'static str;
+
+    /// Sets the value of a key, overwriting any previous value
+    fn set(&mut self, key: String, value: String) -> Result<()>;
+
+    /// Gets the value of a key, `None` when it does not exist
+    fn get(&mut self, key: String) -> Result<Option<String>>;
+
+    /// Removes a key, fails with `KeyNotFound` when it does not exist
+    fn remove(&mut self, key: String) -> Result<()>;
+
+    /// Returns the entries whose key starts with `prefix`, in key order
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>>;
+
+    /// Writes everything to disk
+    fn sync(&mut self) -> Result<()>;
+
+    /// Returns the metrics of the engine
+    fn metrics(&self) -> Arc<Metrics>;
+
+    /// Returns the settings of the engine, which may be changed while it runs
+    fn settings(&self) -> StoreSettings;
+
+    /// Returns the log-structured store behind the engine, `None` for other engines
+    fn as_kv_store(&mut self) -> Option<&mut KvStore> {
+        None
+    }
+}
+
+/// Runs `f` on the `KvStore` behind the locked `store`, for the features
+/// only it has
+pub(crate) fn with_kv_store<E: KvsEngine, T>(
+    store: &Mutex<E>,
+    feature: &str,
+    f: impl FnOnce(&mut KvStore) -> Result<T>,
+) -> Result<T> {
+    let mut guard = store.lock().unwrap_or_else(PoisonError::into_inner);
+    match guard.as_kv_store() {
+        Some(store) => f(store),
+        None => Err(KvsError::Unsupported(format!(
+            "{feature} needs the {KVS_ENGINE} engine, not {}",
+            E::NAME
+        ))),
+    }
+}
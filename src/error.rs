@@ -1,4 +1,6 @@
 use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 /// domain error for the key-value store
 #[derive(Debug)]
@@ -8,10 +10,61 @@ pub enum KvsError {
     /// Represents a serialization/deserialization error
     /// using Serde for JSON handling
     SerdeError(serde_json::Error),
+    /// Represents an error reading or writing CSV data
+    CsvError(csv::Error),
+    /// Represents an error of the sled engine
+    #[cfg(feature = "sled")]
+    SledError(sled::Error),
     /// Represents a key not found error
     KeyNotFound,
     /// Represents an unexpected error
     UnexpectedCommandType,
+    /// Represents an error reported by the server
+    ServerError(String),
+    /// Represents a write sent to a read-only replica
+    ReadOnly,
+    /// Represents a request sent to a raft node that is not the leader,
+    /// with the leader it knows of
+    NotLeader(Option<u64>),
+    /// Represents a request sent to a server of a raft cluster that is not
+    /// the leader, with the address of the leader
+    Redirect(SocketAddr),
+    /// Represents a membership change proposed while another one is uncommitted
+    MembershipChangeInProgress,
+    /// Represents a malformed cluster topology
+    InvalidTopology(String),
+    /// Represents a thread pool that could not be started
+    ThreadPoolError(String),
+    /// Represents a server that did not answer in time
+    Timeout,
+    /// Represents a server refusing connections, usually because it is down
+    ConnectionRefused,
+    /// Represents a store directory already opened by another process
+    DirectoryLocked(PathBuf),
+    /// Represents connections still busy when a shutdown gave up waiting on them
+    DrainTimeout(usize),
+    /// Represents invalid TLS settings or a failed handshake
+    TlsError(String),
+    /// Represents a request made without valid credentials
+    Unauthenticated,
+    /// Represents a request the authenticated user is not allowed to make
+    Forbidden,
+    /// Represents a malformed access control list
+    InvalidAcl(String),
+    /// Represents a key or value larger than the store accepts
+    LimitExceeded(String),
+    /// Represents a malformed or inconsistent configuration file
+    InvalidConfig(String),
+    /// Represents a data directory opened with another engine than the one
+    /// that created it
+    EngineMismatch {
+        /// engine recorded in the directory
+        found: String,
+        /// engine asked to open it
+        requested: String,
+    },
+    /// Represents a feature the engine of the store does not have
+    Unsupported(String),
 }
 
 impl Display for KvsError {
@@ -19,8 +72,40 @@ impl Display for KvsError {
         match self {
             KvsError::IoError(e) => write!(f, "I/O error: {e}"),
             KvsError::SerdeError(e) => write!(f, "Serialization error: {e}"),
+            KvsError::CsvError(e) => write!(f, "CSV error: {e}"),
+            #[cfg(feature = "sled")]
+            KvsError::SledError(e) => write!(f, "Sled error: {e}"),
             KvsError::KeyNotFound => write!(f, "Key not found"),
             KvsError::UnexpectedCommandType => write!(f, "Unexpected command type"),
+            KvsError::ServerError(e) => write!(f, "Server error: {e}"),
+            KvsError::ReadOnly => write!(f, "Server is a read-only replica"),
+            KvsError::NotLeader(Some(leader)) => write!(f, "Not the leader, try node {leader}"),
+            KvsError::NotLeader(None) => write!(f, "Not the leader, no leader known"),
+            KvsError::Redirect(leader) => write!(f, "Not the leader, try {leader}"),
+            KvsError::MembershipChangeInProgress => {
+                write!(f, "Another membership change is in progress")
+            }
+            KvsError::InvalidTopology(e) => write!(f, "Invalid topology: {e}"),
+            KvsError::ThreadPoolError(e) => write!(f, "Thread pool error: {e}"),
+            KvsError::Timeout => write!(f, "Timed out waiting for the server"),
+            KvsError::ConnectionRefused => write!(f, "Connection refused"),
+            KvsError::DirectoryLocked(path) => {
+                write!(f, "{} is in use by another process", path.display())
+            }
+            KvsError::DrainTimeout(busy) => {
+                write!(f, "Shut down with {busy} connections still busy")
+            }
+            KvsError::TlsError(e) => write!(f, "TLS error: {e}"),
+            KvsError::Unauthenticated => write!(f, "Not authenticated"),
+            KvsError::Forbidden => write!(f, "Permission denied"),
+            KvsError::InvalidAcl(e) => write!(f, "Invalid ACL: {e}"),
+            KvsError::LimitExceeded(e) => write!(f, "Limit exceeded: {e}"),
+            KvsError::InvalidConfig(e) => write!(f, "Invalid configuration: {e}"),
+            KvsError::EngineMismatch { found, requested } => write!(
+                f,
+                "Data directory belongs to the {found} engine, refusing to open it with {requested}"
+            ),
+            KvsError::Unsupported(e) => write!(f, "Unsupported: {e}"),
         }
     }
 }
@@ -36,5 +121,24 @@ impl From<serde_json::Error> for KvsError {
     }
 }
 
+impl From<csv::Error> for KvsError {
+    fn from(error: csv::Error) -> Self {
+        KvsError::CsvError(error)
+    }
+}
+
+#[cfg(feature = "sled")]
+impl From<sled::Error> for KvsError {
+    fn from(error: sled::Error) -> Self {
+        KvsError::SledError(error)
+    }
+}
+
+impl From<rustls::Error> for KvsError {
+    fn from(error: rustls::Error) -> Self {
+        KvsError::TlsError(error.to_string())
+    }
+}
+
 /// Result type for the domain Error
 pub type Result<T> = std::result::Result<T, KvsError>;
@@ -0,0 +1,75 @@
+use crate::Result;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Deserializer;
+use std::io::{Read, Write};
+
+/// Format of a logical export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    /// one `{"key": .., "value": ..}` object per line
+    JsonLines,
+    /// a `key,value` header followed by one row per pair
+    Csv,
+}
+
+/// a single exported key/value pair
+#[derive(Deserialize, Serialize)]
+struct Record {
+    key: String,
+    value: String,
+}
+
+/// Streams key/value pairs to `writer` in the given format
+/// and returns the number of pairs written
+pub(crate) fn write_records<W: Write>(
+    writer: W,
+    format: DataFormat,
+    records: impl Iterator<Item = Result<(String, String)>>,
+) -> Result<u64> {
+    let mut count = 0;
+    match format {
+        DataFormat::JsonLines => {
+            let mut writer = writer;
+            for record in records {
+                let (key, value) = record?;
+                serde_json::to_writer(&mut writer, &Record { key, value })?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+        DataFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for record in records {
+                let (key, value) = record?;
+                writer.serialize(Record { key, value })?;
+                count += 1;
+            }
+            if count == 0 {
+                writer.write_record(["key", "value"])?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(count)
+}
+
+/// Parses key/value pairs from `reader` in the given format
+pub(crate) fn read_records<'a, R: Read + 'a>(
+    reader: R,
+    format: DataFormat,
+) -> Box<dyn Iterator<Item = Result<(String, String)>> + 'a> {
+    match format {
+        DataFormat::JsonLines => Box::new(
+            Deserializer::from_reader(reader)
+                .into_iter::<Record>()
+                .map(|record| Ok(record.map(|r| (r.key, r.value))?)),
+        ),
+        DataFormat::Csv => Box::new(
+            csv::Reader::from_reader(reader)
+                .into_deserialize::<Record>()
+                .map(|record| Ok(record.map(|r| (r.key, r.value))?)),
+        ),
+    }
+}
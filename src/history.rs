@@ -0,0 +1,129 @@
+use crate::Result;
+use crate::export::{DataFormat, write_records};
+use crate::kv::{LogEntry, history_path, log_path, sorted_generation_list};
+
+use serde_json::Deserializer;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::ops::Bound;
+use std::path::Path;
+
+/// A point in the history of a store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// state after the record with this sequence number
+    Sequence(u64),
+    /// state at this wall-clock time, in milliseconds since the unix epoch
+    Timestamp(u64),
+}
+
+impl AsOf {
+    fn includes(&self, entry: &LogEntry) -> bool {
+        match *self {
+            AsOf::Sequence(seq) => entry.seq() <= seq,
+            AsOf::Timestamp(ts) => entry.ts() <= ts,
+        }
+    }
+}
+
+/// A read-only view of a store as it was at a point in time
+///
+/// Records dropped by compaction are only visible if the store was
+/// opened with `retain_history`.
+pub struct Snapshot {
+    values: BTreeMap<String, String>,
+    seq: u64,
+}
+
+impl Snapshot {
+    /// Replays the retained and live generations in `path` up to `as_of`
+    pub fn open(path: impl AsRef<Path>, as_of: AsOf) -> Result<Snapshot> {
+        let entries = collect_entries(path.as_ref(), |entry| as_of.includes(entry))?;
+        let mut snapshot = Snapshot {
+            values: BTreeMap::new(),
+            seq: 0,
+        };
+        for entry in entries {
+            snapshot.seq = entry.seq();
+            match entry {
+                LogEntry::Set { key, value, .. } => {
+                    snapshot.values.insert(key, value);
+                }
+                LogEntry::Remove { key, .. } => {
+                    snapshot.values.remove(&key);
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Gets the value of a key at the snapshot point
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Sequence number of the last record included in the snapshot
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    /// Writes every key/value pair starting with `prefix` to `writer`
+    /// and returns the number of pairs exported
+    pub fn export<W: Write>(
+        &self,
+        writer: W,
+        format: DataFormat,
+        prefix: Option<&str>,
+    ) -> Result<u64> {
+        let prefix = prefix.unwrap_or_default();
+        let records = self
+            .values
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| Ok((key.clone(), value.clone())));
+        write_records(writer, format, records)
+    }
+}
+
+/// Collects the records of the retained and live generations in `path`
+/// matching `filter`, ordered by sequence number
+///
+/// Compaction copies records into newer generations, so records are
+/// replayed by sequence rather than by file, and copies are dropped.
+/// Records sharing a sequence number keep the order of the log.
+pub(crate) fn collect_entries(
+    path: &Path,
+    filter: impl Fn(&LogEntry) -> bool,
+) -> Result<Vec<LogEntry>> {
+    let mut entries = Vec::new();
+    let history = history_path(path);
+    if history.is_dir() {
+        read_entries(&history, &filter, &mut entries)?;
+    }
+    read_entries(path, &filter, &mut entries)?;
+    entries.sort_by_key(LogEntry::seq);
+    // a snapshot applied by a follower writes all its records with the
+    // sequence number of the primary, so copies are told apart by key too
+    let mut seen = HashSet::new();
+    entries.retain(|entry| entry.seq() == 0 || seen.insert((entry.seq(), entry.key().to_owned())));
+    Ok(entries)
+}
+
+/// Collects the records of every generation in `dir` matching `filter`
+fn read_entries(
+    dir: &Path,
+    filter: &impl Fn(&LogEntry) -> bool,
+    entries: &mut Vec<LogEntry>,
+) -> Result<()> {
+    for generation in sorted_generation_list(dir)? {
+        let reader = BufReader::new(File::open(log_path(dir, generation))?);
+        for entry in Deserializer::from_reader(reader).into_iter::<LogEntry>() {
+            let entry = entry?;
+            if filter(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,435 @@
+use crate::common::{Request, Response};
+use crate::replication::Follower;
+use crate::server::{execute, lock};
+use crate::{
+    AccessControl, Acl, ClientTls, Credentials, KvStore, KvsError, Result, ShutdownHandle,
+};
+
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::Read;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Server};
+use tracing::{debug, info, warn};
+
+/// How often idle workers check whether the server was shut down
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Largest request body accepted
+const MAX_BODY: u64 = 16 * 1024 * 1024;
+/// Entries of a scan page when the client does not ask for a limit
+const DEFAULT_LIMIT: usize = 100;
+/// Most entries of a scan page and operations of a batch
+const MAX_LIMIT: usize = 1000;
+
+/// A server exposing a KvStore as a JSON REST API over HTTP
+///
+/// - `GET /keys/{key}` returns `{"key", "value"}`, 404 when missing
+/// - `PUT /keys/{key}` with a `{"value"}` body sets the key
+/// - `DELETE /keys/{key}` removes the key, 404 when missing
+/// - `GET /keys?prefix=&limit=&cursor=` returns a page of `{"entries",
+///   "next_cursor"}` ordered by key, the next page starts after the cursor
+/// - `POST /batch` with `{"operations": [{"op": "get"|"set"|"remove",
+///   "key", "value"}]}` runs every operation in turn and returns a result
+///   for each, a failed operation does not stop the following ones
+///
+/// Errors are answered with `{"error": {"code", "message"}}`. When an ACL
+/// is set, clients authenticate with an `Authorization: Bearer <token>`
+/// header on every request.
+pub struct HttpServer {
+    store: Arc<Mutex<KvStore>>,
+    primary: Option<SocketAddr>,
+    shutdown: ShutdownHandle,
+    threads: usize,
+    primary_tls: Option<ClientTls>,
+    primary_credentials: Option<Credentials>,
+    access: AccessControl,
+}
+
+impl HttpServer {
+    /// Creates a primary server for the given store
+    pub fn new(store: KvStore) -> Self {
+        HttpServer {
+            store: Arc::new(Mutex::new(store)),
+            primary: None,
+            shutdown: ShutdownHandle::new(),
+            threads: 4,
+            primary_tls: None,
+            primary_credentials: None,
+            access: AccessControl::default(),
+        }
+    }
+
+    /// Creates a read-only replica applying the log of the server at `primary`
+    pub fn replica(store: KvStore, primary: SocketAddr) -> Self {
+        HttpServer {
+            primary: Some(primary),
+            ..HttpServer::new(store)
+        }
+    }
+
+    /// Sets the number of threads answering requests
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Encrypts the connection of a replica to its primary
+    pub fn with_primary_tls(mut self, tls: ClientTls) -> Self {
+        self.primary_tls = Some(tls);
+        self
+    }
+
+    /// Authenticates a replica to its primary
+    pub fn with_primary_credentials(mut self, credentials: Credentials) -> Self {
+        self.primary_credentials = Some(credentials);
+        self
+    }
+
+    /// Requires clients to authenticate and restricts them to what `acl`
+    /// grants them
+    pub fn with_acl(self, acl: Acl) -> Self {
+        self.access.reload(acl);
+        self
+    }
+
+    /// Returns a handle replacing the ACL while the server runs
+    pub fn access_control(&self) -> AccessControl {
+        self.access.clone()
+    }
+
+    /// Returns a handle shutting the server down
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Listens on `addr` and answers requests until the server is shut down
+    ///
+    /// Once shut down, the requests being answered are completed and the
+    /// store is synced to disk.
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        let server = Arc::new(Server::http(addr).map_err(std::io::Error::other)?);
+        info!(
+            addr = %server.server_addr(),
+            threads = self.threads,
+            read_only = self.primary.is_some(),
+            "http gateway listening"
+        );
+        let follower = self.primary.map(|primary| {
+            Follower::spawn(
+                Arc::clone(&self.store),
+                primary,
+                self.primary_tls.clone(),
+                self.primary_credentials.clone(),
+                self.shutdown.clone(),
+            )
+        });
+        let gateway = Arc::new(Gateway {
+            store: Arc::clone(&self.store),
+            access: self.access.clone(),
+            read_only: self.primary.is_some(),
+        });
+        let workers: Vec<_> = (0..self.threads)
+            .map(|_| {
+                let server = Arc::clone(&server);
+                let gateway = Arc::clone(&gateway);
+                let shutdown = self.shutdown.clone();
+                thread::spawn(move || {
+                    while !shutdown.is_shutdown() {
+                        match server.recv_timeout(POLL_INTERVAL) {
+                            Ok(Some(request)) => gateway.answer(request),
+                            Ok(None) => {}
+                            Err(e) => warn!(error = %e, "error receiving request"),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            let _ = worker.join();
+        }
+        if let Some(follower) = follower {
+            follower.stop();
+        }
+        info!("http gateway stopped");
+        lock(&self.store).sync()
+    }
+}
+
+/// What the workers of an HttpServer share
+struct Gateway {
+    store: Arc<Mutex<KvStore>>,
+    access: AccessControl,
+    read_only: bool,
+}
+
+/// An error answered to an HTTP client
+struct ApiError {
+    status: u16,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn body(&self) -> Value {
+        json!({ "error": { "code": self.code, "message": self.message } })
+    }
+}
+
+impl From<KvsError> for ApiError {
+    fn from(error: KvsError) -> Self {
+        let (status, code) = match &error {
+            KvsError::KeyNotFound => (404, "key_not_found"),
+            KvsError::Unauthenticated => (401, "unauthenticated"),
+            KvsError::Forbidden => (403, "forbidden"),
+            KvsError::ReadOnly => (409, "read_only"),
+            KvsError::LimitExceeded(_) => (413, "too_large"),
+            KvsError::SerdeError(_) => (400, "bad_request"),
+            _ => (500, "internal"),
+        };
+        ApiError::new(status, code, error.to_string())
+    }
+}
+
+/// A successful answer, `None` for 204 No Content
+type Reply = std::result::Result<Option<Value>, ApiError>;
+
+#[derive(Deserialize)]
+struct SetBody {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct BatchBody {
+    operations: Vec<Operation>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Operation {
+    Get { key: String },
+    Set { key: String, value: String },
+    Remove { key: String },
+}
+
+impl Gateway {
+    /// Answers a single request, errors are reported to the client
+    fn answer(&self, mut request: tiny_http::Request) {
+        let (status, body) = match self.route(&mut request) {
+            Ok(Some(body)) => (200, Some(body)),
+            Ok(None) => (204, None),
+            Err(e) => (e.status, Some(e.body())),
+        };
+        debug!(
+            peer = ?request.remote_addr(),
+            method = %request.method(),
+            url = request.url(),
+            status,
+            "answered request"
+        );
+        let responded = match body {
+            Some(body) => {
+                let content_type = Header::from_bytes("Content-Type", "application/json")
+                    .expect("static header is valid");
+                request.respond(
+                    tiny_http::Response::from_string(body.to_string())
+                        .with_status_code(status)
+                        .with_header(content_type),
+                )
+            }
+            None => request.respond(tiny_http::Response::empty(status)),
+        };
+        if let Err(e) = responded {
+            warn!(error = %e, "error answering request");
+        }
+    }
+
+    fn route(&self, request: &mut tiny_http::Request) -> Reply {
+        let user = self.authenticate(request)?;
+        let user = user.as_deref();
+        let url = request.url().to_owned();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        match (request.method(), path) {
+            (Method::Get, "/keys") => self.scan(user, query),
+            (_, "/keys") => Err(method_not_allowed()),
+            (Method::Post, "/batch") => {
+                let batch: BatchBody = read_json(request)?;
+                self.batch(user, batch.operations)
+            }
+            (_, "/batch") => Err(method_not_allowed()),
+            (method, path) => {
+                let Some(key) = path.strip_prefix("/keys/") else {
+                    return Err(ApiError::new(404, "not_found", format!("No route {path}")));
+                };
+                let key = percent_decode_str(key)
+                    .decode_utf8()
+                    .map_err(|_| ApiError::new(400, "bad_request", "Key is not valid UTF-8"))?
+                    .into_owned();
+                if key.is_empty() {
+                    return Err(ApiError::new(400, "bad_request", "Empty key"));
+                }
+                match method {
+                    Method::Get => match self.execute(user, Request::Get { key: key.clone() })? {
+                        Response::Ok(Some(value)) => {
+                            Ok(Some(json!({ "key": key, "value": value })))
+                        }
+                        _ => Err(KvsError::KeyNotFound.into()),
+                    },
+                    Method::Put => {
+                        let body: SetBody = read_json(request)?;
+                        let value = body.value;
+                        self.execute(user, Request::Set { key, value })?;
+                        Ok(None)
+                    }
+                    Method::Delete => {
+                        self.execute(user, Request::Remove { key })?;
+                        Ok(None)
+                    }
+                    _ => Err(method_not_allowed()),
+                }
+            }
+        }
+    }
+
+    /// Returns the user named by the bearer token of the request, if any
+    fn authenticate(
+        &self,
+        request: &tiny_http::Request,
+    ) -> std::result::Result<Option<String>, ApiError> {
+        let header = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"));
+        let Some(header) = header else {
+            return Ok(None);
+        };
+        let token = header
+            .value
+            .as_str()
+            .strip_prefix("Bearer ")
+            .ok_or_else(|| {
+                ApiError::new(401, "unauthenticated", "Only bearer tokens are supported")
+            })?;
+        Ok(self
+            .access
+            .authenticate(&Credentials::Token(token.trim().to_owned()))?)
+    }
+
+    fn scan(&self, user: Option<&str>, query: &str) -> Reply {
+        let mut prefix = String::new();
+        let mut cursor = None;
+        let mut limit = DEFAULT_LIMIT;
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &*name {
+                "prefix" => prefix = value.into_owned(),
+                "cursor" => cursor = Some(value.into_owned()),
+                "limit" => {
+                    limit = value
+                        .parse()
+                        .ok()
+                        .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                        .ok_or_else(|| {
+                            ApiError::new(
+                                400,
+                                "bad_request",
+                                format!("limit must be between 1 and {MAX_LIMIT}"),
+                            )
+                        })?
+                }
+                _ => {}
+            }
+        }
+        let request = Request::Scan { prefix };
+        let prefix = match self.access.authorize(user, request) {
+            Ok(Request::Scan { prefix }) => prefix,
+            Err(Response::Err(e)) => return Err(KvsError::from(e).into()),
+            _ => return Err(KvsError::UnexpectedCommandType.into()),
+        };
+        // one more entry than the page tells whether another page follows
+        let entries = lock(&self.store).scan_page(&prefix, cursor.as_deref(), limit + 1)?;
+        let mut page = entries
+            .into_iter()
+            .map(|(key, value)| json!({ "key": key, "value": value }))
+            .collect::<Vec<_>>();
+        let next_cursor = if page.len() > limit {
+            page.truncate(limit);
+            page.last().map(|entry| entry["key"].clone())
+        } else {
+            None
+        };
+        Ok(Some(json!({ "entries": page, "next_cursor": next_cursor })))
+    }
+
+    fn batch(&self, user: Option<&str>, operations: Vec<Operation>) -> Reply {
+        if operations.len() > MAX_LIMIT {
+            return Err(ApiError::new(
+                400,
+                "bad_request",
+                format!("a batch holds at most {MAX_LIMIT} operations"),
+            ));
+        }
+        let results: Vec<_> = operations
+            .into_iter()
+            .map(|operation| {
+                let request = match operation {
+                    Operation::Get { key } => Request::Get { key },
+                    Operation::Set { key, value } => Request::Set { key, value },
+                    Operation::Remove { key } => Request::Remove { key },
+                };
+                match self.execute(user, request) {
+                    Ok(Response::Ok(value)) => json!({ "value": value }),
+                    Ok(_) => ApiError::from(KvsError::UnexpectedCommandType).body(),
+                    Err(e) => e.body(),
+                }
+            })
+            .collect();
+        Ok(Some(json!({ "results": results })))
+    }
+
+    /// Runs `request` on the store on behalf of `user`
+    fn execute(
+        &self,
+        user: Option<&str>,
+        request: Request,
+    ) -> std::result::Result<Response, ApiError> {
+        let response = match self.access.authorize(user, request) {
+            Ok(request) => execute(&self.store, request, self.read_only),
+            Err(response) => response,
+        };
+        match response {
+            Response::Err(e) => Err(KvsError::from(e).into()),
+            response => Ok(response),
+        }
+    }
+}
+
+fn method_not_allowed() -> ApiError {
+    ApiError::new(405, "method_not_allowed", "Method not allowed")
+}
+
+/// Reads the body of `request` as JSON
+fn read_json<T: for<'de> Deserialize<'de>>(
+    request: &mut tiny_http::Request,
+) -> std::result::Result<T, ApiError> {
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_end(&mut body)
+        .map_err(KvsError::from)?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(ApiError::new(413, "too_large", "Request body too large"));
+    }
+    Ok(serde_json::from_slice(&body).map_err(KvsError::from)?)
+}
@@ -1,34 +1,105 @@
+use crate::engine::{KVS_ENGINE, KvsEngine, check_engine, claim_directory, engine_path};
+use crate::export::{DataFormat, read_records, write_records};
+use crate::history::collect_entries;
+use crate::metrics::{Metrics, Outcome, StoreOp};
+use crate::replication::Catchup;
+use crate::settings::{Durability, StoreSettings};
+use crate::slowlog::{SlowEntry, SlowLog};
+use crate::watch::{Sink, WATCH_BUFFER, WatchEvent, Watcher, notify};
 use crate::{KvsError, error::Result};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Deserializer;
 use std::ffi::OsStr;
 
-use std::io::SeekFrom;
-use std::ops::Range;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::{Bound, Range};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{
-    collections::{BTreeMap, HashMap},
-    fs::{File, OpenOptions},
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{File, OpenOptions, TryLockError},
     io::{BufReader, BufWriter, Read, Seek, Write},
     path::Path,
 };
+use tracing::{debug, info};
 
-#[derive(Deserialize, Serialize, Debug)]
-enum LogEntry {
-    Set { key: String, value: String },
-    Remove { key: String },
+/// a log record, stamped with its sequence number and the wall-clock
+/// time in milliseconds since the unix epoch
+///
+/// Records written before sequences existed deserialize with both set to 0.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) enum LogEntry {
+    Set {
+        key: String,
+        value: String,
+        #[serde(default)]
+        seq: u64,
+        #[serde(default)]
+        ts: u64,
+    },
+    Remove {
+        key: String,
+        #[serde(default)]
+        seq: u64,
+        #[serde(default)]
+        ts: u64,
+    },
 }
 
 impl LogEntry {
-    fn set(key: String, value: String) -> Self {
-        LogEntry::Set { key, value }
+    pub(crate) fn set(seq: u64, key: String, value: String) -> Self {
+        LogEntry::Set {
+            key,
+            value,
+            seq,
+            ts: now_millis(),
+        }
+    }
+    pub(crate) fn remove(seq: u64, key: String) -> Self {
+        LogEntry::Remove {
+            key,
+            seq,
+            ts: now_millis(),
+        }
+    }
+    pub(crate) fn key(&self) -> &str {
+        match self {
+            LogEntry::Set { key, .. } | LogEntry::Remove { key, .. } => key,
+        }
     }
-    fn remove(key: String) -> Self {
-        LogEntry::Remove { key }
+    pub(crate) fn seq(&self) -> u64 {
+        match self {
+            LogEntry::Set { seq, .. } | LogEntry::Remove { seq, .. } => *seq,
+        }
+    }
+    pub(crate) fn ts(&self) -> u64 {
+        match self {
+            LogEntry::Set { ts, .. } | LogEntry::Remove { ts, .. } => *ts,
+        }
     }
 }
 
+/// Options used when opening a KvStore
+#[derive(Debug, Clone, Default)]
+pub struct StoreOptions {
+    /// move generations made stale by compaction into the `history`
+    /// directory instead of deleting them, so older states stay readable
+    pub retain_history: bool,
+}
+
+/// An operation being timed for the metrics and the slow log
+struct OpTimer {
+    op: StoreOp,
+    key: Option<String>,
+    start: Instant,
+    timestamp: u64,
+    compactions: u64,
+}
+
 /// json serialised command position and length
 struct CommandPos {
     generation: u64,
@@ -57,6 +128,19 @@ pub struct KvStore {
     index: BTreeMap<String, CommandPos>,
     // number of stale commands that can be deleted during compaction
     uncompacted: u64,
+    // sequence number of the last written record
+    seq: u64,
+    // records up to this sequence number may no longer be in the log
+    compacted_through: u64,
+    options: StoreOptions,
+    watchers: Vec<Watcher>,
+    metrics: Arc<Metrics>,
+    slow_log: SlowLog,
+    settings: StoreSettings,
+    // compactions run since the store was opened
+    compactions: u64,
+    // held until the store is dropped so no other process opens the directory
+    _lock: File,
 }
 
 struct BufReaderWithPos<R: Read + Seek> {
@@ -122,85 +206,470 @@ impl<W: Write + Seek> Seek for BufWriterWithPos<W> {
     }
 }
 
-const COMPACTION_THRESHOLD: u64 = 1024 * 1024; // 1 MB
+const LOCK_FILE: &str = "kvs.lock";
+/// how long opening waits for the lock of a store going away
+pub(crate) const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+/// delay between two attempts at taking the lock
+pub(crate) const LOCK_RETRY: Duration = Duration::from_millis(10);
+
+/// File in the data directory holding what compaction drops from the log
+const META_FILE: &str = "meta";
+
+/// What the log alone no longer tells once compaction dropped records
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub(crate) struct Meta {
+    /// highest sequence number written to the store
+    pub(crate) seq: u64,
+    /// records up to this sequence number may no longer be in the log
+    #[serde(default)]
+    pub(crate) compacted_through: u64,
+}
 
 impl KvStore {
     /// Gets a value by key
     pub fn get(&mut self, key: String) -> Result<Option<String>> {
-        if let Some(cmd_pos) = self.index.get(&key) {
-            let reader = self
-                .readers
-                .get_mut(&cmd_pos.generation)
-                .expect("Cannot find log reader");
-            reader.seek(SeekFrom::Start(cmd_pos.pos))?;
-            let cmd_reader = reader.take(cmd_pos.len);
-            if let LogEntry::Set { value, .. } = serde_json::from_reader(cmd_reader)? {
-                return Ok(Some(value));
-            } else {
-                return Err(KvsError::UnexpectedCommandType);
-            }
-        }
-
-        Ok(None)
+        let timer = self.start(StoreOp::Get, Some(&key));
+        let result = match self.index.get(&key) {
+            Some(cmd_pos) => read_value(&mut self.readers, cmd_pos).map(Some),
+            None => Ok(None),
+        };
+        self.finish(timer, Outcome::of(&result, Option::is_some), false);
+        result
     }
 
     /// Sets a value for a key
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
-        let entry = LogEntry::set(key, value);
-        let pos = self.writer.pos;
+        let timer = self.start(StoreOp::Set, Some(&key));
+        let result = self.set_entry(key, value);
+        let synced = matches!(result, Ok(true));
+        let result = result.map(|_| ());
+        self.finish(timer, Outcome::of(&result, |_| true), synced);
+        result
+    }
+
+    /// Appends a set, returns whether it was synced to disk
+    fn set_entry(&mut self, key: String, value: String) -> Result<bool> {
+        self.settings.check_limits(&key, &value)?;
+        self.seq += 1;
+        self.append(LogEntry::set(self.seq, key, value))?;
+        let synced = self.commit()?;
+
+        if self.uncompacted > self.settings.compaction_threshold() {
+            self.compact()?;
+        }
 
-        serde_json::to_writer(&mut self.writer, &entry)?;
+        Ok(synced)
+    }
+
+    /// Flushes the records just appended, syncing them when the store
+    /// is durable, returns whether they were synced
+    fn commit(&mut self) -> Result<bool> {
         self.writer.flush()?;
-        if let LogEntry::Set { key, .. } = entry {
-            if let Some(old_entry) = self
-                .index
-                .insert(key, (self.current_generation, pos..self.writer.pos).into())
-            {
-                self.uncompacted += old_entry.len;
+        if self.settings.durability() == Durability::Sync {
+            self.writer.writer.get_ref().sync_data()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Removes a key and its associated value
+    /// Returns an error if the key doesn't exist
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        let timer = self.start(StoreOp::Remove, Some(&key));
+        let result = self.remove_entry(key);
+        let synced = matches!(result, Ok(true));
+        let result = result.map(|_| ());
+        self.finish(timer, Outcome::of(&result, |_| true), synced);
+        result
+    }
+
+    /// Appends a remove, returns whether it was synced to disk
+    fn remove_entry(&mut self, key: String) -> Result<bool> {
+        if !self.index.contains_key(&key) {
+            return Err(KvsError::KeyNotFound);
+        }
+        self.seq += 1;
+        self.append(LogEntry::remove(self.seq, key))?;
+        let synced = self.commit()?;
+
+        if self.uncompacted > self.settings.compaction_threshold() {
+            self.compact()?;
+        }
+
+        Ok(synced)
+    }
+
+    /// Returns the metrics of the store, shared with the servers in front of it
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Returns every live key/value pair starting with `prefix`, ordered by key
+    pub fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.scan_page(prefix, None, usize::MAX)
+    }
+
+    /// Returns up to `limit` live key/value pairs starting with `prefix`,
+    /// ordered by key and following the key `after` when given
+    ///
+    /// Only the returned values are read, so paging through a prefix costs
+    /// a read per key.
+    pub fn scan_page(
+        &mut self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let timer = self.start(StoreOp::Scan, Some(prefix));
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after),
+            _ => Bound::Included(prefix),
+        };
+        let readers = &mut self.readers;
+        let result = self
+            .index
+            .range::<str, _>((start, Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .take(limit)
+            .map(|(key, cmd_pos)| Ok((key.clone(), read_value(readers, cmd_pos)?)))
+            .collect();
+        self.finish(timer, Outcome::of(&result, |_| true), false);
+        result
+    }
+
+    /// Records operations taking at least `threshold` in the slow log,
+    /// `None` turns the slow log off
+    pub fn set_slow_threshold(&mut self, threshold: Option<Duration>) {
+        self.settings.set_slow_threshold(threshold);
+    }
+
+    /// Returns the settings of the store, which may be changed while it runs
+    pub fn settings(&self) -> StoreSettings {
+        self.settings.clone()
+    }
+
+    /// Returns up to `count` of the slowest recent operations, newest first
+    pub fn slow_log(&self, count: usize) -> Vec<SlowEntry> {
+        self.slow_log.entries(count)
+    }
+
+    /// Empties the slow log
+    pub fn reset_slow_log(&mut self) {
+        self.slow_log.reset();
+    }
+
+    /// Writes every live key/value pair starting with `prefix` to `writer`
+    /// and returns the number of pairs exported
+    pub fn export<W: Write>(
+        &mut self,
+        writer: W,
+        format: DataFormat,
+        prefix: Option<&str>,
+    ) -> Result<u64> {
+        let prefix = prefix.unwrap_or_default();
+        let readers = &mut self.readers;
+        let records = self
+            .index
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, cmd_pos)| Ok((key.clone(), read_value(readers, cmd_pos)?)));
+        write_records(writer, format, records)
+    }
+
+    /// Bulk-loads key/value pairs from `reader` and returns the number of pairs imported
+    ///
+    /// Every record is parsed and checked against the limits before any is
+    /// written, so a malformed input leaves the store untouched. Records are
+    /// then appended without flushing one by one, the log is flushed and
+    /// synced once at the end and watchers are only notified after that.
+    pub fn import<R: Read>(&mut self, reader: R, format: DataFormat) -> Result<u64> {
+        let records = read_records(reader, format).collect::<Result<Vec<_>>>()?;
+        for (key, value) in &records {
+            self.settings.check_limits(key, value)?;
+        }
+        let mut entries = Vec::with_capacity(records.len());
+        for (key, value) in records {
+            self.seq += 1;
+            let entry = LogEntry::set(self.seq, key, value);
+            self.write(&entry)?;
+            entries.push(entry);
+        }
+        self.writer.flush()?;
+        self.writer.writer.get_ref().sync_data()?;
+        for entry in &entries {
+            notify(&mut self.watchers, entry);
+        }
+        let count = entries.len() as u64;
+
+        if self.uncompacted > self.settings.compaction_threshold() {
+            self.compact()?;
+        }
+        Ok(count)
+    }
+
+    /// Writes a consistent, openable copy of the store into `dest`
+    ///
+    /// Sealed generations are hard-linked when possible, the active one is
+    /// copied up to the current write position.
+    pub fn checkpoint(&mut self, dest: impl AsRef<Path>) -> Result<()> {
+        let dest = dest.as_ref();
+        prepare_destination(dest)?;
+        self.writer.flush()?;
+
+        let mut generations: Vec<u64> = self.readers.keys().cloned().collect();
+        generations.sort_unstable();
+        for generation in generations {
+            let src = log_path(&self.path, generation);
+            let dst = log_path(dest, generation);
+            if generation == self.current_generation {
+                let mut active = File::open(&src)?.take(self.writer.pos);
+                let mut copy = File::create(&dst)?;
+                std::io::copy(&mut active, &mut copy)?;
+                copy.sync_all()?;
+            } else if let Err(e) = std::fs::hard_link(&src, &dst) {
+                // the backup may be on another filesystem, or on one without links
+                match e.kind() {
+                    ErrorKind::CrossesDevices | ErrorKind::Unsupported => {
+                        std::fs::copy(&src, &dst)?;
+                    }
+                    _ => return Err(e.into()),
+                }
             }
         }
+        std::fs::copy(engine_path(&self.path), engine_path(dest))?;
+        copy_meta(&self.path, dest)?;
+        Ok(())
+    }
+
+    /// Copies the log files of a checkpoint in `backup` into `dest`
+    pub fn restore(backup: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<()> {
+        let (backup, dest) = (backup.as_ref(), dest.as_ref());
+        prepare_destination(dest)?;
+        check_engine(backup, KVS_ENGINE)?;
+        for generation in sorted_generation_list(backup)? {
+            std::fs::copy(log_path(backup, generation), log_path(dest, generation))?;
+        }
+        copy_meta(backup, dest)?;
+        claim_directory(dest, KVS_ENGINE)?;
+        Ok(())
+    }
 
-        if self.uncompacted > COMPACTION_THRESHOLD {
+    /// Appends a record replicated from a primary, keeping its sequence number
+    ///
+    /// Records older than the last one applied are ignored. Records of an
+    /// applied snapshot share a sequence number, so ones equal to it are kept.
+    pub(crate) fn apply(&mut self, entry: LogEntry) -> Result<()> {
+        if entry.seq() < self.seq {
+            return Ok(());
+        }
+        self.append(entry)?;
+        self.writer.flush()?;
+
+        if self.uncompacted > self.settings.compaction_threshold() {
             self.compact()?;
         }
+        Ok(())
+    }
 
+    /// Replaces the content of the store with a snapshot of a primary
+    /// taken at sequence number `seq`
+    pub(crate) fn apply_snapshot(
+        &mut self,
+        seq: u64,
+        entries: Vec<(String, String)>,
+    ) -> Result<()> {
+        let keep: HashSet<&str> = entries.iter().map(|(key, _)| key.as_str()).collect();
+        let stale: Vec<String> = self
+            .index
+            .keys()
+            .filter(|key| !keep.contains(key.as_str()))
+            .cloned()
+            .collect();
+        for key in stale {
+            self.append(LogEntry::remove(seq, key))?;
+        }
+        for (key, value) in entries {
+            self.append(LogEntry::set(seq, key, value))?;
+        }
+        self.seq = seq;
+        self.writer.flush()?;
+        self.writer.writer.get_ref().sync_data()?;
+        // the records of the primary before the snapshot never reached this log
+        self.compacted_through = seq;
+        self.write_meta()?;
+
+        if self.uncompacted > self.settings.compaction_threshold() {
+            self.compact()?;
+        }
         Ok(())
     }
 
-    /// Removes a key and its associated value
-    /// Returns an error if the key doesn't exist
-    pub fn remove(&mut self, key: String) -> Result<()> {
-        if self.index.contains_key(&key) {
-            let entry = LogEntry::remove(key);
-            serde_json::to_writer(&mut self.writer, &entry)?;
-            self.writer.flush()?;
-            if let LogEntry::Remove { key, .. } = entry {
-                let old_cmd = self.index.remove(&key).expect("key not found");
-                self.uncompacted += old_cmd.len;
+    /// Compacts the log and returns every live key/value pair, read in a
+    /// single pass over the compacted generation
+    pub(crate) fn compacted_entries(&mut self) -> Result<Vec<(String, String)>> {
+        let generation = self.compact()?;
+        let reader = BufReader::new(File::open(log_path(&self.path, generation))?);
+        let mut entries = Vec::with_capacity(self.index.len());
+        for entry in Deserializer::from_reader(reader).into_iter::<LogEntry>() {
+            if let LogEntry::Set { key, value, .. } = entry? {
+                entries.push((key, value));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Every live key/value pair, read straight from the index so that
+    /// it does not count as a scan
+    fn entries(&mut self) -> Result<Vec<(String, String)>> {
+        let readers = &mut self.readers;
+        self.index
+            .iter()
+            .map(|(key, cmd_pos)| Ok((key.clone(), read_value(readers, cmd_pos)?)))
+            .collect()
+    }
+
+    /// Subscribes a follower to every record from sequence number `from` onwards
+    ///
+    /// Records still in the log are replayed, a follower asking for records
+    /// dropped by compaction gets a snapshot of the store instead. A follower
+    /// falling more than `WATCH_BUFFER` records behind is disconnected, it
+    /// subscribes again from where it stopped.
+    pub(crate) fn subscribe(&mut self, from: u64) -> Result<(Catchup, Receiver<LogEntry>)> {
+        let catchup = if from <= self.compacted_through {
+            Catchup::Snapshot {
+                seq: self.seq,
+                entries: self.entries()?,
             }
-            Ok(())
         } else {
-            Err(KvsError::KeyNotFound)
+            Catchup::Records(collect_entries(&self.path, |entry| entry.seq() >= from)?)
+        };
+        let (sender, receiver) = mpsc::sync_channel(WATCH_BUFFER);
+        self.watchers.push(Watcher {
+            prefix: String::new(),
+            sink: Sink::Records(sender),
+        });
+        Ok((catchup, receiver))
+    }
+
+    /// Subscribes to the changes of keys starting with `prefix`
+    ///
+    /// Events are sent once the change is written to the log.
+    /// The subscription ends when the receiver is dropped, or once the
+    /// receiver falls more than 1024 events behind.
+    pub fn watch(&mut self, prefix: impl Into<String>) -> Receiver<WatchEvent> {
+        let (sender, receiver) = mpsc::sync_channel(WATCH_BUFFER);
+        self.watchers.push(Watcher {
+            prefix: prefix.into(),
+            sink: Sink::Events(sender),
+        });
+        receiver
+    }
+
+    /// Subscribes to the changes of keys starting with `prefix`, replaying
+    /// the changes from sequence number `from` onwards out of the log first
+    ///
+    /// Changes dropped by compaction are only replayed if the store
+    /// was opened with `retain_history`. The replayed changes do not count
+    /// towards how far the receiver may fall behind.
+    pub fn watch_from(
+        &mut self,
+        prefix: impl Into<String>,
+        from: u64,
+    ) -> Result<Receiver<WatchEvent>> {
+        let prefix = prefix.into();
+        let past = collect_entries(&self.path, |entry| {
+            entry.seq() >= from && entry.key().starts_with(&prefix)
+        })?;
+        let (sender, receiver) = mpsc::sync_channel(past.len() + WATCH_BUFFER);
+        for entry in &past {
+            // the receiver is still in scope and the channel has room,
+            // sending cannot fail
+            let _ = sender.try_send(entry.into());
         }
+        self.watchers.push(Watcher {
+            prefix,
+            sink: Sink::Events(sender),
+        });
+        Ok(receiver)
+    }
+
+    /// Ends every subscription, receivers get what was already sent
+    /// and then see the channel closed
+    pub(crate) fn close_subscriptions(&mut self) {
+        self.watchers.clear();
+    }
+
+    /// Sequence number of the last record written to the store
+    pub fn sequence(&self) -> u64 {
+        self.seq
     }
 
     /// Opens a KvStore at a given directory path
     pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::open_with_options(path, StoreOptions::default())
+    }
+
+    /// Opens a KvStore at a given directory path with the given options
+    pub fn open_with_options(path: impl Into<PathBuf>, options: StoreOptions) -> Result<KvStore> {
         let path = path.into();
         std::fs::create_dir_all(&path)?;
+        check_engine(&path, KVS_ENGINE)?;
+        let lock = lock_directory(&path)?;
+        claim_directory(&path, KVS_ENGINE)?;
 
         let mut readers = HashMap::new();
         let mut index = BTreeMap::new();
         let generation_list = sorted_generation_list(&path)?;
         let mut uncompacted = 0;
+        let mut seq = 0;
+        let mut records = 0;
 
+        let start = Instant::now();
         for &generation in &generation_list {
             let mut reader = BufReaderWithPos::new(File::open(log_path(&path, generation))?)?;
-            uncompacted += load(generation, &mut reader, &mut index)?;
+            let loaded = records;
+            uncompacted += load(generation, &mut reader, &mut index, &mut seq, &mut records)?;
+            debug!(
+                generation,
+                records = records - loaded,
+                "replayed generation"
+            );
             readers.insert(generation, reader);
         }
+        let compacted_through = match read_meta(&path)? {
+            Some(meta) => {
+                // compaction may have dropped the last records written
+                seq = seq.max(meta.seq);
+                meta.compacted_through
+            }
+            None => {
+                // a log written before the meta file existed may have been
+                // compacted, followers get a snapshot rather than a partial log
+                let compacted_through = if generation_list.is_empty() { 0 } else { seq };
+                write_meta(
+                    &path,
+                    &Meta {
+                        seq,
+                        compacted_through,
+                    },
+                )?;
+                compacted_through
+            }
+        };
         let current_generation = generation_list.last().unwrap_or(&0) + 1;
         let writer = new_log_file(&path, current_generation, &mut readers)?;
+        let metrics = Arc::new(Metrics::default());
+        metrics.set_generations(readers.len());
+        info!(
+            path = %path.display(),
+            generations = generation_list.len(),
+            records,
+            keys = index.len(),
+            seq,
+            stale_bytes = uncompacted,
+            elapsed = ?start.elapsed(),
+            "opened store"
+        );
 
         Ok(KvStore {
             path,
@@ -209,12 +678,73 @@ impl KvStore {
             current_generation,
             index,
             uncompacted,
+            seq,
+            compacted_through,
+            options,
+            watchers: Vec::new(),
+            metrics,
+            slow_log: SlowLog::default(),
+            settings: StoreSettings::default(),
+            compactions: 0,
+            _lock: lock,
         })
     }
 
-    /// Compacts the log by removing redundant entries
-    fn compact(&mut self) -> Result<()> {
+    /// Flushes the active log and waits until it reaches the disk
+    pub fn sync(&mut self) -> Result<()> {
+        let timer = self.start(StoreOp::Sync, None);
+        let result = self
+            .writer
+            .flush()
+            .and_then(|_| self.writer.writer.get_ref().sync_data());
+        let result = result.map_err(KvsError::from);
+        self.finish(timer, Outcome::of(&result, |_| true), true);
+        result
+    }
+
+    /// Starts timing an operation on `key`
+    fn start(&self, op: StoreOp, key: Option<&str>) -> OpTimer {
+        OpTimer {
+            op,
+            // keys are only copied when a slow operation may need them
+            key: key
+                .filter(|_| self.settings.slow_threshold().is_some())
+                .map(str::to_owned),
+            start: Instant::now(),
+            timestamp: now_millis(),
+            compactions: self.compactions,
+        }
+    }
+
+    /// Reports a finished operation to the metrics, and to the slow log
+    /// when it was slow
+    fn finish(&mut self, timer: OpTimer, outcome: Outcome, synced: bool) {
+        let duration = timer.start.elapsed();
+        self.metrics.observe(timer.op, outcome, duration);
+        self.slow_log.record(
+            SlowEntry {
+                id: 0,
+                timestamp: timer.timestamp,
+                op: timer.op,
+                key: timer.key,
+                duration,
+                compacted: self.compactions != timer.compactions,
+                synced,
+            },
+            self.settings.slow_threshold(),
+        );
+    }
+
+    /// Compacts the log by removing redundant entries,
+    /// returns the generation holding the live ones
+    fn compact(&mut self) -> Result<u64> {
+        let start = Instant::now();
         let compaction_generation = self.current_generation + 1;
+        info!(
+            generation = compaction_generation,
+            stale_bytes = self.uncompacted,
+            "compaction started"
+        );
         self.current_generation += 2;
         self.writer = self.new_log_file(self.current_generation)?;
 
@@ -234,6 +764,12 @@ impl KvStore {
             new_pos += len;
         }
         compaction_writer.flush()?;
+        compaction_writer.writer.get_ref().sync_data()?;
+        if !self.options.retain_history {
+            self.compacted_through = self.seq;
+        }
+        // the records about to be dropped may hold the highest sequence number
+        self.write_meta()?;
 
         let stale_gens: Vec<_> = self
             .readers
@@ -241,17 +777,121 @@ impl KvStore {
             .filter(|&&generation| generation < compaction_generation)
             .cloned()
             .collect();
+        if self.options.retain_history {
+            std::fs::create_dir_all(history_path(&self.path))?;
+        }
+        // measured up front, failing to measure must not leave the removal half done
+        let reclaimed: u64 = stale_gens
+            .iter()
+            .map(|&stale_gen| {
+                std::fs::metadata(log_path(&self.path, stale_gen)).map_or(0, |meta| meta.len())
+            })
+            .sum();
         for stale_gen in stale_gens {
             self.readers.remove(&stale_gen);
-            std::fs::remove_file(log_path(&self.path, stale_gen))?;
+            if self.options.retain_history {
+                std::fs::rename(
+                    log_path(&self.path, stale_gen),
+                    log_path(&history_path(&self.path), stale_gen),
+                )?;
+            } else {
+                std::fs::remove_file(log_path(&self.path, stale_gen))?;
+            }
         }
         self.uncompacted = 0;
+        self.compactions += 1;
+        self.metrics.set_generations(self.readers.len());
+        let reclaimed = reclaimed.saturating_sub(new_pos);
+        self.metrics.compacted(start.elapsed(), reclaimed);
+        info!(
+            generation = compaction_generation,
+            written_bytes = new_pos,
+            reclaimed_bytes = reclaimed,
+            elapsed = ?start.elapsed(),
+            "compaction finished"
+        );
+        Ok(compaction_generation)
+    }
+
+    /// Appends `entry` to the active log without flushing it, updates the
+    /// index and notifies the watchers
+    fn append(&mut self, entry: LogEntry) -> Result<()> {
+        self.write(&entry)?;
+        notify(&mut self.watchers, &entry);
         Ok(())
     }
 
+    /// Appends `entry` to the active log without flushing it and updates the index
+    fn write(&mut self, entry: &LogEntry) -> Result<()> {
+        let pos = self.writer.pos;
+        serde_json::to_writer(&mut self.writer, entry)?;
+        self.seq = self.seq.max(entry.seq());
+        let old_entry = match entry {
+            LogEntry::Set { key, .. } => self.index.insert(
+                key.clone(),
+                (self.current_generation, pos..self.writer.pos).into(),
+            ),
+            LogEntry::Remove { key, .. } => self.index.remove(key),
+        };
+        if let Some(old_entry) = old_entry {
+            self.uncompacted += old_entry.len;
+        }
+        self.metrics.wrote(self.writer.pos - pos);
+        Ok(())
+    }
+
+    /// Records the sequence numbers compaction may drop from the log
+    fn write_meta(&self) -> Result<()> {
+        write_meta(
+            &self.path,
+            &Meta {
+                seq: self.seq,
+                compacted_through: self.compacted_through,
+            },
+        )
+    }
+
     /// Create a new log file
     fn new_log_file(&mut self, generation: u64) -> Result<BufWriterWithPos<File>> {
-        new_log_file(&self.path, generation, &mut self.readers)
+        let writer = new_log_file(&self.path, generation, &mut self.readers)?;
+        self.metrics.set_generations(self.readers.len());
+        Ok(writer)
+    }
+}
+
+impl KvsEngine for KvStore {
+    const NAME: &'static str = KVS_ENGINE;
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KvStore::set(self, key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        KvStore::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        KvStore::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        KvStore::scan(self, prefix)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        KvStore::sync(self)
+    }
+
+    fn metrics(&self) -> Arc<Metrics> {
+        KvStore::metrics(self)
+    }
+
+    fn settings(&self) -> StoreSettings {
+        KvStore::settings(self)
+    }
+
+    fn as_kv_store(&mut self) -> Option<&mut KvStore> {
+        Some(self)
     }
 }
 
@@ -268,12 +908,119 @@ fn new_log_file(
     Ok(writer)
 }
 
-fn log_path(dir: &Path, generation: u64) -> PathBuf {
+/// Creates `dest` if needed and makes sure it holds no log file
+fn prepare_destination(dest: &Path) -> Result<()> {
+    std::fs::create_dir_all(dest)?;
+    if !sorted_generation_list(dest)?.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already holds log files", dest.display()),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Reads the value of the `Set` command stored at `cmd_pos`
+fn read_value(
+    readers: &mut HashMap<u64, BufReaderWithPos<File>>,
+    cmd_pos: &CommandPos,
+) -> Result<String> {
+    let reader = readers
+        .get_mut(&cmd_pos.generation)
+        .expect("Cannot find log reader");
+    reader.seek(SeekFrom::Start(cmd_pos.pos))?;
+    let cmd_reader = reader.take(cmd_pos.len);
+    if let LogEntry::Set { value, .. } = serde_json::from_reader(cmd_reader)? {
+        Ok(value)
+    } else {
+        Err(KvsError::UnexpectedCommandType)
+    }
+}
+
+/// Takes the exclusive lock of the store in `dir`, held until the file is closed
+///
+/// A store just dropped may still hold the lock for a moment through a
+/// process being spawned by another thread, which shares the descriptor
+/// until it runs, so the lock is waited on up to `LOCK_TIMEOUT`.
+pub(crate) fn lock_directory(dir: &Path) -> Result<File> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(dir))?;
+    let deadline = Instant::now() + LOCK_TIMEOUT;
+    loop {
+        match lock.try_lock() {
+            Ok(()) => return Ok(lock),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(LOCK_RETRY);
+            }
+            Err(TryLockError::WouldBlock) => return Err(KvsError::DirectoryLocked(dir.to_owned())),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
+
+pub(crate) fn lock_path(dir: &Path) -> PathBuf {
+    dir.join(LOCK_FILE)
+}
+
+pub(crate) fn meta_path(dir: &Path) -> PathBuf {
+    dir.join(META_FILE)
+}
+
+/// Reads the meta file of the store in `dir`, `None` when there is none
+pub(crate) fn read_meta(dir: &Path) -> Result<Option<Meta>> {
+    match std::fs::read(meta_path(dir)) {
+        Ok(buf) => Ok(Some(serde_json::from_slice(&buf)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replaces the meta file of the store in `dir`
+///
+/// The file is written aside and renamed over the previous one, so a
+/// crash leaves either of them whole.
+pub(crate) fn write_meta(dir: &Path, meta: &Meta) -> Result<()> {
+    let path = meta_path(dir);
+    let temp = path.with_extension("tmp");
+    let mut file = File::create(&temp)?;
+    serde_json::to_writer(&mut file, meta)?;
+    file.sync_all()?;
+    std::fs::rename(&temp, &path)?;
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Copies the meta file of `src`, if any, into `dest`
+fn copy_meta(src: &Path, dest: &Path) -> Result<()> {
+    match std::fs::copy(meta_path(src), meta_path(dest)) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) fn log_path(dir: &Path, generation: u64) -> PathBuf {
     dir.join(format!("{generation}.log"))
 }
 
+/// directory holding the generations retained by compaction
+pub(crate) fn history_path(dir: &Path) -> PathBuf {
+    dir.join("history")
+}
+
+/// milliseconds since the unix epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
 /// generate a sorted list of generations from the log files in the given path
-fn sorted_generation_list(path: &Path) -> Result<Vec<u64>> {
+pub(crate) fn sorted_generation_list(path: &Path) -> Result<Vec<u64>> {
     let mut generations: Vec<u64> = std::fs::read_dir(path)?
         .flat_map(|res| -> Result<_> { Ok(res?.path()) })
         .filter(|path| path.is_file() && path.extension() == Some("log".as_ref()))
@@ -295,19 +1042,24 @@ fn load(
     generation: u64,
     reader: &mut BufReaderWithPos<File>,
     index: &mut BTreeMap<String, CommandPos>,
+    seq: &mut u64,
+    records: &mut u64,
 ) -> Result<u64> {
     let mut pos = reader.seek(SeekFrom::Start(0))?;
     let mut stream = Deserializer::from_reader(reader).into_iter::<LogEntry>();
     let mut uncompacted = 0;
     while let Some(entry) = stream.next() {
         let new_pos = stream.byte_offset() as u64;
-        match entry? {
+        let entry = entry?;
+        *seq = (*seq).max(entry.seq());
+        *records += 1;
+        match entry {
             LogEntry::Set { key, .. } => {
                 if let Some(old_entry) = index.insert(key, (generation, pos..new_pos).into()) {
                     uncompacted += old_entry.len;
                 }
             }
-            LogEntry::Remove { key } => {
+            LogEntry::Remove { key, .. } => {
                 if let Some(old_entry) = index.remove(&key) {
                     uncompacted += old_entry.len;
                 }
@@ -1,6 +1,63 @@
 #![deny(missing_docs)]
 //! A simple key-value store.
+pub use acl::{AccessControl, Acl, Credentials, Grant, Permission, User, hash_secret};
+pub use async_client::{AsyncKvsClient, AsyncWatchStream};
+pub use async_server::AsyncKvsServer;
+pub use client::{ClientOptions, KvsClient, Pipeline, WatchStream};
+pub use client_pool::KvsClientPool;
+pub use config::{
+    AuthSection, ClusterSection, LimitsSection, LogSection, PeerSection, ServerConfig, ServerMode,
+    ServerSection, StorageSection, TlsSection,
+};
+pub use engine::KvsEngine;
 pub use error::{KvsError, Result};
-pub use kv::KvStore;
+pub use export::DataFormat;
+pub use history::{AsOf, Snapshot};
+pub use http::HttpServer;
+pub use kv::{KvStore, StoreOptions};
+pub use logging::{LogFormat, init_logging};
+pub use memory_engine::MemoryKvsEngine;
+pub use metrics::{Metrics, MetricsServer, StoreOp};
+pub use server::KvsServer;
+pub use settings::{Durability, StoreSettings};
+pub use shard::{HashRing, Shard, ShardedKvsClient, Topology, rebalance, rebalance_with};
+pub use shell::{Shell, ShellTarget};
+pub use shutdown::ShutdownHandle;
+#[cfg(feature = "sled")]
+pub use sled_engine::SledKvsEngine;
+pub use slowlog::SlowEntry;
+pub use thread_pool::{NaiveThreadPool, RayonThreadPool, SharedQueueThreadPool, ThreadPool};
+pub use tls::{ClientTls, ServerTls};
+pub use verify::{CorruptRange, DanglingEntry, RepairReport, VerifyReport, repair, verify};
+pub use watch::WatchEvent;
+mod acl;
+mod async_client;
+mod async_server;
+mod client;
+mod client_pool;
+mod cluster;
+mod common;
+mod config;
+mod engine;
 mod error;
+mod export;
+mod history;
+mod http;
 mod kv;
+mod logging;
+mod memory_engine;
+mod metrics;
+pub mod raft;
+mod replication;
+mod server;
+mod settings;
+mod shard;
+mod shell;
+mod shutdown;
+#[cfg(feature = "sled")]
+mod sled_engine;
+mod slowlog;
+mod thread_pool;
+mod tls;
+mod verify;
+mod watch;
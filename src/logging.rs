@@ -0,0 +1,37 @@
+use crate::Result;
+
+use serde::Deserialize;
+use std::io::{Error, ErrorKind};
+use tracing_subscriber::EnvFilter;
+
+/// How log events are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    /// human readable lines
+    Text,
+    /// one JSON object per line, with the fields of the event
+    Json,
+}
+
+/// Writes log events to stderr for the rest of the process
+///
+/// `filter` is a level such as `info`, or a list of directives such as
+/// `info,networked_kv_store::kv=debug`. `RUST_LOG` takes precedence when set.
+pub fn init_logging(filter: &str, format: LogFormat) -> Result<()> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(env) if !env.is_empty() => EnvFilter::try_new(env),
+        _ => EnvFilter::try_new(filter),
+    }
+    .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    let installed = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    };
+    installed.map_err(|e| Error::other(e.to_string()))?;
+    Ok(())
+}
@@ -0,0 +1,257 @@
+use crate::engine::{check_engine, claim_directory};
+use crate::export::{DataFormat, read_records, write_records};
+use crate::kv::lock_directory;
+use crate::metrics::{Metrics, Outcome, StoreOp};
+use crate::settings::StoreSettings;
+use crate::{KvsEngine, KvsError, Result};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// File in the data directory holding the last snapshot
+const SNAPSHOT_FILE: &str = "snapshot.jsonl";
+
+/// A storage engine keeping every key in memory, for tests and caches
+///
+/// Created with `new`, nothing touches the disk and everything is lost
+/// when the engine is dropped. Opened on a directory, the entries are
+/// snapshotted to it periodically, on `sync` and when the engine is dropped,
+/// and loaded back on open. The size limits of the settings apply, the
+/// other settings do not.
+pub struct MemoryKvsEngine {
+    shared: Arc<Shared>,
+    metrics: Arc<Metrics>,
+    settings: StoreSettings,
+    // held until the engine is dropped so no other process snapshots to the directory
+    _lock: Option<File>,
+    // dropped with the engine, which stops the snapshot thread
+    _snapshots: Option<Sender<()>>,
+}
+
+struct Shared {
+    entries: Mutex<Entries>,
+    snapshot: Option<PathBuf>,
+    // serializes snapshot writers
+    writing: Mutex<()>,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: BTreeMap<String, String>,
+    // changed since the last snapshot
+    dirty: bool,
+}
+
+impl Default for MemoryKvsEngine {
+    fn default() -> Self {
+        MemoryKvsEngine::new()
+    }
+}
+
+impl MemoryKvsEngine {
+    /// Creates an empty engine living in memory only
+    pub fn new() -> MemoryKvsEngine {
+        MemoryKvsEngine {
+            shared: Arc::new(Shared {
+                entries: Mutex::default(),
+                snapshot: None,
+                writing: Mutex::default(),
+            }),
+            metrics: Arc::new(Metrics::default()),
+            settings: StoreSettings::default(),
+            _lock: None,
+            _snapshots: None,
+        }
+    }
+
+    /// Opens an engine snapshotting its entries to `path` every `interval`
+    /// when they changed, starting from the last snapshot found there
+    pub fn open(path: impl Into<PathBuf>, interval: Duration) -> Result<MemoryKvsEngine> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        check_engine(&path, Self::NAME)?;
+        let lock = lock_directory(&path)?;
+        claim_directory(&path, Self::NAME)?;
+
+        let snapshot = path.join(SNAPSHOT_FILE);
+        let mut map = BTreeMap::new();
+        if snapshot.exists() {
+            let reader = BufReader::new(File::open(&snapshot)?);
+            for record in read_records(reader, DataFormat::JsonLines) {
+                let (key, value) = record?;
+                map.insert(key, value);
+            }
+        }
+        info!(path = %path.display(), keys = map.len(), "opened memory store");
+
+        let shared = Arc::new(Shared {
+            entries: Mutex::new(Entries { map, dirty: false }),
+            snapshot: Some(snapshot),
+            writing: Mutex::default(),
+        });
+        let (snapshots, stopped) = mpsc::channel();
+        spawn_snapshots(Arc::downgrade(&shared), interval, stopped);
+        Ok(MemoryKvsEngine {
+            shared,
+            metrics: Arc::new(Metrics::default()),
+            settings: StoreSettings::default(),
+            _lock: Some(lock),
+            _snapshots: Some(snapshots),
+        })
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        lock(&self.shared.entries)
+    }
+
+    /// Reports a finished operation to the metrics
+    fn observe<T>(&self, op: StoreOp, start: Instant, result: &Result<T>) {
+        self.metrics
+            .observe(op, Outcome::of(result, |_| true), start.elapsed());
+    }
+}
+
+impl KvsEngine for MemoryKvsEngine {
+    const NAME: &'static str = "memory";
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        let start = Instant::now();
+        let result = self.settings.check_limits(&key, &value).map(|_| {
+            let mut entries = self.entries();
+            entries.map.insert(key, value);
+            entries.dirty = true;
+        });
+        self.observe(StoreOp::Set, start, &result);
+        result
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        let start = Instant::now();
+        let result = Ok(self.entries().map.get(&key).cloned());
+        self.metrics.observe(
+            StoreOp::Get,
+            Outcome::of(&result, Option::is_some),
+            start.elapsed(),
+        );
+        result
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        let start = Instant::now();
+        let mut entries = self.entries();
+        let result = match entries.map.remove(&key) {
+            Some(_) => {
+                entries.dirty = true;
+                Ok(())
+            }
+            None => Err(KvsError::KeyNotFound),
+        };
+        drop(entries);
+        self.observe(StoreOp::Remove, start, &result);
+        result
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let start = Instant::now();
+        let result = Ok(self
+            .entries()
+            .map
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect());
+        self.observe(StoreOp::Scan, start, &result);
+        result
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = snapshot(&self.shared);
+        self.observe(StoreOp::Sync, start, &result);
+        result
+    }
+
+    fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    fn settings(&self) -> StoreSettings {
+        self.settings.clone()
+    }
+}
+
+impl Drop for MemoryKvsEngine {
+    fn drop(&mut self) {
+        if let Err(e) = snapshot(&self.shared) {
+            warn!(error = %e, "final snapshot failed");
+        }
+    }
+}
+
+/// Snapshots the entries every `interval` until the engine is dropped,
+/// which closes `stopped`
+fn spawn_snapshots(shared: Weak<Shared>, interval: Duration, stopped: Receiver<()>) {
+    thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let Some(shared) = shared.upgrade() else {
+                break;
+            };
+            if let Err(e) = snapshot(&shared) {
+                warn!(error = %e, "snapshot failed");
+            }
+        }
+    });
+}
+
+/// Writes the entries to the snapshot file when they changed since the last one
+///
+/// The snapshot is written aside and renamed over the previous one, so a
+/// crash leaves either of them whole.
+fn snapshot(shared: &Shared) -> Result<()> {
+    let Some(path) = &shared.snapshot else {
+        return Ok(());
+    };
+    let _writing = lock(&shared.writing);
+    let map = {
+        let mut entries = lock(&shared.entries);
+        if !entries.dirty {
+            return Ok(());
+        }
+        entries.dirty = false;
+        entries.map.clone()
+    };
+    let written = write_snapshot(path, map);
+    if written.is_err() {
+        lock(&shared.entries).dirty = true;
+    }
+    written
+}
+
+/// Locks `mutex`, an operation panicking with it locked leaves
+/// the entries consistent
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_snapshot(path: &Path, map: BTreeMap<String, String>) -> Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    write_records(&mut writer, DataFormat::JsonLines, map.into_iter().map(Ok))?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    std::fs::rename(&temp, path)?;
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use crate::{KvsError, Result};
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tiny_http::{Header, Method, Server};
+use tracing::warn;
+
+/// Upper bounds in seconds of the latency histogram buckets
+const BUCKETS: [f64; 12] = [
+    0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.1, 0.5, 2.5,
+];
+
+/// A store operation, as timed by the metrics and the slow log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StoreOp {
+    /// a get
+    Get,
+    /// a set
+    Set,
+    /// a remove
+    Remove,
+    /// a scan of a prefix
+    Scan,
+    /// a flush of the log to disk
+    Sync,
+}
+
+impl StoreOp {
+    /// Returns the lowercase name of the operation
+    pub fn name(self) -> &'static str {
+        OPS[self as usize].1
+    }
+}
+
+pub(crate) const OPS: [(StoreOp, &str); 5] = [
+    (StoreOp::Get, "get"),
+    (StoreOp::Set, "set"),
+    (StoreOp::Remove, "remove"),
+    (StoreOp::Scan, "scan"),
+    (StoreOp::Sync, "sync"),
+];
+
+/// How a store operation ended
+#[derive(Clone, Copy)]
+pub(crate) enum Outcome {
+    Ok,
+    /// the key did not exist
+    NotFound,
+    Error,
+}
+
+const OUTCOMES: [(Outcome, &str); 3] = [
+    (Outcome::Ok, "ok"),
+    (Outcome::NotFound, "not_found"),
+    (Outcome::Error, "error"),
+];
+
+impl Outcome {
+    /// Outcome of an operation, `found` tells whether its key existed
+    pub(crate) fn of<T>(result: &Result<T>, found: impl FnOnce(&T) -> bool) -> Self {
+        match result {
+            Ok(value) if found(value) => Outcome::Ok,
+            Ok(_) | Err(KvsError::KeyNotFound) => Outcome::NotFound,
+            Err(_) => Outcome::Error,
+        }
+    }
+}
+
+/// Counts of observations falling in each bucket, with their total
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Writes the histogram as `name`, `labels` being the other labels of
+    /// its series, empty or ending with a comma
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{{labels}le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{name}_bucket{{{labels}le=\"+Inf\"}} {count}");
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{labels}}}"),
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {sum}");
+        let _ = writeln!(out, "{name}_count{labels} {count}");
+    }
+}
+
+/// Counters and gauges of a store and the servers in front of it
+///
+/// Every KvStore keeps its own, see `KvStore::metrics`. They are rendered
+/// in the Prometheus text format by `render` and served by `MetricsServer`.
+#[derive(Default)]
+pub struct Metrics {
+    latency: [Histogram; OPS.len()],
+    operations: [[AtomicU64; OUTCOMES.len()]; OPS.len()],
+    bytes_written: AtomicU64,
+    compaction: Histogram,
+    reclaimed_bytes: AtomicU64,
+    generations: AtomicU64,
+    connections: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn observe(&self, op: StoreOp, outcome: Outcome, elapsed: Duration) {
+        self.latency[op as usize].observe(elapsed);
+        self.operations[op as usize][outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn wrote(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn compacted(&self, elapsed: Duration, reclaimed: u64) {
+        self.compaction.observe(elapsed);
+        self.reclaimed_bytes.fetch_add(reclaimed, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_generations(&self, generations: usize) {
+        self.generations
+            .store(generations as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a connection as open until the returned guard is dropped
+    pub(crate) fn open_connection(self: &Arc<Self>) -> OpenConnection {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(Arc::clone(self))
+    }
+
+    /// Renders every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP kvs_operation_duration_seconds Latency of store operations.\n");
+        out.push_str("# TYPE kvs_operation_duration_seconds histogram\n");
+        for (op, name) in OPS {
+            self.latency[op as usize].render(
+                &mut out,
+                "kvs_operation_duration_seconds",
+                &format!("op=\"{name}\","),
+            );
+        }
+        out.push_str("# HELP kvs_operations_total Store operations by outcome.\n");
+        out.push_str("# TYPE kvs_operations_total counter\n");
+        for (op, op_name) in OPS {
+            for (outcome, outcome_name) in OUTCOMES {
+                let count = self.operations[op as usize][outcome as usize].load(Ordering::Relaxed);
+                let _ = writeln!(
+                    out,
+                    "kvs_operations_total{{op=\"{op_name}\",outcome=\"{outcome_name}\"}} {count}"
+                );
+            }
+        }
+        counter(
+            &mut out,
+            "kvs_bytes_written_total",
+            "Bytes appended to the log.",
+            &self.bytes_written,
+        );
+        out.push_str("# HELP kvs_compaction_duration_seconds Duration of log compactions.\n");
+        out.push_str("# TYPE kvs_compaction_duration_seconds histogram\n");
+        self.compaction
+            .render(&mut out, "kvs_compaction_duration_seconds", "");
+        counter(
+            &mut out,
+            "kvs_compaction_reclaimed_bytes_total",
+            "Bytes of log freed by compactions.",
+            &self.reclaimed_bytes,
+        );
+        gauge(
+            &mut out,
+            "kvs_generations",
+            "Log files of the store.",
+            &self.generations,
+        );
+        gauge(
+            &mut out,
+            "kvs_open_connections",
+            "Client connections currently open.",
+            &self.connections,
+        );
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let value = value.load(Ordering::Relaxed);
+    let _ = write!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+    );
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let value = value.load(Ordering::Relaxed);
+    let _ = write!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"
+    );
+}
+
+/// A connection counted in the metrics until dropped
+pub(crate) struct OpenConnection(Arc<Metrics>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Serves metrics to Prometheus on `GET /metrics`
+pub struct MetricsServer {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsServer {
+    /// Creates a server exposing `metrics`
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        MetricsServer { metrics }
+    }
+
+    /// Listens on `addr` and answers scrapes, one at a time, forever
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        let server = Server::http(addr).map_err(std::io::Error::other)?;
+        for request in server.incoming_requests() {
+            let response = if request.method() == &Method::Get && request.url() == "/metrics" {
+                let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+                    .expect("static header is valid");
+                tiny_http::Response::from_string(self.metrics.render()).with_header(content_type)
+            } else {
+                tiny_http::Response::from_string("Not found\n").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                warn!(error = %e, "error answering scrape");
+            }
+        }
+        Ok(())
+    }
+}
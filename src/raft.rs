@@ -0,0 +1,950 @@
+//! Raft consensus replicating the writes of a KvStore across a cluster
+//!
+//! A node is driven from the outside and can be carried by any transport,
+//! `KvsServer::cluster` serves one over TCP.
+
+use crate::kv::LogEntry;
+use crate::{KvStore, KvsError, Metrics, Result, StoreSettings};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Deserializer;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Identifier of a node in a raft cluster
+pub type NodeId = u64;
+
+/// ticks without hearing from a leader before a follower starts an election,
+/// randomised up to twice this value
+const ELECTION_TICKS: u64 = 10;
+/// ticks between two heartbeats of a leader
+const HEARTBEAT_TICKS: u64 = 3;
+/// number of applied entries kept in the raft log before it is truncated
+const SNAPSHOT_THRESHOLD: u64 = 500;
+/// maximum number of entries sent in a single `AppendEntries`
+const MAX_APPEND_ENTRIES: usize = 100;
+
+/// A command replicated through the raft log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Command {
+    /// sets a value for a key
+    Set {
+        /// key to set
+        key: String,
+        /// new value
+        value: String,
+    },
+    /// removes a key
+    Remove {
+        /// key to remove
+        key: String,
+    },
+    /// adds a voting member to the cluster
+    AddMember(NodeId),
+    /// removes a voting member from the cluster
+    RemoveMember(NodeId),
+    /// appended by every new leader to commit the entries of previous terms
+    Noop,
+}
+
+/// An entry of the raft log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// term of the leader that created the entry
+    pub term: u64,
+    /// position of the entry in the log, starting at 1
+    pub index: u64,
+    /// replicated command
+    pub command: Command,
+}
+
+/// The state of the store at a log index, sent to followers whose
+/// missing entries were truncated from the leader's log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaftSnapshot {
+    /// index of the last entry included in the snapshot
+    pub index: u64,
+    /// term of the last entry included in the snapshot
+    pub term: u64,
+    /// voting members at `index`
+    pub members: Vec<NodeId>,
+    /// every live key/value pair of the store
+    pub entries: Vec<(String, String)>,
+}
+
+/// A message exchanged between raft nodes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    /// sent by candidates to gather votes
+    RequestVote {
+        /// candidate's term
+        term: u64,
+        /// index of the candidate's last log entry
+        last_index: u64,
+        /// term of the candidate's last log entry
+        last_term: u64,
+    },
+    /// reply to `RequestVote`
+    Vote {
+        /// voter's term
+        term: u64,
+        /// whether the vote was granted
+        granted: bool,
+    },
+    /// sent by leaders to replicate entries, empty as a heartbeat
+    AppendEntries {
+        /// leader's term
+        term: u64,
+        /// index of the entry preceding `entries`
+        prev_index: u64,
+        /// term of the entry preceding `entries`
+        prev_term: u64,
+        /// entries to append
+        entries: Vec<Entry>,
+        /// leader's commit index
+        commit: u64,
+    },
+    /// reply to `AppendEntries` and `InstallSnapshot`
+    AppendResponse {
+        /// follower's term
+        term: u64,
+        /// whether the entries were appended
+        success: bool,
+        /// last index known to match the leader's log on success,
+        /// a hint of where to retry from otherwise
+        match_index: u64,
+    },
+    /// sent by leaders to followers that are too far behind
+    InstallSnapshot {
+        /// leader's term
+        term: u64,
+        /// state of the store to install
+        snapshot: RaftSnapshot,
+    },
+}
+
+impl Message {
+    fn term(&self) -> u64 {
+        match self {
+            Message::RequestVote { term, .. }
+            | Message::Vote { term, .. }
+            | Message::AppendEntries { term, .. }
+            | Message::AppendResponse { term, .. }
+            | Message::InstallSnapshot { term, .. } => *term,
+        }
+    }
+}
+
+/// state that must survive restarts, but for the log
+#[derive(Serialize, Deserialize, Default)]
+struct PersistentState {
+    term: u64,
+    voted_for: Option<NodeId>,
+    snapshot_index: u64,
+    snapshot_term: u64,
+    snapshot_members: Vec<NodeId>,
+}
+
+/// The entries following the last snapshot, kept in memory and appended
+/// to a file as they come
+struct RaftLog {
+    path: PathBuf,
+    entries: Vec<Entry>,
+    /// position of each entry in the file
+    offsets: Vec<u64>,
+    writer: BufWriter<File>,
+    len: u64,
+}
+
+impl RaftLog {
+    /// Loads the log at `path`, keeping the entries following `snapshot_index`
+    ///
+    /// Entries past a gap, or torn by a crash, are cut off the file.
+    fn open(path: PathBuf, snapshot_index: u64) -> Result<RaftLog> {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut offsets = Vec::new();
+        let mut len = 0;
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            let mut stream = Deserializer::from_reader(reader).into_iter::<Entry>();
+            while let Some(Ok(entry)) = stream.next() {
+                let next = entries
+                    .last()
+                    .map_or(snapshot_index + 1, |last| last.index + 1);
+                if entry.index > next {
+                    break;
+                }
+                // entries covered by the snapshot are dropped from the file
+                // the next time the log is truncated
+                if entry.index == next {
+                    entries.push(entry);
+                    offsets.push(len);
+                }
+                len = stream.byte_offset() as u64;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.set_len(len)?;
+        Ok(RaftLog {
+            path,
+            entries,
+            offsets,
+            writer: BufWriter::new(file),
+            len,
+        })
+    }
+
+    /// Appends `entry`, durable once `sync` returns
+    fn push(&mut self, entry: Entry) -> Result<()> {
+        let bytes = serde_json::to_vec(&entry)?;
+        self.writer.write_all(&bytes)?;
+        self.offsets.push(self.len);
+        self.len += bytes.len() as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    /// Keeps the first `count` entries, cutting the others off the file
+    fn truncate(&mut self, count: usize) -> Result<()> {
+        if count >= self.entries.len() {
+            return Ok(());
+        }
+        self.writer.flush()?;
+        self.len = self.offsets[count];
+        self.writer.get_ref().set_len(self.len)?;
+        self.entries.truncate(count);
+        self.offsets.truncate(count);
+        Ok(())
+    }
+
+    /// Drops the first `count` entries, rewriting the file without them
+    fn drain(&mut self, count: usize) -> Result<()> {
+        self.writer.flush()?;
+        self.entries.drain(..count.min(self.entries.len()));
+        let tmp_path = self.path.with_extension("log.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        self.offsets.clear();
+        self.len = 0;
+        for entry in &self.entries {
+            let bytes = serde_json::to_vec(entry)?;
+            writer.write_all(&bytes)?;
+            self.offsets.push(self.len);
+            self.len += bytes.len() as u64;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        Ok(())
+    }
+}
+
+enum Role {
+    Follower,
+    Candidate {
+        votes: HashSet<NodeId>,
+    },
+    Leader {
+        next_index: HashMap<NodeId, u64>,
+        match_index: HashMap<NodeId, u64>,
+    },
+}
+
+/// A node of a raft cluster replicating writes to its KvStore
+///
+/// The node is driven from the outside: `tick` advances its logical clock,
+/// `step` hands it a message from a peer and `take_messages` returns the
+/// messages it wants delivered, so any transport, or a simulated network,
+/// can carry them. Committed commands are applied to the store with their
+/// log index as sequence number.
+///
+/// The term, vote and snapshot position are kept in `raft.json`, the log
+/// in `raft.log`, appended to as entries come and only rewritten when the
+/// entries covered by a snapshot are dropped.
+pub struct RaftNode {
+    id: NodeId,
+    path: PathBuf,
+    store: Arc<Mutex<KvStore>>,
+    state: PersistentState,
+    log: RaftLog,
+    /// snapshot sent to followers missing entries dropped from the log
+    snapshot: Option<RaftSnapshot>,
+    role: Role,
+    leader: Option<NodeId>,
+    commit_index: u64,
+    last_applied: u64,
+    election_elapsed: u64,
+    election_timeout: u64,
+    heartbeat_elapsed: u64,
+    rng: u64,
+    outbox: Vec<(NodeId, Message)>,
+}
+
+impl RaftNode {
+    /// Opens the node `id` stored in `path`
+    ///
+    /// `members` is the initial cluster configuration, it is ignored when
+    /// the node already holds raft state. A node joining an existing
+    /// cluster starts with no members and learns them from the leader.
+    pub fn open(id: NodeId, members: Vec<NodeId>, path: impl Into<PathBuf>) -> Result<RaftNode> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        let store = KvStore::open(path.join("data"))?;
+        let state_path = path.join("raft.json");
+        let exists = state_path.exists();
+        let state: PersistentState = if exists {
+            serde_json::from_reader(BufReader::new(File::open(&state_path)?))?
+        } else {
+            PersistentState::default()
+        };
+        let mut log = RaftLog::open(path.join("raft.log"), state.snapshot_index)?;
+        if !exists && log.entries.is_empty() {
+            // every founding node starts from the same log, so the initial
+            // configuration replicates to nodes joining later like any change
+            for (index, member) in (1..).zip(members) {
+                log.push(Entry {
+                    term: 0,
+                    index,
+                    command: Command::AddMember(member),
+                })?;
+            }
+            log.sync()?;
+        }
+        let last_applied = store.sequence().max(state.snapshot_index);
+        let mut node = RaftNode {
+            id,
+            path,
+            store: Arc::new(Mutex::new(store)),
+            state,
+            log,
+            snapshot: None,
+            role: Role::Follower,
+            leader: None,
+            commit_index: last_applied,
+            last_applied,
+            election_elapsed: 0,
+            election_timeout: 0,
+            heartbeat_elapsed: 0,
+            rng: id.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            outbox: Vec::new(),
+        };
+        node.reset_election_timer();
+        Ok(node)
+    }
+
+    /// Identifier of the node
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Current term
+    pub fn term(&self) -> u64 {
+        self.state.term
+    }
+
+    /// true when the node is the leader of its term
+    pub fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader { .. })
+    }
+
+    /// The leader the node last heard from, if any
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
+
+    /// Index of the last committed entry known to the node
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    /// Index of the last entry applied to the store
+    pub fn applied_index(&self) -> u64 {
+        self.last_applied
+    }
+
+    /// Voting members of the cluster, including uncommitted changes
+    pub fn members(&self) -> Vec<NodeId> {
+        self.members_at(self.last_index())
+    }
+
+    /// Settings of the store, changed while the node runs
+    pub fn settings(&self) -> StoreSettings {
+        self.store().settings()
+    }
+
+    /// Metrics of the store
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.store().metrics()
+    }
+
+    /// The store the node applies committed commands to, shared with
+    /// the server answering requests the log is not involved in
+    pub(crate) fn shared_store(&self) -> Arc<Mutex<KvStore>> {
+        Arc::clone(&self.store)
+    }
+
+    /// Appends a command to the log, returns the index it will be committed at
+    ///
+    /// Only the leader accepts commands, other nodes answer with
+    /// `NotLeader` and the leader they know of so clients can redirect.
+    pub fn propose(&mut self, command: Command) -> Result<u64> {
+        if !self.is_leader() {
+            return Err(KvsError::NotLeader(self.leader));
+        }
+        match &command {
+            Command::AddMember(_) | Command::RemoveMember(_) => {
+                let pending = self
+                    .entries_from(self.commit_index + 1)
+                    .iter()
+                    .any(|entry| is_membership_change(&entry.command));
+                if pending {
+                    return Err(KvsError::MembershipChangeInProgress);
+                }
+            }
+            Command::Remove { key } if self.store().get(key.clone())?.is_none() => {
+                return Err(KvsError::KeyNotFound);
+            }
+            _ => {}
+        }
+        let index = self.append(command)?;
+        self.broadcast_append();
+        self.advance_commit()?;
+        Ok(index)
+    }
+
+    /// Gets a value by key from the leader's store
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        if !self.is_leader() {
+            return Err(KvsError::NotLeader(self.leader));
+        }
+        self.store().get(key)
+    }
+
+    /// Returns every live key/value pair starting with `prefix` from the
+    /// leader's store, ordered by key
+    pub fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        if !self.is_leader() {
+            return Err(KvsError::NotLeader(self.leader));
+        }
+        self.store().scan(prefix)
+    }
+
+    /// Advances the logical clock by one tick
+    pub fn tick(&mut self) -> Result<()> {
+        if self.is_leader() {
+            self.heartbeat_elapsed += 1;
+            if self.heartbeat_elapsed >= HEARTBEAT_TICKS {
+                self.heartbeat_elapsed = 0;
+                self.broadcast_append();
+            }
+        } else {
+            self.election_elapsed += 1;
+            if self.election_elapsed >= self.election_timeout && self.members().contains(&self.id) {
+                self.start_election()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a message sent by `from`
+    pub fn step(&mut self, from: NodeId, message: Message) -> Result<()> {
+        if message.term() > self.state.term {
+            let leader = match message {
+                Message::AppendEntries { .. } | Message::InstallSnapshot { .. } => Some(from),
+                _ => None,
+            };
+            self.become_follower(message.term(), leader)?;
+        }
+        match message {
+            Message::RequestVote {
+                term,
+                last_index,
+                last_term,
+            } => self.handle_request_vote(from, term, last_index, last_term),
+            Message::Vote { term, granted } => self.handle_vote(from, term, granted),
+            Message::AppendEntries {
+                term,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => self.handle_append(from, term, prev_index, prev_term, entries, commit),
+            Message::AppendResponse {
+                term,
+                success,
+                match_index,
+            } => self.handle_append_response(from, term, success, match_index),
+            Message::InstallSnapshot { term, snapshot } => {
+                self.handle_install_snapshot(from, term, snapshot)
+            }
+        }
+    }
+
+    /// Returns the messages to deliver, addressed to their recipient
+    pub fn take_messages(&mut self) -> Vec<(NodeId, Message)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn handle_request_vote(
+        &mut self,
+        candidate: NodeId,
+        term: u64,
+        last_index: u64,
+        last_term: u64,
+    ) -> Result<()> {
+        let up_to_date = (last_term, last_index) >= (self.last_term(), self.last_index());
+        let granted = term == self.state.term
+            && self.state.voted_for.is_none_or(|vote| vote == candidate)
+            && up_to_date;
+        if granted {
+            self.state.voted_for = Some(candidate);
+            self.persist()?;
+            self.reset_election_timer();
+        }
+        self.send(
+            candidate,
+            Message::Vote {
+                term: self.state.term,
+                granted,
+            },
+        );
+        Ok(())
+    }
+
+    fn handle_vote(&mut self, voter: NodeId, term: u64, granted: bool) -> Result<()> {
+        let members = self.members();
+        let Role::Candidate { votes } = &mut self.role else {
+            return Ok(());
+        };
+        if term == self.state.term && granted && members.contains(&voter) {
+            votes.insert(voter);
+            if votes.len() > members.len() / 2 {
+                self.become_leader()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_append(
+        &mut self,
+        leader: NodeId,
+        term: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    ) -> Result<()> {
+        if term < self.state.term {
+            return self.reply_append(leader, false, self.last_index());
+        }
+        self.role = Role::Follower;
+        self.leader = Some(leader);
+        self.reset_election_timer();
+
+        if prev_index > self.last_index() {
+            return self.reply_append(leader, false, self.last_index());
+        }
+        if prev_index >= self.state.snapshot_index && self.term_at(prev_index) != Some(prev_term) {
+            return self.reply_append(leader, false, prev_index.saturating_sub(1));
+        }
+
+        let entry_count = entries.len() as u64;
+        let mut changed = false;
+        for entry in entries {
+            if entry.index <= self.state.snapshot_index {
+                continue;
+            }
+            match self.term_at(entry.index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => self.truncate_from(entry.index)?,
+                None => {}
+            }
+            self.log.push(entry)?;
+            changed = true;
+        }
+        if changed {
+            self.log.sync()?;
+        }
+
+        // entries past the ones just checked may still conflict with the leader
+        let match_index = (prev_index + entry_count).max(self.state.snapshot_index);
+        if commit.min(match_index) > self.commit_index {
+            self.commit_index = commit.min(match_index);
+            self.apply_committed()?;
+        }
+        self.reply_append(leader, true, match_index)
+    }
+
+    fn handle_append_response(
+        &mut self,
+        follower: NodeId,
+        term: u64,
+        success: bool,
+        match_index: u64,
+    ) -> Result<()> {
+        if term != self.state.term {
+            return Ok(());
+        }
+        let last_index = self.last_index();
+        let Role::Leader {
+            next_index,
+            match_index: matched,
+        } = &mut self.role
+        else {
+            return Ok(());
+        };
+        if success {
+            let matched = matched.entry(follower).or_default();
+            *matched = (*matched).max(match_index);
+            next_index.insert(follower, *matched + 1);
+        } else {
+            let next = next_index.entry(follower).or_insert(last_index + 1);
+            *next = (*next - 1).min(match_index + 1).max(1);
+        }
+
+        if success {
+            self.advance_commit()?;
+        }
+        if !success || match_index < last_index {
+            self.send_append(follower);
+        }
+        Ok(())
+    }
+
+    fn handle_install_snapshot(
+        &mut self,
+        leader: NodeId,
+        term: u64,
+        snapshot: RaftSnapshot,
+    ) -> Result<()> {
+        if term < self.state.term {
+            return self.reply_append(leader, false, self.last_index());
+        }
+        self.role = Role::Follower;
+        self.leader = Some(leader);
+        self.reset_election_timer();
+
+        if snapshot.index <= self.commit_index {
+            return self.reply_append(leader, true, self.commit_index);
+        }
+        // entries following the snapshot are kept when the log agrees with it
+        let dropped = if self.term_at(snapshot.index) == Some(snapshot.term) {
+            (snapshot.index - self.state.snapshot_index) as usize
+        } else {
+            self.log.entries.len()
+        };
+        self.store()
+            .apply_snapshot(snapshot.index, snapshot.entries)?;
+        self.state.snapshot_index = snapshot.index;
+        self.state.snapshot_term = snapshot.term;
+        self.state.snapshot_members = snapshot.members;
+        self.commit_index = snapshot.index;
+        self.last_applied = snapshot.index;
+        self.persist()?;
+        self.log.drain(dropped)?;
+        self.reply_append(leader, true, snapshot.index)
+    }
+
+    fn reply_append(&mut self, leader: NodeId, success: bool, match_index: u64) -> Result<()> {
+        self.send(
+            leader,
+            Message::AppendResponse {
+                term: self.state.term,
+                success,
+                match_index,
+            },
+        );
+        Ok(())
+    }
+
+    fn start_election(&mut self) -> Result<()> {
+        self.state.term += 1;
+        self.state.voted_for = Some(self.id);
+        self.persist()?;
+        self.leader = None;
+        self.role = Role::Candidate {
+            votes: HashSet::from([self.id]),
+        };
+        self.reset_election_timer();
+
+        if self.members().len() == 1 {
+            return self.become_leader();
+        }
+        let message = Message::RequestVote {
+            term: self.state.term,
+            last_index: self.last_index(),
+            last_term: self.last_term(),
+        };
+        for peer in self.peers() {
+            self.send(peer, message.clone());
+        }
+        Ok(())
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) -> Result<()> {
+        self.state.term = term;
+        self.state.voted_for = None;
+        self.persist()?;
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.reset_election_timer();
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> Result<()> {
+        self.role = Role::Leader {
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+        };
+        self.leader = Some(self.id);
+        self.heartbeat_elapsed = 0;
+        self.append(Command::Noop)?;
+        self.broadcast_append();
+        self.advance_commit()
+    }
+
+    /// Appends a command of the current term to the leader's log
+    fn append(&mut self, command: Command) -> Result<u64> {
+        let index = self.last_index() + 1;
+        self.log.push(Entry {
+            term: self.state.term,
+            index,
+            command,
+        })?;
+        self.log.sync()?;
+        Ok(index)
+    }
+
+    fn broadcast_append(&mut self) {
+        for peer in self.peers() {
+            self.send_append(peer);
+        }
+    }
+
+    /// Sends `peer` the entries it is missing, or a snapshot when
+    /// they were truncated from the log
+    fn send_append(&mut self, peer: NodeId) {
+        let last_index = self.last_index();
+        let Role::Leader { next_index, .. } = &mut self.role else {
+            return;
+        };
+        let next = *next_index.entry(peer).or_insert(last_index + 1);
+        let message = if next <= self.state.snapshot_index {
+            match self.snapshot() {
+                Ok(snapshot) => Message::InstallSnapshot {
+                    term: self.state.term,
+                    snapshot,
+                },
+                Err(_) => return,
+            }
+        } else {
+            let prev_index = next - 1;
+            let mut entries = self.entries_from(next).to_vec();
+            entries.truncate(MAX_APPEND_ENTRIES);
+            Message::AppendEntries {
+                term: self.state.term,
+                prev_index,
+                prev_term: self.term_at(prev_index).unwrap_or_default(),
+                entries,
+                commit: self.commit_index,
+            }
+        };
+        self.send(peer, message);
+    }
+
+    /// Commits the entries of the current term replicated on a majority
+    fn advance_commit(&mut self) -> Result<()> {
+        let Role::Leader { match_index, .. } = &self.role else {
+            return Ok(());
+        };
+        let committed = (self.commit_index + 1..=self.last_index())
+            .rev()
+            .take_while(|&index| self.term_at(index) == Some(self.state.term))
+            .find(|&index| {
+                let members = self.members_at(index);
+                let replicas = members
+                    .iter()
+                    .filter(|&&member| {
+                        member == self.id || match_index.get(&member).is_some_and(|&m| m >= index)
+                    })
+                    .count();
+                replicas > members.len() / 2
+            });
+        if let Some(index) = committed {
+            self.commit_index = index;
+            self.apply_committed()?;
+        }
+        Ok(())
+    }
+
+    /// Applies the committed entries to the store
+    fn apply_committed(&mut self) -> Result<()> {
+        while self.last_applied < self.commit_index {
+            let index = self.last_applied + 1;
+            let command = self.entries_from(index)[0].command.clone();
+            match command {
+                Command::Set { key, value } => {
+                    self.store().apply(LogEntry::set(index, key, value))?
+                }
+                Command::Remove { key } => {
+                    let mut store = self.store();
+                    if store.get(key.clone())?.is_some() {
+                        store.apply(LogEntry::remove(index, key))?;
+                    }
+                }
+                Command::RemoveMember(member) if member == self.id && self.is_leader() => {
+                    self.role = Role::Follower;
+                    self.leader = None;
+                }
+                Command::AddMember(_) | Command::RemoveMember(_) | Command::Noop => {}
+            }
+            self.last_applied = index;
+        }
+        if self.last_applied - self.state.snapshot_index > SNAPSHOT_THRESHOLD {
+            self.truncate_log()?;
+        }
+        Ok(())
+    }
+
+    /// Drops the applied entries from the log, the store now holds them
+    ///
+    /// Applying only flushes the store, so it is synced first: the entries
+    /// dropped must survive a power loss in the store. The snapshot
+    /// position is persisted next, a crash before the log is drained
+    /// leaves entries in the file that are skipped when it is loaded.
+    fn truncate_log(&mut self) -> Result<()> {
+        self.store().sync()?;
+        let index = self.last_applied;
+        let dropped = (index - self.state.snapshot_index) as usize;
+        self.state.snapshot_term = self.term_at(index).unwrap_or_default();
+        self.state.snapshot_members = self.members_at(index);
+        self.state.snapshot_index = index;
+        self.persist()?;
+        self.log.drain(dropped)
+    }
+
+    /// A snapshot of the store recent enough for followers missing entries
+    /// dropped from the log
+    ///
+    /// The snapshot is read from the generation a compaction of the store
+    /// leaves, and reused until the log is truncated again.
+    fn snapshot(&mut self) -> Result<RaftSnapshot> {
+        let fresh = self
+            .snapshot
+            .as_ref()
+            .filter(|snapshot| snapshot.index >= self.state.snapshot_index);
+        if let Some(snapshot) = fresh {
+            return Ok(snapshot.clone());
+        }
+        let snapshot = RaftSnapshot {
+            index: self.last_applied,
+            term: self.term_at(self.last_applied).unwrap_or_default(),
+            members: self.members_at(self.last_applied),
+            entries: self.store().compacted_entries()?,
+        };
+        self.snapshot = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+
+    fn truncate_from(&mut self, index: u64) -> Result<()> {
+        self.log
+            .truncate((index - self.state.snapshot_index - 1) as usize)
+    }
+
+    /// Locks the store for a single operation
+    fn store(&self) -> MutexGuard<'_, KvStore> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn send(&mut self, to: NodeId, message: Message) {
+        self.outbox.push((to, message));
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        self.members()
+            .into_iter()
+            .filter(|&member| member != self.id)
+            .collect()
+    }
+
+    /// Voting members as of the entry at `index`
+    fn members_at(&self, index: u64) -> Vec<NodeId> {
+        let mut members = self.state.snapshot_members.clone();
+        for entry in self
+            .log
+            .entries
+            .iter()
+            .take_while(|entry| entry.index <= index)
+        {
+            match entry.command {
+                Command::AddMember(member) if !members.contains(&member) => members.push(member),
+                Command::RemoveMember(member) => members.retain(|&m| m != member),
+                _ => {}
+            }
+        }
+        members
+    }
+
+    fn last_index(&self) -> u64 {
+        self.state.snapshot_index + self.log.entries.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.log
+            .entries
+            .last()
+            .map_or(self.state.snapshot_term, |entry| entry.term)
+    }
+
+    /// Term of the entry at `index`, `None` when it is unknown
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.state.snapshot_index {
+            return Some(self.state.snapshot_term);
+        }
+        let offset = index.checked_sub(self.state.snapshot_index + 1)?;
+        self.log
+            .entries
+            .get(offset as usize)
+            .map(|entry| entry.term)
+    }
+
+    /// Entries from `index` to the end of the log, `index` must not be truncated
+    fn entries_from(&self, index: u64) -> &[Entry] {
+        let offset = (index - self.state.snapshot_index - 1) as usize;
+        &self.log.entries[offset.min(self.log.entries.len())..]
+    }
+
+    fn reset_election_timer(&mut self) {
+        // xorshift, deterministic per node so simulations are reproducible
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.election_elapsed = 0;
+        self.election_timeout = ELECTION_TICKS + self.rng % ELECTION_TICKS;
+    }
+
+    /// Writes the term, vote and snapshot position atomically
+    fn persist(&self) -> Result<()> {
+        let tmp_path = self.path.join("raft.json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &self.state)?;
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+        std::fs::rename(tmp_path, self.path.join("raft.json"))?;
+        Ok(())
+    }
+}
+
+fn is_membership_change(command: &Command) -> bool {
+    matches!(command, Command::AddMember(_) | Command::RemoveMember(_))
+}
@@ -0,0 +1,154 @@
+use crate::common::{Request, Response, receive, send};
+use crate::engine::with_kv_store;
+use crate::kv::LogEntry;
+use crate::tls::Stream;
+use crate::{ClientTls, Credentials, KvsEngine, KvsError, Result, ShutdownHandle};
+
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// delay before a follower reconnects to its primary
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// how long a follower may take to connect to its primary
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// how a follower catches up before receiving new records
+pub(crate) enum Catchup {
+    /// the whole content of the store, for followers behind compaction
+    Snapshot {
+        seq: u64,
+        entries: Vec<(String, String)>,
+    },
+    /// the records the follower is missing, still found in the log
+    Records(Vec<LogEntry>),
+}
+
+/// Streams every record of `store` from sequence number `from` onwards
+/// to a follower, until the follower goes away
+pub(crate) fn serve_follower<E: KvsEngine>(
+    store: &Mutex<E>,
+    from: u64,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let (catchup, records) = with_kv_store(store, "replication", |store| store.subscribe(from))?;
+    match catchup {
+        Catchup::Snapshot { seq, entries } => send(writer, &Response::Snapshot { seq, entries })?,
+        Catchup::Records(entries) => {
+            for entry in entries {
+                send(writer, &Response::Record(entry))?;
+            }
+        }
+    }
+    for entry in records {
+        send(writer, &Response::Record(entry))?;
+    }
+    Ok(())
+}
+
+/// A thread keeping a store in sync with its primary until stopped
+pub(crate) struct Follower {
+    /// connection to the primary, closed to stop the thread
+    connection: Arc<Mutex<Option<TcpStream>>>,
+    thread: JoinHandle<()>,
+}
+
+impl Follower {
+    /// Keeps `store` in sync with the server at `primary`, reconnecting
+    /// whenever the connection is lost, until `shutdown` is requested
+    pub(crate) fn spawn<E: KvsEngine>(
+        store: Arc<Mutex<E>>,
+        primary: SocketAddr,
+        tls: Option<ClientTls>,
+        credentials: Option<Credentials>,
+        shutdown: ShutdownHandle,
+    ) -> Follower {
+        let connection = Arc::new(Mutex::new(None));
+        let current = Arc::clone(&connection);
+        let thread = thread::spawn(move || {
+            loop {
+                let followed = follow_once(
+                    &store,
+                    primary,
+                    tls.as_ref(),
+                    credentials.as_ref(),
+                    &shutdown,
+                    &current,
+                );
+                if shutdown.is_shutdown() {
+                    return;
+                }
+                if let Err(e) = followed {
+                    warn!(%primary, error = %e, "replication interrupted");
+                }
+                thread::sleep(RECONNECT_DELAY);
+            }
+        });
+        Follower { connection, thread }
+    }
+
+    /// Closes the connection to the primary and waits for the thread,
+    /// once the shutdown was requested
+    pub(crate) fn stop(self) {
+        let connection = self.connection.lock().expect("follower lock poisoned");
+        if let Some(stream) = connection.as_ref() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        drop(connection);
+        let _ = self.thread.join();
+    }
+}
+
+/// Applies the records streamed by the primary over a single connection,
+/// kept in `current` while open
+fn follow_once<E: KvsEngine>(
+    store: &Mutex<E>,
+    primary: SocketAddr,
+    tls: Option<&ClientTls>,
+    credentials: Option<&Credentials>,
+    shutdown: &ShutdownHandle,
+    current: &Mutex<Option<TcpStream>>,
+) -> Result<()> {
+    let stream = TcpStream::connect_timeout(&primary, CONNECT_TIMEOUT)?;
+    {
+        // stop closes the connection once the shutdown is requested
+        let mut current = current.lock().expect("follower lock poisoned");
+        if shutdown.is_shutdown() {
+            return Ok(());
+        }
+        *current = Some(stream.try_clone()?);
+    }
+    let stream = match tls {
+        Some(tls) => tls.connect(stream)?,
+        None => Stream::Plain(stream),
+    };
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    if let Some(credentials) = credentials {
+        send(&mut writer, &Request::Auth(credentials.clone()))?;
+        match receive(&mut reader)? {
+            Some(Response::Ok(_)) => {}
+            Some(Response::Err(e)) => return Err(e.into()),
+            _ => return Err(KvsError::UnexpectedCommandType),
+        }
+    }
+    let from = with_kv_store(store, "replication", |store| Ok(store.sequence()))? + 1;
+    info!(%primary, from, "following primary");
+    send(&mut writer, &Request::Replicate { from })?;
+    while let Some(response) = receive(&mut reader)? {
+        with_kv_store(store, "replication", |store| match response {
+            Response::Snapshot { seq, entries } => {
+                info!(seq, keys = entries.len(), "applying snapshot from primary");
+                store.apply_snapshot(seq, entries)
+            }
+            Response::Record(entry) => store.apply(entry),
+            Response::Err(e) => Err(e.into()),
+            _ => Err(KvsError::UnexpectedCommandType),
+        })?;
+    }
+    Ok(())
+}
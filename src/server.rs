@@ -0,0 +1,548 @@
+use crate::cluster::Cluster;
+use crate::common::{
+    MAX_REQUEST_BYTES, RemoteError, Request, Response, receive_limited, send, write,
+};
+use crate::engine::with_kv_store;
+use crate::metrics::{Metrics, OpenConnection};
+use crate::raft::{NodeId, RaftNode};
+use crate::replication::{Follower, serve_follower};
+use crate::tls::Stream;
+use crate::{
+    AccessControl, Acl, ClientTls, Credentials, KvStore, KvsEngine, KvsError, NaiveThreadPool,
+    Result, ServerTls, ShutdownHandle, ThreadPool, WatchEvent,
+};
+
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long a shutdown waits for in-flight requests by default
+pub(crate) const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A server exposing a storage engine over TCP, a KvStore by default
+///
+/// A server is either a primary, accepting writes and streaming its log to
+/// followers, a read-only replica following a primary, or a member of a
+/// raft cluster. Connections are served by a thread pool, a thread per
+/// connection by default.
+pub struct KvsServer<P: ThreadPool = NaiveThreadPool, E: KvsEngine = KvStore> {
+    store: Arc<Mutex<E>>,
+    primary: Option<SocketAddr>,
+    pool: P,
+    shutdown: ShutdownHandle,
+    drain_timeout: Duration,
+    tls: Option<ServerTls>,
+    primary_tls: Option<ClientTls>,
+    primary_credentials: Option<Credentials>,
+    access: AccessControl,
+    cluster: Option<Cluster>,
+}
+
+impl<E: KvsEngine> KvsServer<NaiveThreadPool, E> {
+    /// Creates a primary server for the given store
+    pub fn new(store: E) -> Self {
+        KvsServer {
+            store: Arc::new(Mutex::new(store)),
+            primary: None,
+            pool: NaiveThreadPool,
+            shutdown: ShutdownHandle::new(),
+            drain_timeout: DRAIN_TIMEOUT,
+            tls: None,
+            primary_tls: None,
+            primary_credentials: None,
+            access: AccessControl::default(),
+            cluster: None,
+        }
+    }
+}
+
+impl KvsServer<NaiveThreadPool, KvStore> {
+    /// Creates a read-only replica applying the log of the server at `primary`
+    pub fn replica(store: KvStore, primary: SocketAddr) -> Self {
+        KvsServer {
+            store: Arc::new(Mutex::new(store)),
+            primary: Some(primary),
+            pool: NaiveThreadPool,
+            shutdown: ShutdownHandle::new(),
+            drain_timeout: DRAIN_TIMEOUT,
+            tls: None,
+            primary_tls: None,
+            primary_credentials: None,
+            access: AccessControl::default(),
+            cluster: None,
+        }
+    }
+
+    /// Creates a member of a raft cluster serving the store of `node`,
+    /// `peers` are the addresses of the other members
+    ///
+    /// Writes are answered once committed and reads by the leader, the other
+    /// members redirect clients to it. Peers are reached with the TLS settings
+    /// and credentials given for a primary.
+    pub fn cluster(node: RaftNode, peers: HashMap<NodeId, SocketAddr>) -> Self {
+        KvsServer {
+            store: node.shared_store(),
+            primary: None,
+            pool: NaiveThreadPool,
+            shutdown: ShutdownHandle::new(),
+            drain_timeout: DRAIN_TIMEOUT,
+            tls: None,
+            primary_tls: None,
+            primary_credentials: None,
+            access: AccessControl::default(),
+            cluster: Some(Cluster::new(node, peers)),
+        }
+    }
+}
+
+impl<P: ThreadPool, E: KvsEngine> KvsServer<P, E> {
+    /// Serves connections with `pool` instead
+    ///
+    /// A connection holds its thread until it is closed, watchers and
+    /// followers included, so a fixed size pool must be large enough for
+    /// the long-lived connections plus the regular traffic.
+    pub fn with_pool<Q: ThreadPool>(self, pool: Q) -> KvsServer<Q, E> {
+        KvsServer {
+            store: self.store,
+            primary: self.primary,
+            pool,
+            shutdown: self.shutdown,
+            drain_timeout: self.drain_timeout,
+            tls: self.tls,
+            primary_tls: self.primary_tls,
+            primary_credentials: self.primary_credentials,
+            access: self.access,
+            cluster: self.cluster,
+        }
+    }
+
+    /// Encrypts the connections of clients
+    pub fn with_tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Encrypts the connection of a replica to its primary
+    pub fn with_primary_tls(mut self, tls: ClientTls) -> Self {
+        self.primary_tls = Some(tls);
+        self
+    }
+
+    /// Authenticates a replica to its primary
+    pub fn with_primary_credentials(mut self, credentials: Credentials) -> Self {
+        self.primary_credentials = Some(credentials);
+        self
+    }
+
+    /// Requires clients to authenticate and restricts them to what `acl`
+    /// grants them
+    pub fn with_acl(self, acl: Acl) -> Self {
+        self.access.reload(acl);
+        self
+    }
+
+    /// Returns a handle replacing the ACL while the server runs
+    pub fn access_control(&self) -> AccessControl {
+        self.access.clone()
+    }
+
+    /// Sets how long a shutdown waits for in-flight requests
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Returns a handle shutting the server down
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Listens on `addr` and serves every connection on the pool
+    /// until the server is shut down
+    ///
+    /// Once shut down, connections are closed after the request they are
+    /// running and the store is synced to disk. Fails with `DrainTimeout`
+    /// when requests are still running after the drain timeout.
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        self.shutdown.wake_through(local_addr);
+        info!(
+            addr = %local_addr,
+            primary = ?self.primary,
+            tls = self.tls.is_some(),
+            cluster = self.cluster.is_some(),
+            "listening"
+        );
+        let follower = self.primary.map(|primary| {
+            Follower::spawn(
+                Arc::clone(&self.store),
+                primary,
+                self.primary_tls.clone(),
+                self.primary_credentials.clone(),
+                self.shutdown.clone(),
+            )
+        });
+        let cluster = self.cluster.map(|cluster| {
+            Arc::new(
+                cluster
+                    .with_peer_settings(self.primary_tls.clone(), self.primary_credentials.clone()),
+            )
+        });
+        let driver = cluster.clone().map(|cluster| {
+            let shutdown = self.shutdown.clone();
+            thread::spawn(move || cluster.drive(&shutdown))
+        });
+        let read_only = self.primary.is_some();
+        let metrics = lock(&self.store).metrics();
+        let connections = Arc::new(Connections::default());
+        for stream in listener.incoming() {
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+            // a connection failing before it is served only loses itself
+            let accepted = stream.and_then(|stream| Ok((stream.peer_addr()?, stream)));
+            let (peer, stream) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(error = %e, "error accepting connection");
+                    continue;
+                }
+            };
+            let connection = match Connections::register(&connections, &stream, &metrics) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!(%peer, error = %e, "error accepting connection");
+                    continue;
+                }
+            };
+            info!(%peer, id = connection.id, "connection opened");
+            let store = Arc::clone(&self.store);
+            let shutdown = self.shutdown.clone();
+            let access = self.access.clone();
+            let tls = self.tls.clone();
+            let cluster = cluster.clone();
+            self.pool.spawn(move || {
+                let stream = match &tls {
+                    Some(tls) => tls.accept(stream),
+                    None => Ok(Stream::Plain(stream)),
+                };
+                let served = stream.and_then(|stream| {
+                    let cluster = cluster.as_deref();
+                    serve(
+                        &store,
+                        stream,
+                        read_only,
+                        cluster,
+                        &shutdown,
+                        &access,
+                        &connection,
+                    )
+                });
+                if let Err(e) = served {
+                    warn!(%peer, error = %e, "error serving client");
+                }
+                info!(%peer, id = connection.id, "connection closed");
+            });
+        }
+        drop(listener);
+
+        info!(timeout = ?self.drain_timeout, "shutting down, draining connections");
+        let busy = connections.drain(self.drain_timeout);
+        if let Some(follower) = follower {
+            follower.stop();
+        }
+        if let Some(driver) = driver {
+            let _ = driver.join();
+        }
+        close_subscriptions(&self.store);
+        connections.wait_subscriptions(self.drain_timeout);
+        lock(&self.store).sync()?;
+        // the directory can be opened again once run returns, unless
+        // requests are still running
+        drop((cluster, self.store));
+        if busy > 0 {
+            warn!(busy, "drain timed out");
+        }
+        match busy {
+            0 => Ok(()),
+            busy => Err(KvsError::DrainTimeout(busy)),
+        }
+    }
+}
+
+/// Open connections, so a shutdown can close them and wait for them
+#[derive(Default)]
+struct Connections {
+    open: Mutex<OpenConnections>,
+    closed: Condvar,
+}
+
+#[derive(Default)]
+struct OpenConnections {
+    next_id: u64,
+    streams: HashMap<u64, (TcpStream, bool)>,
+}
+
+/// A connection registered in Connections until dropped
+struct Connection {
+    connections: Arc<Connections>,
+    id: u64,
+    _open: OpenConnection,
+}
+
+impl Connections {
+    fn register(
+        connections: &Arc<Connections>,
+        stream: &TcpStream,
+        metrics: &Arc<Metrics>,
+    ) -> Result<Connection> {
+        let mut open = connections.open.lock().expect("connections lock poisoned");
+        let id = open.next_id;
+        open.next_id += 1;
+        open.streams.insert(id, (stream.try_clone()?, false));
+        Ok(Connection {
+            connections: Arc::clone(connections),
+            id,
+            _open: metrics.open_connection(),
+        })
+    }
+
+    /// Stops reading from every connection and waits up to `timeout` for
+    /// the requests they are running, returns the number still running
+    ///
+    /// Subscriptions are closed without being waited on.
+    fn drain(&self, timeout: Duration) -> usize {
+        let open = self.open.lock().expect("connections lock poisoned");
+        for (stream, subscribed) in open.streams.values() {
+            let how = if *subscribed {
+                Shutdown::Both
+            } else {
+                Shutdown::Read
+            };
+            let _ = stream.shutdown(how);
+        }
+        let busy = |open: &OpenConnections| {
+            open.streams
+                .values()
+                .filter(|(_, subscribed)| !subscribed)
+                .count()
+        };
+        let (open, _) = self
+            .closed
+            .wait_timeout_while(open, timeout, |open| busy(open) > 0)
+            .expect("connections lock poisoned");
+        busy(&open)
+    }
+}
+
+impl Connections {
+    /// Waits up to `timeout` for the subscriptions to end, once the store
+    /// closed them
+    fn wait_subscriptions(&self, timeout: Duration) {
+        let open = self.open.lock().expect("connections lock poisoned");
+        let _ = self
+            .closed
+            .wait_timeout_while(open, timeout, |open| {
+                open.streams.values().any(|(_, subscribed)| *subscribed)
+            })
+            .expect("connections lock poisoned");
+    }
+}
+
+impl Connection {
+    /// Leaves the connection out of the requests a shutdown waits for
+    fn subscribed(&self) {
+        let mut open = self
+            .connections
+            .open
+            .lock()
+            .expect("connections lock poisoned");
+        if let Some((_, subscribed)) = open.streams.get_mut(&self.id) {
+            *subscribed = true;
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut open = self
+            .connections
+            .open
+            .lock()
+            .expect("connections lock poisoned");
+        open.streams.remove(&self.id);
+        self.connections.closed.notify_all();
+    }
+}
+
+/// Answers the requests of a single connection until it is closed
+fn serve<E: KvsEngine>(
+    store: &Mutex<E>,
+    stream: Stream,
+    read_only: bool,
+    cluster: Option<&Cluster>,
+    shutdown: &ShutdownHandle,
+    access: &AccessControl,
+    connection: &Connection,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut user = None;
+
+    loop {
+        let request = match receive_limited::<_, Request>(&mut reader, MAX_REQUEST_BYTES) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(KvsError::LimitExceeded(limit)) => {
+                // the rest of the line cannot be skipped, the connection is closed
+                warn!(%limit, "closing a connection sending an oversized request");
+                send(
+                    &mut writer,
+                    &Response::Err(RemoteError::LimitExceeded(limit)),
+                )?;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        let request = match authorize(access, &mut user, request) {
+            Ok(request) => request,
+            Err(response) => {
+                send(&mut writer, &response)?;
+                continue;
+            }
+        };
+        if request.is_subscription() {
+            connection.subscribed();
+            return subscribe(store, request, &mut writer);
+        }
+        if let Request::Shutdown = request {
+            send(&mut writer, &Response::Ok(None))?;
+            shutdown.shutdown();
+            continue;
+        }
+        let response = match cluster {
+            Some(cluster) => cluster.execute(store, request),
+            None => execute(store, request, read_only),
+        };
+        // pipelined requests are answered with a single flush
+        write(&mut writer, &response)?;
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Hands `request` back when the user of the connection may run it,
+/// otherwise the response to send instead
+///
+/// Authentication requests are answered here and remember the user.
+pub(crate) fn authorize(
+    access: &AccessControl,
+    user: &mut Option<String>,
+    request: Request,
+) -> std::result::Result<Request, Response> {
+    match request {
+        Request::Auth(credentials) => match access.authenticate(&credentials) {
+            Ok(name) => {
+                *user = name;
+                Err(Response::Ok(None))
+            }
+            Err(e) => {
+                *user = None;
+                Err(Response::Err(e.into()))
+            }
+        },
+        request => access.authorize(user.as_deref(), request),
+    }
+}
+
+/// Runs a request answered with a single response
+pub(crate) fn execute<E: KvsEngine>(
+    store: &Mutex<E>,
+    request: Request,
+    read_only: bool,
+) -> Response {
+    if let Request::Tagged { id, request } = request {
+        let response = Box::new(execute(store, *request, read_only));
+        return Response::Tagged { id, response };
+    }
+    let response = match request {
+        Request::Set { .. } | Request::Remove { .. } if read_only => Err(KvsError::ReadOnly),
+        Request::Get { key } => lock(store).get(key).map(Response::Ok),
+        Request::Set { key, value } => lock(store).set(key, value).map(|_| Response::Ok(None)),
+        Request::Remove { key } => lock(store).remove(key).map(|_| Response::Ok(None)),
+        Request::Scan { prefix } => lock(store).scan(&prefix).map(Response::Entries),
+        Request::SlowLogGet { count } => with_kv_store(store, "the slow log", |store| {
+            Ok(Response::SlowLog(store.slow_log(count)))
+        }),
+        Request::SlowLogReset => with_kv_store(store, "the slow log", |store| {
+            store.reset_slow_log();
+            Ok(Response::Ok(None))
+        }),
+        Request::Checkpoint { dest } => with_kv_store(store, "backups", |store| {
+            store.checkpoint(dest)?;
+            Ok(Response::Ok(None))
+        }),
+        Request::Watch { .. }
+        | Request::Replicate { .. }
+        | Request::Raft { .. }
+        | Request::Tagged { .. }
+        | Request::Shutdown
+        | Request::Auth(_) => Err(KvsError::UnexpectedCommandType),
+    };
+    response.unwrap_or_else(|e| Response::Err(e.into()))
+}
+
+/// Runs a subscription, the connection carries nothing else
+/// until the client goes away
+pub(crate) fn subscribe<E: KvsEngine>(
+    store: &Mutex<E>,
+    request: Request,
+    writer: &mut impl Write,
+) -> Result<()> {
+    match request {
+        Request::Watch { prefix, from } => {
+            let events = with_kv_store(store, "watching", |store| match from {
+                Some(from) => store.watch_from(prefix, from),
+                None => Ok(store.watch(prefix)),
+            });
+            match events {
+                Ok(events) => stream_events(writer, events),
+                Err(e) => send(writer, &Response::Err(e.into())),
+            }
+        }
+        Request::Replicate { from } => serve_follower(store, from, writer),
+        _ => Err(KvsError::UnexpectedCommandType),
+    }
+}
+
+/// Ends every subscription to `store`, their threads return once they
+/// have forwarded what they received
+pub(crate) fn close_subscriptions<E: KvsEngine>(store: &Mutex<E>) {
+    if let Some(store) = lock(store).as_kv_store() {
+        store.close_subscriptions();
+    }
+}
+
+/// Locks the store for a single operation
+///
+/// A job panicking with the store locked only loses its own connection,
+/// the store keeps serving the others.
+pub(crate) fn lock<E: KvsEngine>(store: &Mutex<E>) -> MutexGuard<'_, E> {
+    store.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Forwards watch events to the client
+fn stream_events(writer: &mut impl Write, events: Receiver<WatchEvent>) -> Result<()> {
+    for event in events {
+        send(writer, &Response::Event(event))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use crate::{KvsError, Result};
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Stale bytes in the log that trigger a compaction by default
+pub(crate) const DEFAULT_COMPACTION_THRESHOLD: u64 = 1024 * 1024; // 1 MB
+
+/// Stands for an unset setting in the atomics below
+const UNSET: u64 = u64::MAX;
+
+/// How far a write goes before it is acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// handed to the operating system, lost if the machine crashes
+    #[default]
+    Flush,
+    /// on disk, each write waits for the log to be synced
+    Sync,
+}
+
+/// Settings of an open KvStore that may change while it runs
+///
+/// Clones share the settings, so whoever holds one can tune the store
+/// without locking it. See `KvStore::settings`.
+#[derive(Clone)]
+pub struct StoreSettings {
+    inner: Arc<Settings>,
+}
+
+struct Settings {
+    slow_threshold_nanos: AtomicU64,
+    compaction_threshold: AtomicU64,
+    sync_writes: AtomicBool,
+    max_key_bytes: AtomicU64,
+    max_value_bytes: AtomicU64,
+}
+
+impl Default for StoreSettings {
+    fn default() -> Self {
+        StoreSettings {
+            inner: Arc::new(Settings {
+                slow_threshold_nanos: AtomicU64::new(UNSET),
+                compaction_threshold: AtomicU64::new(DEFAULT_COMPACTION_THRESHOLD),
+                sync_writes: AtomicBool::new(false),
+                max_key_bytes: AtomicU64::new(UNSET),
+                max_value_bytes: AtomicU64::new(UNSET),
+            }),
+        }
+    }
+}
+
+impl StoreSettings {
+    /// Records operations taking at least `threshold` in the slow log,
+    /// `None` turns the slow log off
+    pub fn set_slow_threshold(&self, threshold: Option<Duration>) {
+        let nanos = threshold.map_or(UNSET, |threshold| {
+            threshold.as_nanos().min(UNSET as u128 - 1) as u64
+        });
+        self.inner
+            .slow_threshold_nanos
+            .store(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the threshold of the slow log, `None` when it is off
+    pub fn slow_threshold(&self) -> Option<Duration> {
+        load(&self.inner.slow_threshold_nanos).map(Duration::from_nanos)
+    }
+
+    /// Compacts the log once it holds more than `bytes` of stale records
+    pub fn set_compaction_threshold(&self, bytes: u64) {
+        self.inner
+            .compaction_threshold
+            .store(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the stale bytes that trigger a compaction
+    pub fn compaction_threshold(&self) -> u64 {
+        self.inner.compaction_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Sets how far writes go before they are acknowledged
+    pub fn set_durability(&self, durability: Durability) {
+        self.inner
+            .sync_writes
+            .store(durability == Durability::Sync, Ordering::Relaxed);
+    }
+
+    /// Returns how far writes go before they are acknowledged
+    pub fn durability(&self) -> Durability {
+        match self.inner.sync_writes.load(Ordering::Relaxed) {
+            true => Durability::Sync,
+            false => Durability::Flush,
+        }
+    }
+
+    /// Rejects keys longer than `bytes`, `None` accepts any key
+    pub fn set_max_key_bytes(&self, bytes: Option<u64>) {
+        store(&self.inner.max_key_bytes, bytes);
+    }
+
+    /// Returns the longest key accepted
+    pub fn max_key_bytes(&self) -> Option<u64> {
+        load(&self.inner.max_key_bytes)
+    }
+
+    /// Rejects values longer than `bytes`, `None` accepts any value
+    pub fn set_max_value_bytes(&self, bytes: Option<u64>) {
+        store(&self.inner.max_value_bytes, bytes);
+    }
+
+    /// Returns the longest value accepted
+    pub fn max_value_bytes(&self) -> Option<u64> {
+        load(&self.inner.max_value_bytes)
+    }
+
+    /// Fails with `LimitExceeded` when `key` or `value` is too long
+    pub(crate) fn check_limits(&self, key: &str, value: &str) -> Result<()> {
+        check_limit("key", key.len(), self.max_key_bytes())?;
+        check_limit("value", value.len(), self.max_value_bytes())
+    }
+}
+
+/// Fails with `LimitExceeded` when a `what` of `len` bytes is longer than `max`
+fn check_limit(what: &str, len: usize, max: Option<u64>) -> Result<()> {
+    match max {
+        Some(max) if len as u64 > max => Err(KvsError::LimitExceeded(format!(
+            "{what} of {len} bytes, at most {max} allowed"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn store(setting: &AtomicU64, value: Option<u64>) {
+    setting.store(value.unwrap_or(UNSET), Ordering::Relaxed);
+}
+
+fn load(setting: &AtomicU64) -> Option<u64> {
+    match setting.load(Ordering::Relaxed) {
+        UNSET => None,
+        value => Some(value),
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{ClientOptions, KvsClient, KvsError, Result};
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// A server holding part of the keys of a sharded deployment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    /// stable name of the shard, placement depends on it rather than on the address
+    pub name: String,
+    /// address of the server
+    pub addr: SocketAddr,
+}
+
+/// The shards of a sharded deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Topology {
+    /// shards in the cluster
+    pub shards: Vec<Shard>,
+    /// points each shard gets on the hash ring
+    #[serde(default = "default_virtual_nodes")]
+    pub virtual_nodes: u32,
+}
+
+fn default_virtual_nodes() -> u32 {
+    128
+}
+
+impl Topology {
+    /// Creates a topology with the default number of virtual nodes
+    pub fn new(shards: Vec<Shard>) -> Self {
+        Topology {
+            shards,
+            virtual_nodes: default_virtual_nodes(),
+        }
+    }
+
+    /// Reads a topology from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let topology: Topology = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        topology.validate()?;
+        Ok(topology)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.shards.is_empty() {
+            return Err(KvsError::InvalidTopology("no shard".to_owned()));
+        }
+        if self.virtual_nodes == 0 {
+            return Err(KvsError::InvalidTopology(
+                "virtual_nodes must be positive".to_owned(),
+            ));
+        }
+        let mut names: Vec<_> = self.shards.iter().map(|shard| &shard.name).collect();
+        names.sort();
+        if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(KvsError::InvalidTopology(format!(
+                "duplicate shard {}",
+                pair[0]
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Maps keys to shards with consistent hashing
+///
+/// Every shard is placed at `virtual_nodes` points of a ring of hashes,
+/// a key belongs to the first shard found clockwise from its own hash.
+/// Adding or removing a shard only moves the keys next to its points.
+pub struct HashRing {
+    points: BTreeMap<u64, usize>,
+    shards: Vec<Shard>,
+}
+
+impl HashRing {
+    /// Builds the ring of a topology
+    pub fn new(topology: &Topology) -> Result<Self> {
+        topology.validate()?;
+        let mut points = BTreeMap::new();
+        for (position, shard) in topology.shards.iter().enumerate() {
+            for vnode in 0..topology.virtual_nodes {
+                points.insert(hash(format!("{}#{vnode}", shard.name).as_bytes()), position);
+            }
+        }
+        Ok(HashRing {
+            points,
+            shards: topology.shards.clone(),
+        })
+    }
+
+    /// Shard owning `key`
+    pub fn owner(&self, key: &str) -> &Shard {
+        let point = hash(key.as_bytes());
+        let (_, &position) = self
+            .points
+            .range(point..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .expect("ring has no point");
+        &self.shards[position]
+    }
+}
+
+/// 64-bit FNV-1a followed by a splitmix64 finalizer, stable across
+/// releases and platforms unlike the std hasher
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// A client routing every request to the shard owning its key
+pub struct ShardedKvsClient {
+    ring: HashRing,
+    clients: HashMap<String, KvsClient>,
+    options: ClientOptions,
+}
+
+impl ShardedKvsClient {
+    /// Creates a client for a topology, connections are opened on first use
+    /// and wait on the shards as long as it takes
+    pub fn new(topology: &Topology) -> Result<Self> {
+        ShardedKvsClient::with_options(topology, ClientOptions::without_timeouts())
+    }
+
+    /// Creates a client for a topology opening its connections with the
+    /// timeouts, TLS settings and credentials of `options`
+    pub fn with_options(topology: &Topology, options: ClientOptions) -> Result<Self> {
+        Ok(ShardedKvsClient {
+            ring: HashRing::new(topology)?,
+            clients: HashMap::new(),
+            options,
+        })
+    }
+
+    /// Gets a value by key
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.client_for(&key)?.get(key)
+    }
+
+    /// Sets a value for a key
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.client_for(&key)?.set(key, value)
+    }
+
+    /// Removes a key and its associated value
+    /// Returns an error if the key doesn't exist
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.client_for(&key)?.remove(key)
+    }
+
+    /// Gets every key/value pair whose key starts with `prefix` from all
+    /// the shards, sorted by key
+    ///
+    /// Shards served at the same address are only asked once.
+    pub fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+        let mut scanned: Vec<SocketAddr> = Vec::new();
+        for shard in &self.ring.shards {
+            if scanned.contains(&shard.addr) {
+                continue;
+            }
+            scanned.push(shard.addr);
+            let client = connected(&mut self.clients, shard, &self.options)?;
+            entries.extend(client.scan(prefix.clone())?);
+        }
+        // every shard answers in key order, the sort merges the runs
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entries)
+    }
+
+    fn client_for(&mut self, key: &str) -> Result<&mut KvsClient> {
+        connected(&mut self.clients, self.ring.owner(key), &self.options)
+    }
+}
+
+/// The client of `shard`, connected on first use
+fn connected<'a>(
+    clients: &'a mut HashMap<String, KvsClient>,
+    shard: &Shard,
+    options: &ClientOptions,
+) -> Result<&'a mut KvsClient> {
+    if !clients.contains_key(&shard.name) {
+        let client = KvsClient::connect_with(shard.addr, options)?;
+        clients.insert(shard.name.clone(), client);
+    }
+    Ok(clients.get_mut(&shard.name).expect("client was inserted"))
+}
+
+/// Moves every key whose owner differs between the `from` and `to`
+/// topologies, returns the number of keys moved
+///
+/// A key stays where it is when its new owner is served at the same
+/// address, even under another name. Every shard of both topologies must
+/// be reachable. Each key is written to its new owner before it is
+/// removed from the old one, so a key is never missing from both during
+/// the move, but writes made to a moved key while it is copied may be
+/// lost: pause writers while rebalancing.
+pub fn rebalance(from: &Topology, to: &Topology) -> Result<u64> {
+    rebalance_with(from, to, &ClientOptions::without_timeouts())
+}
+
+/// Same as `rebalance`, connecting to the shards with `options`
+pub fn rebalance_with(from: &Topology, to: &Topology, options: &ClientOptions) -> Result<u64> {
+    let target = HashRing::new(to)?;
+    let mut clients = ShardedKvsClient::with_options(to, options.clone())?;
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for shard in from.shards.iter().chain(&to.shards) {
+        if !addrs.contains(&shard.addr) {
+            addrs.push(shard.addr);
+        }
+    }
+
+    let mut moved = 0;
+    for addr in addrs {
+        let mut source = KvsClient::connect_with(addr, options)?;
+        for (key, value) in source.scan(String::new())? {
+            if target.owner(&key).addr == addr {
+                continue;
+            }
+            clients.set(key.clone(), value)?;
+            source.remove(key)?;
+            moved += 1;
+        }
+    }
+    Ok(moved)
+}
@@ -0,0 +1,295 @@
+use crate::metrics::OPS;
+use crate::{KvsClient, KvsEngine, KvsError, Result, StoreOp};
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Commands of the shell, with their arguments, as printed by `help`
+const HELP: &str = "\
+get <key>            print the value of a key
+set <key> <value>    set the value of a key
+rm <key>             remove a key
+scan [prefix]        print the keys starting with prefix, and their values
+stats                print what this session did
+help                 print this help
+exit                 leave the shell, as does Ctrl-D
+Quote keys and values containing spaces with \"double quotes\".";
+
+/// What the commands of a shell run against, an open store or a connection
+pub trait ShellTarget {
+    /// Gets the value of a key
+    fn get(&mut self, key: String) -> Result<Option<String>>;
+    /// Sets the value of a key
+    fn set(&mut self, key: String, value: String) -> Result<()>;
+    /// Removes a key
+    fn remove(&mut self, key: String) -> Result<()>;
+    /// Lists the keys starting with `prefix` and their values, in key order
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>>;
+    /// Describes the target for `stats`, as names and values
+    fn describe(&mut self) -> Vec<(&'static str, String)>;
+}
+
+impl<E: KvsEngine> ShellTarget for E {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        KvsEngine::get(self, key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KvsEngine::set(self, key, value)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        KvsEngine::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        KvsEngine::scan(self, prefix)
+    }
+
+    fn describe(&mut self) -> Vec<(&'static str, String)> {
+        let mut lines = vec![("engine", E::NAME.to_owned())];
+        if let Some(store) = self.as_kv_store() {
+            lines.push(("sequence", store.sequence().to_string()));
+        }
+        lines
+    }
+}
+
+impl ShellTarget for KvsClient {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        KvsClient::get(self, key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KvsClient::set(self, key, value)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        KvsClient::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        KvsClient::scan(self, prefix.to_owned())
+    }
+
+    fn describe(&mut self) -> Vec<(&'static str, String)> {
+        let server = match self.peer_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(e) => format!("unknown ({e})"),
+        };
+        vec![("server", server)]
+    }
+}
+
+/// Calls of one kind of command in a session and the time they took
+#[derive(Default, Clone, Copy)]
+struct Calls {
+    count: u64,
+    errors: u64,
+    elapsed: Duration,
+}
+
+/// An interactive session running many commands against one target
+///
+/// Lines are read with line editing, and kept in a history file across
+/// sessions when one is given. When stdin is not a terminal the lines are
+/// read as they come, so commands can be piped in.
+pub struct Shell<T: ShellTarget> {
+    target: T,
+    history: Option<PathBuf>,
+    calls: [Calls; OPS.len()],
+    started: Instant,
+}
+
+impl<T: ShellTarget> Shell<T> {
+    /// Creates a shell running its commands against `target`
+    pub fn new(target: T) -> Self {
+        Shell {
+            target,
+            history: None,
+            calls: [Calls::default(); OPS.len()],
+            started: Instant::now(),
+        }
+    }
+
+    /// Loads the history from `path` and saves it back there on exit
+    pub fn with_history(mut self, path: impl Into<PathBuf>) -> Self {
+        self.history = Some(path.into());
+        self
+    }
+
+    /// Reads and runs commands until `exit` or the end of input
+    pub fn run(&mut self, prompt: &str) -> Result<()> {
+        let mut editor = DefaultEditor::new().map_err(readline_error)?;
+        if let Some(path) = &self.history {
+            // missing on the first session
+            let _ = editor.load_history(path);
+        }
+        let mut stdout = std::io::stdout();
+        loop {
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                // Ctrl-C drops the line being typed
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(readline_error(e)),
+            };
+            if !line.trim().is_empty() {
+                editor.add_history_entry(&line).map_err(readline_error)?;
+            }
+            if !self.execute(&line, &mut stdout)? {
+                break;
+            }
+        }
+        if let Some(path) = &self.history {
+            editor.save_history(path).map_err(readline_error)?;
+        }
+        Ok(())
+    }
+
+    /// Runs one line, returning false when it asks to leave the shell
+    ///
+    /// Failed commands are reported on stderr and the session goes on, only
+    /// failing to write to `out` is an error.
+    pub fn execute(&mut self, line: &str, out: &mut impl Write) -> Result<bool> {
+        let words = match split(line) {
+            Ok(words) => words,
+            Err(message) => {
+                eprintln!("Error: {message}");
+                return Ok(true);
+            }
+        };
+        let Some((command, args)) = words.split_first() else {
+            return Ok(true);
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match (command.as_str(), args.as_slice()) {
+            ("get", [key]) => {
+                match self.timed(StoreOp::Get, |target| target.get(key.to_string())) {
+                    Some(Some(value)) => writeln!(out, "{value}")?,
+                    Some(None) => writeln!(out, "Key not found")?,
+                    None => {}
+                }
+            }
+            ("set", [key, value]) => {
+                let (key, value) = (key.to_string(), value.to_string());
+                if self
+                    .timed(StoreOp::Set, |target| target.set(key, value))
+                    .is_some()
+                {
+                    writeln!(out, "OK")?;
+                }
+            }
+            ("rm", [key]) => {
+                match self.timed(StoreOp::Remove, |target| {
+                    match target.remove(key.to_string()) {
+                        Ok(()) => Ok(true),
+                        Err(KvsError::KeyNotFound) => Ok(false),
+                        Err(e) => Err(e),
+                    }
+                }) {
+                    Some(true) => writeln!(out, "OK")?,
+                    Some(false) => writeln!(out, "Key not found")?,
+                    None => {}
+                }
+            }
+            ("scan", [] | [_]) => {
+                let prefix = args.first().copied().unwrap_or_default();
+                if let Some(entries) = self.timed(StoreOp::Scan, |target| target.scan(prefix)) {
+                    for (key, value) in &entries {
+                        writeln!(out, "{key} {value}")?;
+                    }
+                    writeln!(out, "({} keys)", entries.len())?;
+                }
+            }
+            ("stats", []) => self.stats(out)?,
+            ("help", []) => writeln!(out, "{HELP}")?,
+            ("exit" | "quit", []) => return Ok(false),
+            ("get" | "set" | "rm" | "scan" | "stats" | "help" | "exit" | "quit", _) => {
+                eprintln!("Error: wrong number of arguments, see `help`");
+            }
+            (command, _) => eprintln!("Error: unknown command `{command}`, see `help`"),
+        }
+        Ok(true)
+    }
+
+    /// Runs `f` against the target and counts the call, reporting its error
+    fn timed<R>(&mut self, op: StoreOp, f: impl FnOnce(&mut T) -> Result<R>) -> Option<R> {
+        let start = Instant::now();
+        let result = f(&mut self.target);
+        let calls = &mut self.calls[op as usize];
+        calls.count += 1;
+        calls.elapsed += start.elapsed();
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                calls.errors += 1;
+                eprintln!("Error: {e}");
+                None
+            }
+        }
+    }
+
+    fn stats(&mut self, out: &mut impl Write) -> Result<()> {
+        for (name, value) in self.target.describe() {
+            writeln!(out, "{name}: {value}")?;
+        }
+        writeln!(out, "session: {}s", self.started.elapsed().as_secs())?;
+        for op in [StoreOp::Get, StoreOp::Set, StoreOp::Remove, StoreOp::Scan] {
+            let calls = self.calls[op as usize];
+            let mean = match calls.count {
+                0 => 0,
+                count => calls.elapsed.as_micros() / u128::from(count),
+            };
+            writeln!(
+                out,
+                "{}: {} calls, {} errors, {}µs mean",
+                op.name(),
+                calls.count,
+                calls.errors,
+                mean
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a line into words on whitespace, double quotes keeping a word
+/// together and `\` escaping the next character inside them
+fn split(line: &str) -> std::result::Result<Vec<String>, &'static str> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(words);
+        };
+        let mut word = String::new();
+        if first == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => word.extend(chars.next()),
+                    Some(c) => word.push(c),
+                    None => return Err("unterminated quote"),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+}
+
+fn readline_error(e: ReadlineError) -> KvsError {
+    match e {
+        ReadlineError::Io(e) => KvsError::IoError(e),
+        e => KvsError::IoError(std::io::Error::other(e)),
+    }
+}
@@ -0,0 +1,61 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Asks a running KvsServer or AsyncKvsServer to shut down
+///
+/// The server stops accepting connections, lets the requests it already
+/// received finish, syncs the store to disk and returns from `run`.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<State>,
+}
+
+struct State {
+    requested: watch::Sender<bool>,
+    // address a blocking accept loop can be woken up through
+    wake: Mutex<Option<SocketAddr>>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> Self {
+        ShutdownHandle {
+            state: Arc::new(State {
+                requested: watch::Sender::new(false),
+                wake: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Starts shutting the server down, returns without waiting for it
+    pub fn shutdown(&self) {
+        self.state.requested.send_replace(true);
+        if let Some(addr) = *self.state.wake.lock().expect("shutdown lock poisoned") {
+            // the accept loop sees the flag once this connection comes in
+            let _ = TcpStream::connect(addr);
+        }
+    }
+
+    /// Whether a shutdown was requested
+    pub fn is_shutdown(&self) -> bool {
+        *self.state.requested.borrow()
+    }
+
+    /// Makes `shutdown` connect to the server listening on `addr`
+    pub(crate) fn wake_through(&self, addr: SocketAddr) {
+        let ip = match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        *self.state.wake.lock().expect("shutdown lock poisoned") =
+            Some(SocketAddr::new(ip, addr.port()));
+    }
+
+    /// Waits until a shutdown is requested
+    pub(crate) async fn requested(&self) {
+        let mut requested = self.state.requested.subscribe();
+        // the sender lives as long as self
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+}
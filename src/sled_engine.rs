@@ -0,0 +1,154 @@
+use crate::engine::{check_engine, claim_directory};
+use crate::kv::{LOCK_RETRY, LOCK_TIMEOUT};
+use crate::metrics::{Metrics, Outcome, StoreOp};
+use crate::settings::{Durability, StoreSettings};
+use crate::{KvsEngine, KvsError, Result};
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+/// A storage engine backed by the sled embedded database, to compare
+/// `KvStore` against
+///
+/// Writes are flushed to disk when the durability is `Sync`, otherwise
+/// sled flushes them in the background. The size limits of the settings
+/// apply, the compaction and slow log thresholds do not.
+pub struct SledKvsEngine {
+    db: sled::Db,
+    metrics: Arc<Metrics>,
+    settings: StoreSettings,
+}
+
+impl SledKvsEngine {
+    /// Opens the sled database in a given directory, creating it if needed
+    pub fn open(path: impl Into<PathBuf>) -> Result<SledKvsEngine> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        check_engine(&path, Self::NAME)?;
+        let db = open_db(&path)?;
+        claim_directory(&path, Self::NAME)?;
+        Ok(SledKvsEngine {
+            db,
+            metrics: Arc::new(Metrics::default()),
+            settings: StoreSettings::default(),
+        })
+    }
+
+    /// Flushes the writes just made when the engine is durable
+    fn commit(&self) -> Result<()> {
+        if self.settings.durability() == Durability::Sync {
+            self.db.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Reports a finished operation to the metrics
+    fn observe<T>(&self, op: StoreOp, start: Instant, result: &Result<T>) {
+        self.metrics
+            .observe(op, Outcome::of(result, |_| true), start.elapsed());
+    }
+}
+
+impl KvsEngine for SledKvsEngine {
+    const NAME: &'static str = "sled";
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        let start = Instant::now();
+        let result = self.settings.check_limits(&key, &value).and_then(|_| {
+            self.db.insert(key.as_bytes(), value.as_bytes())?;
+            self.metrics.wrote((key.len() + value.len()) as u64);
+            self.commit()
+        });
+        self.observe(StoreOp::Set, start, &result);
+        result
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        let start = Instant::now();
+        let result = match self.db.get(key) {
+            Ok(Some(value)) => to_string(&value).map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e.into()),
+        };
+        self.metrics.observe(
+            StoreOp::Get,
+            Outcome::of(&result, Option::is_some),
+            start.elapsed(),
+        );
+        result
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        let start = Instant::now();
+        let result = match self.db.remove(key) {
+            Ok(Some(_)) => self.commit(),
+            Ok(None) => Err(KvsError::KeyNotFound),
+            Err(e) => Err(e.into()),
+        };
+        self.observe(StoreOp::Remove, start, &result);
+        result
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let start = Instant::now();
+        let result = self
+            .db
+            .scan_prefix(prefix)
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((to_string(&key)?, to_string(&value)?))
+            })
+            .collect();
+        self.observe(StoreOp::Scan, start, &result);
+        result
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.db.flush().map(|_| ()).map_err(KvsError::from);
+        self.observe(StoreOp::Sync, start, &result);
+        result
+    }
+
+    fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    fn settings(&self) -> StoreSettings {
+        self.settings.clone()
+    }
+}
+
+/// Decodes a key or value, all of them are written as UTF-8
+fn to_string(bytes: &sled::IVec) -> Result<String> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
+}
+
+/// Opens the database in `path`, waiting up to `LOCK_TIMEOUT` for its lock
+///
+/// The background threads of a database just dropped keep its lock until
+/// they are done with it.
+fn open_db(path: &Path) -> Result<sled::Db> {
+    let deadline = Instant::now() + LOCK_TIMEOUT;
+    loop {
+        match sled::open(path) {
+            Err(sled::Error::Io(e)) if is_lock_error(&e) => {
+                if Instant::now() >= deadline {
+                    return Err(KvsError::DirectoryLocked(path.to_owned()));
+                }
+                thread::sleep(LOCK_RETRY);
+            }
+            db => return Ok(db?),
+        }
+    }
+}
+
+/// Whether sled failed to take the lock of the database, which it only
+/// tells in the message
+fn is_lock_error(error: &std::io::Error) -> bool {
+    error.kind() == ErrorKind::Other && error.to_string().starts_with("could not acquire lock")
+}
@@ -0,0 +1,67 @@
+use crate::metrics::StoreOp;
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tracing::warn;
+
+/// Entries kept by the slow log, the oldest are dropped first
+const SLOW_LOG_LEN: usize = 128;
+
+/// A store operation that took longer than the slow log threshold
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlowEntry {
+    /// increasing number of the entry, never reused
+    pub id: u64,
+    /// when the operation started, in milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// what the operation was
+    pub op: StoreOp,
+    /// key of the operation or prefix of a scan, `None` for a sync
+    pub key: Option<String>,
+    /// how long the operation took
+    pub duration: Duration,
+    /// whether the operation compacted the log
+    pub compacted: bool,
+    /// whether the operation waited for the log to reach the disk
+    pub synced: bool,
+}
+
+/// The slowest recent operations of a store
+#[derive(Default)]
+pub(crate) struct SlowLog {
+    entries: VecDeque<SlowEntry>,
+    next_id: u64,
+}
+
+impl SlowLog {
+    /// Keeps `entry` when it took at least `threshold`, its id is assigned here
+    pub(crate) fn record(&mut self, mut entry: SlowEntry, threshold: Option<Duration>) {
+        if threshold.is_none_or(|threshold| entry.duration < threshold) {
+            return;
+        }
+        entry.id = self.next_id;
+        self.next_id += 1;
+        warn!(
+            op = entry.op.name(),
+            key = entry.key.as_deref(),
+            duration = ?entry.duration,
+            compacted = entry.compacted,
+            synced = entry.synced,
+            "slow operation"
+        );
+        if self.entries.len() == SLOW_LOG_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Returns up to `count` entries, newest first
+    pub(crate) fn entries(&self, count: usize) -> Vec<SlowEntry> {
+        self.entries.iter().rev().take(count).cloned().collect()
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,26 @@
+//! Thread pools running the jobs of the server
+
+use crate::Result;
+
+mod naive;
+mod rayon;
+mod shared_queue;
+
+pub use self::naive::NaiveThreadPool;
+pub use self::rayon::RayonThreadPool;
+pub use self::shared_queue::SharedQueueThreadPool;
+
+/// A pool of threads running jobs concurrently
+pub trait ThreadPool: Send + 'static {
+    /// Creates a pool running up to `threads` jobs at once
+    fn new(threads: u32) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Runs `job` on a thread of the pool
+    ///
+    /// A job panicking does not take the pool down with it.
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static;
+}
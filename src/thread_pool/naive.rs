@@ -0,0 +1,20 @@
+use super::ThreadPool;
+use crate::Result;
+
+use std::thread;
+
+/// Not a pool at all, every job gets a thread of its own
+pub struct NaiveThreadPool;
+
+impl ThreadPool for NaiveThreadPool {
+    fn new(_threads: u32) -> Result<Self> {
+        Ok(NaiveThreadPool)
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        thread::spawn(job);
+    }
+}
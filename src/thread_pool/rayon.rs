@@ -0,0 +1,25 @@
+use super::ThreadPool;
+use crate::{KvsError, Result};
+
+/// A work-stealing pool backed by rayon, every worker has its own queue
+/// and takes jobs from the others once it is empty
+pub struct RayonThreadPool(rayon::ThreadPool);
+
+impl ThreadPool for RayonThreadPool {
+    fn new(threads: u32) -> Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1) as usize)
+            .thread_name(|_| "kvs-worker".to_owned())
+            .panic_handler(|_| {})
+            .build()
+            .map_err(|e| KvsError::ThreadPoolError(e.to_string()))?;
+        Ok(RayonThreadPool(pool))
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.0.spawn(job);
+    }
+}
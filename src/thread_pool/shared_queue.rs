@@ -0,0 +1,70 @@
+use super::ThreadPool;
+use crate::Result;
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::error;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of workers taking jobs from a single queue
+///
+/// A worker whose job panics is replaced by a new one, the pool keeps
+/// its size. Workers exit once the pool is dropped and the queue drained.
+pub struct SharedQueueThreadPool {
+    sender: Sender<Job>,
+}
+
+impl ThreadPool for SharedQueueThreadPool {
+    fn new(threads: u32) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..threads.max(1) {
+            spawn_worker(Worker(Arc::clone(&receiver)))?;
+        }
+        Ok(SharedQueueThreadPool { sender })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender
+            .send(Box::new(job))
+            .expect("thread pool has no worker");
+    }
+}
+
+fn spawn_worker(worker: Worker) -> Result<()> {
+    thread::Builder::new()
+        .name("kvs-worker".to_owned())
+        .spawn(move || worker.run())?;
+    Ok(())
+}
+
+struct Worker(Arc<Mutex<Receiver<Job>>>);
+
+impl Worker {
+    fn run(&self) {
+        loop {
+            // the lock is released before the job runs
+            let job = self.0.lock().expect("job queue lock poisoned").recv();
+            match job {
+                Ok(job) => job(),
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let worker = Worker(Arc::clone(&self.0));
+            if let Err(e) = spawn_worker(worker) {
+                error!(error = %e, "failed to replace worker");
+            }
+        }
+    }
+}
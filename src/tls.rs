@@ -0,0 +1,298 @@
+use crate::{KvsError, Result};
+
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{
+    ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection,
+};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// TLS settings of a server: the certificate it presents and, optionally,
+/// the authority client certificates must be signed by
+#[derive(Debug, Clone)]
+pub struct ServerTls {
+    config: Arc<ServerConfig>,
+}
+
+impl ServerTls {
+    /// Loads the PEM certificate chain and private key of the server
+    ///
+    /// With a `client_ca`, clients must present a certificate signed by it.
+    pub fn load(
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+        client_ca: Option<&Path>,
+    ) -> Result<Self> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+        let builder = match client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(load_roots(ca)?),
+                    provider(),
+                )
+                .build()
+                .map_err(|e| KvsError::TlsError(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(load_certs(cert)?, load_key(key)?)?;
+        Ok(ServerTls {
+            config: Arc::new(config),
+        })
+    }
+
+    pub(crate) fn config(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.config)
+    }
+
+    /// Wraps an accepted connection once the handshake is done
+    pub(crate) fn accept(&self, stream: TcpStream) -> Result<Stream> {
+        let connection = ServerConnection::new(self.config())?;
+        TlsStream::handshake(connection.into(), stream)
+    }
+}
+
+/// TLS settings of a client: the authority server certificates must be
+/// signed by, the name they must carry and, optionally, the certificate
+/// the client presents
+#[derive(Debug, Clone)]
+pub struct ClientTls {
+    config: Arc<ClientConfig>,
+    roots: Arc<RootCertStore>,
+    server_name: ServerName<'static>,
+}
+
+impl ClientTls {
+    /// Trusts servers with a certificate signed by the PEM authority `ca`
+    /// and issued for `server_name`, a DNS name or an IP address
+    pub fn load(ca: impl AsRef<Path>, server_name: &str) -> Result<Self> {
+        let roots = Arc::new(load_roots(ca.as_ref())?);
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(Arc::clone(&roots))
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(server_name.to_owned())
+            .map_err(|e| KvsError::TlsError(format!("invalid server name {server_name}: {e}")))?;
+        Ok(ClientTls {
+            config: Arc::new(config),
+            roots,
+            server_name,
+        })
+    }
+
+    /// Presents the PEM certificate chain and private key to servers
+    /// verifying client certificates
+    pub fn with_identity(mut self, cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self> {
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(Arc::clone(&self.roots))
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)?;
+        self.config = Arc::new(config);
+        Ok(self)
+    }
+
+    pub(crate) fn config(&self) -> Arc<ClientConfig> {
+        Arc::clone(&self.config)
+    }
+
+    pub(crate) fn server_name(&self) -> ServerName<'static> {
+        self.server_name.clone()
+    }
+
+    /// Wraps a connection to a server once the handshake is done
+    pub(crate) fn connect(&self, stream: TcpStream) -> Result<Stream> {
+        let connection = ClientConnection::new(self.config(), self.server_name())?;
+        TlsStream::handshake(connection.into(), stream)
+    }
+}
+
+/// Ciphertext read from the socket at once, small enough for rustls to
+/// take whole
+const RECORD_BUFFER: usize = 8 * 1024;
+
+/// A connection, in plain text or encrypted
+///
+/// Clones share the connection, one of them may read while another writes.
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    Tls(Arc<TlsStream>),
+}
+
+impl Stream {
+    pub(crate) fn try_clone(&self) -> Result<Stream> {
+        Ok(match self {
+            Stream::Plain(stream) => Stream::Plain(stream.try_clone()?),
+            Stream::Tls(stream) => Stream::Tls(Arc::clone(stream)),
+        })
+    }
+
+    pub(crate) fn peer_addr(&self) -> Result<SocketAddr> {
+        let sock = match self {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => &stream.sock,
+        };
+        Ok(sock.peer_addr()?)
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.set_read_timeout(timeout)?,
+            Stream::Tls(stream) => stream.sock.set_read_timeout(timeout)?,
+        }
+        Ok(())
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write_with(|connection| connection.writer().write(buf)),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            // records are written to the socket as soon as they are sealed
+            Stream::Tls(_) => Ok(()),
+        }
+    }
+}
+
+/// A TLS connection over a socket
+///
+/// The socket is read from and written to without holding the lock of the
+/// connection, so a thread waiting for records does not hold up another
+/// sending them. `output` keeps the records sent by both in order. The
+/// peer is sent a close_notify once the last clone of the stream is gone.
+pub(crate) struct TlsStream {
+    connection: Mutex<Connection>,
+    output: Mutex<()>,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    fn handshake(mut connection: Connection, mut sock: TcpStream) -> Result<Stream> {
+        while connection.is_handshaking() {
+            if connection.complete_io(&mut sock)? == (0, 0) {
+                return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+            }
+        }
+        Ok(Stream::Tls(Arc::new(TlsStream {
+            connection: Mutex::new(connection),
+            output: Mutex::new(()),
+            sock,
+        })))
+    }
+
+    fn read(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            // fails with UnexpectedEof when the peer closed without a close_notify
+            match lock(&self.connection).reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                read => return read,
+            }
+            let mut records = [0; RECORD_BUFFER];
+            let read = (&self.sock).read(&mut records)?;
+            let mut records = &records[..read];
+            let mut connection = lock(&self.connection);
+            // an empty read records the end of the stream
+            let processed = loop {
+                connection.read_tls(&mut records)?;
+                let processed = connection.process_new_packets();
+                if processed.is_err() || records.is_empty() {
+                    break processed;
+                }
+            };
+            let replies = connection.wants_write();
+            drop(connection);
+            if replies {
+                self.write_with(|_| Ok(()))?;
+            }
+            processed.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        }
+    }
+
+    /// Runs `f` on the connection and sends the records it sealed
+    fn write_with<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        let _output = lock(&self.output);
+        let mut records = Vec::new();
+        let result = {
+            let mut connection = lock(&self.connection);
+            let result = f(&mut connection)?;
+            while connection.wants_write() {
+                connection.write_tls(&mut records)?;
+            }
+            result
+        };
+        (&self.sock).write_all(&records)?;
+        Ok(result)
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        // the peer may be gone already
+        let _ = self.write_with(|connection| {
+            connection.send_close_notify();
+            Ok(())
+        });
+    }
+}
+
+fn lock<T>(stream: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    stream.lock().expect("connection lock poisoned")
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(path: impl AsRef<Path>) -> Result<Vec<CertificateDer<'static>>> {
+    let path = path.as_ref();
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| KvsError::TlsError(format!("{}: {e}", path.display())))?;
+    if certs.is_empty() {
+        return Err(KvsError::TlsError(format!(
+            "{}: no certificate",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: impl AsRef<Path>) -> Result<PrivateKeyDer<'static>> {
+    let path = path.as_ref();
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| KvsError::TlsError(format!("{}: {e}", path.display())))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
@@ -0,0 +1,301 @@
+use crate::Result;
+use crate::engine::{KVS_ENGINE, check_engine, engine_path};
+use crate::kv::{
+    LogEntry, history_path, lock_directory, lock_path, log_path, meta_path, read_meta,
+    sorted_generation_list, write_meta,
+};
+
+use serde_json::Deserializer;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// a byte range of a log file that does not hold a readable record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptRange {
+    /// generation of the damaged log file
+    pub generation: u64,
+    /// byte range that could not be parsed
+    pub range: Range<u64>,
+}
+
+/// a `Remove` record for a key that had no value when it was replayed,
+/// usually a sign that the matching `Set` was lost
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DanglingEntry {
+    /// generation holding the record
+    pub generation: u64,
+    /// offset of the record in the log file
+    pub pos: u64,
+    /// key the record refers to
+    pub key: String,
+}
+
+/// Result of an offline integrity check
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// generations found in the directory, in replay order
+    pub generations: Vec<u64>,
+    /// number of records that were read successfully
+    pub records: u64,
+    /// number of keys holding a value after replaying every readable record
+    pub live_keys: u64,
+    /// unreadable byte ranges
+    pub corrupt: Vec<CorruptRange>,
+    /// removals of keys that had no value
+    pub dangling: Vec<DanglingEntry>,
+    /// files in the directory that are not log files
+    pub unexpected_files: Vec<PathBuf>,
+    /// generations retained in `history/` by `retain_history`, oldest first
+    pub history_generations: Vec<u64>,
+    /// number of records of the retained generations that were read successfully
+    pub history_records: u64,
+    /// unreadable byte ranges of the retained generations, which `repair`
+    /// leaves alone
+    pub history_corrupt: Vec<CorruptRange>,
+}
+
+impl VerifyReport {
+    /// true when no corruption, dangling entry or unexpected file was found
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty()
+            && self.dangling.is_empty()
+            && self.unexpected_files.is_empty()
+            && self.history_corrupt.is_empty()
+    }
+}
+
+/// Result of salvaging a damaged store
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// number of records that were read successfully
+    pub salvaged_records: u64,
+    /// number of keys written to the fresh generation
+    pub live_keys: u64,
+    /// unreadable byte ranges that were dropped
+    pub dropped: Vec<CorruptRange>,
+    /// generation holding the salvaged data
+    pub generation: u64,
+    /// generations that were replaced
+    pub removed_generations: Vec<u64>,
+}
+
+/// a single step of a log scan: either a parsed record or a damaged range
+enum Scanned {
+    Record(Range<u64>, LogEntry),
+    Corrupt(Range<u64>),
+}
+
+/// Walks every generation in `path` and validates every record
+/// without modifying the directory
+///
+/// The generations retained in `history/` are checked for damage too,
+/// `AsOf` reads and exports depend on them. They hold what compaction
+/// dropped, so they take no part in finding live keys or dangling removals.
+pub fn verify(path: impl AsRef<Path>) -> Result<VerifyReport> {
+    let path = path.as_ref();
+    check_engine(path, KVS_ENGINE)?;
+    let mut report = VerifyReport {
+        generations: sorted_generation_list(path)?,
+        unexpected_files: unexpected_files(path)?,
+        ..Default::default()
+    };
+
+    let mut live = BTreeSet::new();
+    for &generation in &report.generations {
+        let buf = std::fs::read(log_path(path, generation))?;
+        for scanned in scan_log(&buf) {
+            match scanned {
+                Scanned::Record(range, entry) => {
+                    report.records += 1;
+                    match entry {
+                        LogEntry::Set { key, .. } => {
+                            live.insert(key);
+                        }
+                        LogEntry::Remove { key, .. } => {
+                            if !live.remove(&key) {
+                                report.dangling.push(DanglingEntry {
+                                    generation,
+                                    pos: range.start,
+                                    key,
+                                });
+                            }
+                        }
+                    }
+                }
+                Scanned::Corrupt(range) => report.corrupt.push(CorruptRange { generation, range }),
+            }
+        }
+    }
+    report.live_keys = live.len() as u64;
+
+    let history = history_path(path);
+    if history.is_dir() {
+        report.history_generations = sorted_generation_list(&history)?;
+        report
+            .unexpected_files
+            .extend(unexpected_history_files(&history)?);
+        for &generation in &report.history_generations {
+            let buf = std::fs::read(log_path(&history, generation))?;
+            for scanned in scan_log(&buf) {
+                match scanned {
+                    Scanned::Record(..) => report.history_records += 1,
+                    Scanned::Corrupt(range) => report
+                        .history_corrupt
+                        .push(CorruptRange { generation, range }),
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Salvages every readable record of the store in `path` into a fresh
+/// generation and deletes the old log files
+///
+/// The store must not be open while it is repaired.
+pub fn repair(path: impl AsRef<Path>) -> Result<RepairReport> {
+    let path = path.as_ref();
+    check_engine(path, KVS_ENGINE)?;
+    let _lock = lock_directory(path)?;
+    let generations = sorted_generation_list(path)?;
+    let mut report = RepairReport {
+        generation: generations.last().unwrap_or(&0) + 1,
+        ..Default::default()
+    };
+
+    let mut values = BTreeMap::new();
+    let mut meta = read_meta(path)?.unwrap_or_default();
+    for &generation in &generations {
+        let buf = std::fs::read(log_path(path, generation))?;
+        for scanned in scan_log(&buf) {
+            match scanned {
+                Scanned::Record(
+                    _,
+                    LogEntry::Set {
+                        key,
+                        value,
+                        seq,
+                        ts,
+                    },
+                ) => {
+                    report.salvaged_records += 1;
+                    meta.seq = meta.seq.max(seq);
+                    values.insert(key, (value, seq, ts));
+                }
+                Scanned::Record(_, LogEntry::Remove { key, seq, .. }) => {
+                    report.salvaged_records += 1;
+                    meta.seq = meta.seq.max(seq);
+                    values.remove(&key);
+                }
+                Scanned::Corrupt(range) => report.dropped.push(CorruptRange { generation, range }),
+            }
+        }
+    }
+
+    let file = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(log_path(path, report.generation))?;
+    let mut writer = BufWriter::new(file);
+    for (key, (value, seq, ts)) in values {
+        serde_json::to_writer(
+            &mut writer,
+            &LogEntry::Set {
+                key,
+                value,
+                seq,
+                ts,
+            },
+        )?;
+        report.live_keys += 1;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    // the removes dropped here may hold the highest sequence number,
+    // and followers can no longer replay what came before
+    meta.compacted_through = meta.seq;
+    write_meta(path, &meta)?;
+
+    for generation in generations {
+        std::fs::remove_file(log_path(path, generation))?;
+        report.removed_generations.push(generation);
+    }
+    Ok(report)
+}
+
+/// Parses a whole log file, resynchronising on the next record
+/// boundary after every unreadable range
+fn scan_log(buf: &[u8]) -> Vec<Scanned> {
+    let mut scanned = Vec::new();
+    let mut pos = 0;
+    loop {
+        let mut stream = Deserializer::from_slice(&buf[pos..]).into_iter::<LogEntry>();
+        match stream.next() {
+            None => break,
+            Some(Ok(entry)) => {
+                let end = pos + stream.byte_offset();
+                let start = pos + leading_whitespace(&buf[pos..end]);
+                scanned.push(Scanned::Record(start as u64..end as u64, entry));
+                pos = end;
+            }
+            Some(Err(_)) => {
+                let next = next_record_start(buf, pos + 1).unwrap_or(buf.len());
+                scanned.push(Scanned::Corrupt(pos as u64..next as u64));
+                pos = next;
+            }
+        }
+    }
+    scanned
+}
+
+fn leading_whitespace(buf: &[u8]) -> usize {
+    buf.iter().take_while(|b| b.is_ascii_whitespace()).count()
+}
+
+/// offset of the next byte sequence that looks like the start of a record
+fn next_record_start(buf: &[u8], from: usize) -> Option<usize> {
+    const MARKERS: [&[u8]; 2] = [b"{\"Set\":", b"{\"Remove\":"];
+    (from..buf.len()).find(|&i| MARKERS.iter().any(|marker| buf[i..].starts_with(marker)))
+}
+
+fn unexpected_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut unexpected = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !is_log_file(&path)
+            && path != history_path(dir)
+            && path != lock_path(dir)
+            && path != engine_path(dir)
+            && path != meta_path(dir)
+        {
+            unexpected.push(path);
+        }
+    }
+    unexpected.sort();
+    Ok(unexpected)
+}
+
+fn unexpected_history_files(history: &Path) -> Result<Vec<PathBuf>> {
+    let mut unexpected = Vec::new();
+    for entry in std::fs::read_dir(history)? {
+        let path = entry?.path();
+        if !is_log_file(&path) {
+            unexpected.push(path);
+        }
+    }
+    unexpected.sort();
+    Ok(unexpected)
+}
+
+fn is_log_file(path: &Path) -> bool {
+    path.is_file()
+        && path.extension() == Some("log".as_ref())
+        && path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .is_some_and(|stem| stem.parse::<u64>().is_ok())
+}
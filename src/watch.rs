@@ -0,0 +1,108 @@
+use crate::kv::LogEntry;
+
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use tracing::warn;
+
+/// Changes a watcher may fall behind by before it is disconnected
+pub(crate) const WATCH_BUFFER: usize = 1024;
+
+/// A change applied to the store, as seen by a watcher
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatchEvent {
+    /// a key was set
+    Set {
+        /// sequence number of the change
+        seq: u64,
+        /// key that was set
+        key: String,
+        /// new value
+        value: String,
+    },
+    /// a key was removed
+    Remove {
+        /// sequence number of the change
+        seq: u64,
+        /// key that was removed
+        key: String,
+    },
+}
+
+impl WatchEvent {
+    /// Sequence number of the change
+    pub fn seq(&self) -> u64 {
+        match self {
+            WatchEvent::Set { seq, .. } | WatchEvent::Remove { seq, .. } => *seq,
+        }
+    }
+
+    /// Key affected by the change
+    pub fn key(&self) -> &str {
+        match self {
+            WatchEvent::Set { key, .. } | WatchEvent::Remove { key, .. } => key,
+        }
+    }
+}
+
+impl From<&LogEntry> for WatchEvent {
+    fn from(entry: &LogEntry) -> Self {
+        match entry {
+            LogEntry::Set {
+                key, value, seq, ..
+            } => WatchEvent::Set {
+                seq: *seq,
+                key: key.clone(),
+                value: value.clone(),
+            },
+            LogEntry::Remove { key, seq, .. } => WatchEvent::Remove {
+                seq: *seq,
+                key: key.clone(),
+            },
+        }
+    }
+}
+
+/// a subscription to the changes of keys starting with `prefix`
+pub(crate) struct Watcher {
+    pub(crate) prefix: String,
+    pub(crate) sink: Sink,
+}
+
+/// where a watcher wants its changes delivered
+pub(crate) enum Sink {
+    /// public watch events
+    Events(SyncSender<WatchEvent>),
+    /// raw log records, for replication
+    Records(SyncSender<LogEntry>),
+}
+
+/// Sends `entry` to every interested watcher and drops the watchers
+/// whose receiver is gone or who fell `WATCH_BUFFER` changes behind
+pub(crate) fn notify(watchers: &mut Vec<Watcher>, entry: &LogEntry) {
+    if watchers.is_empty() {
+        return;
+    }
+    watchers.retain(|watcher| {
+        if !entry.key().starts_with(&watcher.prefix) {
+            return true;
+        }
+        let sent = match &watcher.sink {
+            Sink::Events(sender) => sender.try_send(entry.into()).map_err(|e| is_full(&e)),
+            Sink::Records(sender) => sender.try_send(entry.clone()).map_err(|e| is_full(&e)),
+        };
+        match sent {
+            Ok(()) => true,
+            Err(full) => {
+                if full {
+                    warn!(prefix = %watcher.prefix, "disconnecting a watcher falling behind");
+                }
+                false
+            }
+        }
+    });
+}
+
+/// Whether the receiver fell behind, rather than went away
+fn is_full<T>(error: &TrySendError<T>) -> bool {
+    matches!(error, TrySendError::Full(_))
+}
@@ -0,0 +1,259 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{
+    AccessControl, Acl, AsyncKvsClient, AsyncKvsServer, ClientOptions, Credentials, Grant, KvStore,
+    KvsClient, KvsError, KvsServer, Permission, Result, User, hash_secret,
+};
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn hash(secret: &str) -> String {
+    hash_secret(secret).unwrap()
+}
+
+// alice reads everything and writes under "alice/", the bot only reads "public/",
+// the replica may follow the store
+fn acl() -> Acl {
+    Acl {
+        users: vec![
+            User {
+                name: "alice".to_owned(),
+                password_hash: Some(hash("secret")),
+                token_id: None,
+                token_hash: None,
+                grants: vec![
+                    Grant {
+                        prefix: String::new(),
+                        permission: Permission::Read,
+                    },
+                    Grant {
+                        prefix: "alice/".to_owned(),
+                        permission: Permission::Write,
+                    },
+                ],
+            },
+            User {
+                name: "bot".to_owned(),
+                password_hash: None,
+                token_id: Some("bot".to_owned()),
+                token_hash: Some(hash("bot.token-secret")),
+                grants: vec![Grant {
+                    prefix: "public/".to_owned(),
+                    permission: Permission::Read,
+                }],
+            },
+            User {
+                name: "replica".to_owned(),
+                password_hash: Some(hash("replica-secret")),
+                token_id: None,
+                token_hash: None,
+                grants: vec![Grant {
+                    prefix: String::new(),
+                    permission: Permission::Admin,
+                }],
+            },
+        ],
+    }
+}
+
+fn alice() -> Credentials {
+    Credentials::Password {
+        user: "alice".to_owned(),
+        password: "secret".to_owned(),
+    }
+}
+
+fn spawn_server(store_dir: &Path, acl: Acl) -> Result<(SocketAddr, AccessControl)> {
+    let addr = free_addr();
+    let server = KvsServer::new(KvStore::open(store_dir)?).with_acl(acl);
+    let access = server.access_control();
+    thread::spawn(move || server.run(addr).unwrap());
+    for _ in 0..100 {
+        if std::net::TcpStream::connect(addr).is_ok() {
+            return Ok((addr, access));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// Requests should need valid credentials and stay within the granted prefixes.
+#[test]
+fn password_and_prefix_grants() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (addr, _) = spawn_server(temp_dir.path(), acl())?;
+
+    let mut client = KvsClient::connect(addr)?;
+    assert!(matches!(
+        client.get("alice/key".to_owned()),
+        Err(KvsError::Unauthenticated)
+    ));
+    assert!(matches!(
+        client.authenticate(Credentials::Password {
+            user: "alice".to_owned(),
+            password: "wrong".to_owned(),
+        }),
+        Err(KvsError::Unauthenticated)
+    ));
+
+    let options = ClientOptions {
+        credentials: Some(alice()),
+        ..ClientOptions::default()
+    };
+    let mut client = KvsClient::connect_with(addr, &options)?;
+    client.set("alice/key".to_owned(), "value".to_owned())?;
+    assert_eq!(
+        client.get("alice/key".to_owned())?,
+        Some("value".to_owned())
+    );
+    assert!(matches!(
+        client.set("bob/key".to_owned(), "value".to_owned()),
+        Err(KvsError::Forbidden)
+    ));
+    assert!(matches!(client.shutdown(), Err(KvsError::Forbidden)));
+    Ok(())
+}
+
+// A reloaded ACL should apply to connections already authenticated.
+#[test]
+fn reload_applies_to_open_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (addr, access) = spawn_server(temp_dir.path(), acl())?;
+
+    let mut client = KvsClient::connect(addr)?;
+    client.authenticate(Credentials::Token("bot.token-secret".to_owned()))?;
+    assert_eq!(client.get("public/key".to_owned())?, None);
+    assert!(matches!(
+        client.get("alice/key".to_owned()),
+        Err(KvsError::Forbidden)
+    ));
+
+    let mut acl = acl();
+    acl.users[1].grants.clear();
+    access.reload(acl);
+    assert!(matches!(
+        client.get("public/key".to_owned()),
+        Err(KvsError::Forbidden)
+    ));
+    Ok(())
+}
+
+// A token should be looked up by its id, and stop working once the ACL
+// drops it even though it was verified moments before.
+#[test]
+fn tokens_found_by_id() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (addr, access) = spawn_server(temp_dir.path(), acl())?;
+    let mut client = KvsClient::connect(addr)?;
+
+    for token in [
+        "bot.wrong",
+        "other.token-secret",
+        "token-secret",
+        "bot-token-secret",
+    ] {
+        assert!(matches!(
+            client.authenticate(Credentials::Token(token.to_owned())),
+            Err(KvsError::Unauthenticated)
+        ));
+    }
+    client.authenticate(Credentials::Token("bot.token-secret".to_owned()))?;
+
+    let mut acl = acl();
+    acl.users[1].token_hash = Some(hash("bot.new-secret"));
+    access.reload(acl);
+    assert!(matches!(
+        client.authenticate(Credentials::Token("bot.token-secret".to_owned())),
+        Err(KvsError::Unauthenticated)
+    ));
+    client.authenticate(Credentials::Token("bot.new-secret".to_owned()))?;
+    Ok(())
+}
+
+// The async server should enforce the same rules.
+#[tokio::test(flavor = "multi_thread")]
+async fn async_server_enforces_acl() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let server = AsyncKvsServer::new(KvStore::open(temp_dir.path())?).with_acl(acl());
+    tokio::spawn(server.run(addr));
+    let mut client = loop {
+        if let Ok(client) = AsyncKvsClient::connect(addr).await {
+            break client;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+
+    assert!(matches!(
+        client.set("alice/key".to_owned(), "value".to_owned()).await,
+        Err(KvsError::Unauthenticated)
+    ));
+    client.authenticate(alice()).await?;
+    client
+        .set("alice/key".to_owned(), "value".to_owned())
+        .await?;
+    assert!(matches!(
+        client.remove("other".to_owned()).await,
+        Err(KvsError::Forbidden)
+    ));
+    Ok(())
+}
+
+// kvs-server should authenticate to a primary enforcing an ACL with the
+// credentials it is given.
+#[test]
+fn replica_authenticates_to_primary() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (primary, _) = spawn_server(temp_dir.path(), acl())?;
+    let options = ClientOptions {
+        credentials: Some(alice()),
+        ..ClientOptions::default()
+    };
+    KvsClient::connect_with(primary, &options)?.set("alice/key".to_owned(), "value".to_owned())?;
+
+    for mode in [None, Some("--async")] {
+        let replica_dir = TempDir::new().expect("unable to create temporary working directory");
+        let addr = free_addr();
+        let mut replica = Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(mode)
+            .arg("--addr")
+            .arg(addr.to_string())
+            .arg("--replica-of")
+            .arg(primary.to_string())
+            .args([
+                "--primary-user",
+                "replica",
+                "--primary-password",
+                "replica-secret",
+            ])
+            .current_dir(&replica_dir)
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let value = loop {
+            let value =
+                KvsClient::connect(addr).and_then(|mut client| client.get("alice/key".to_owned()));
+            match value {
+                Ok(Some(value)) => break value,
+                _ if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+                other => panic!("replica never caught up: {other:?}"),
+            }
+        };
+        replica.kill()?;
+        replica.wait()?;
+        assert_eq!(value, "value");
+    }
+    Ok(())
+}
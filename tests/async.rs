@@ -0,0 +1,94 @@
+use networked_kv_store::{AsyncKvsClient, AsyncKvsServer, KvStore, KvsClient, KvsError, Result};
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Starts an async server on a free port of the current runtime and returns its address.
+async fn spawn_server(store: KvStore) -> SocketAddr {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    tokio::spawn(AsyncKvsServer::new(store).run(addr));
+    for _ in 0..100 {
+        if AsyncKvsClient::connect(addr).await.is_ok() {
+            return addr;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("server did not start");
+}
+
+// Basic operations and errors should work through the async client and server.
+#[tokio::test(flavor = "multi_thread")]
+async fn async_roundtrip() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(KvStore::open(temp_dir.path())?).await;
+
+    let mut client = AsyncKvsClient::connect(addr).await?;
+    client.set("key1".to_owned(), "value1".to_owned()).await?;
+    assert_eq!(
+        client.get("key1".to_owned()).await?,
+        Some("value1".to_owned())
+    );
+    assert_eq!(client.get("key2".to_owned()).await?, None);
+    client.remove("key1".to_owned()).await?;
+    assert!(matches!(
+        client.remove("key1".to_owned()).await,
+        Err(KvsError::KeyNotFound)
+    ));
+    Ok(())
+}
+
+// Many idle connections should not keep other clients from being served.
+#[tokio::test(flavor = "multi_thread")]
+async fn many_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(KvStore::open(temp_dir.path())?).await;
+
+    let mut idle = Vec::new();
+    for _ in 0..500 {
+        idle.push(AsyncKvsClient::connect(addr).await?);
+    }
+    let tasks: Vec<_> = (0..16)
+        .map(|client_id| {
+            tokio::spawn(async move {
+                let mut client = AsyncKvsClient::connect(addr).await?;
+                for key_id in 0..20 {
+                    let key = format!("key{client_id}-{key_id}");
+                    client.set(key.clone(), key_id.to_string()).await?;
+                }
+                Ok::<_, KvsError>(())
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap()?;
+    }
+    let entries = idle[0].scan("key".to_owned()).await?;
+    assert_eq!(entries.len(), 16 * 20);
+    Ok(())
+}
+
+// The async server should stream watch events to synchronous clients.
+#[tokio::test(flavor = "multi_thread")]
+async fn watch_on_async_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(KvStore::open(temp_dir.path())?).await;
+
+    let mut events = AsyncKvsClient::connect(addr)
+        .await?
+        .watch("user:".to_owned(), None)
+        .await?;
+    let writer = tokio::task::spawn_blocking(move || -> Result<()> {
+        let mut client = KvsClient::connect(addr)?;
+        // give the subscription time to register
+        std::thread::sleep(Duration::from_millis(100));
+        client.set("order:1".to_owned(), "book".to_owned())?;
+        client.set("user:1".to_owned(), "alice".to_owned())
+    });
+    writer.await.unwrap()?;
+    let event = events.next_event().await.unwrap()?;
+    assert_eq!(event.key(), "user:1");
+    Ok(())
+}
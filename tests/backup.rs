@@ -0,0 +1,129 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{KvStore, KvsClient, KvsServer, Result};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::net::{SocketAddr, TcpListener};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Serves `store` on a free port and returns a client once it answers.
+fn spawn_server(store: KvStore) -> (SocketAddr, KvsClient) {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    thread::spawn(move || KvsServer::new(store).run(addr).unwrap());
+    for _ in 0..100 {
+        if let Ok(client) = KvsClient::connect(addr) {
+            return (addr, client);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// A checkpoint should hold every write before it and none after it.
+#[test]
+fn checkpoint_is_consistent() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for iter in 0..3 {
+        for key_id in 0..1000 {
+            store.set(format!("key{key_id}"), format!("{iter}"))?;
+        }
+    }
+    store.remove("key0".to_owned())?;
+
+    store.checkpoint(backup_dir.path())?;
+    store.set("key1".to_owned(), "after".to_owned())?;
+    store.set("late".to_owned(), "after".to_owned())?;
+
+    let mut backup = KvStore::open(backup_dir.path())?;
+    assert_eq!(backup.get("key0".to_owned())?, None);
+    assert_eq!(backup.get("key1".to_owned())?, Some("2".to_owned()));
+    assert_eq!(backup.get("key999".to_owned())?, Some("2".to_owned()));
+    assert_eq!(backup.get("late".to_owned())?, None);
+    Ok(())
+}
+
+// Checkpointing into a directory that already holds a store should fail.
+#[test]
+fn checkpoint_refuses_existing_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let other_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut other = KvStore::open(other_dir.path())?;
+    other.set("key1".to_owned(), "value1".to_owned())?;
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(store.checkpoint(other_dir.path()).is_err());
+    Ok(())
+}
+
+// `kvs backup` followed by `kvs restore` should reproduce the store elsewhere.
+#[test]
+fn cli_backup_restore() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary working directory");
+    let restore_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .arg("backup")
+        .arg(backup_dir.path())
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .arg("restore")
+        .arg(backup_dir.path())
+        .current_dir(&restore_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["get", "key1"])
+        .current_dir(&restore_dir)
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+    Ok(())
+}
+
+// `kvs-client backup` should copy the store of a running server, which
+// keeps taking writes.
+#[test]
+fn cli_backup_running_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let backup_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (addr, mut client) = spawn_server(KvStore::open(temp_dir.path())?);
+    client.set("key1".to_owned(), "value1".to_owned())?;
+
+    // the store is locked by the server
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["backup", backup_dir.path().to_str().unwrap()])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["backup", backup_dir.path().to_str().unwrap()])
+        .args(["--addr", &addr.to_string()])
+        .assert()
+        .success();
+    client.set("key2".to_owned(), "value2".to_owned())?;
+
+    let mut backup = KvStore::open(backup_dir.path())?;
+    assert_eq!(backup.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(backup.get("key2".to_owned())?, None);
+    Ok(())
+}
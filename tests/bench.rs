@@ -0,0 +1,36 @@
+use assert_cmd::prelude::*;
+use serde_json::Value;
+use std::process::Command;
+
+// A short run should report operations of both kinds without errors.
+#[test]
+fn bench_reports_json() {
+    let output = Command::cargo_bin("kvs-bench")
+        .unwrap()
+        .args(["--engine", "memory", "--keys", "100", "--duration", "1"])
+        .args(["--distribution", "zipfian", "--read-ratio", "0.5", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["target"], "memory");
+    assert_eq!(report["distribution"], "zipfian");
+    for kind in ["reads", "writes"] {
+        assert!(report[kind]["count"].as_u64().unwrap() > 0, "{report}");
+        assert_eq!(report[kind]["errors"], 0);
+        assert!(
+            report[kind]["p99_us"].as_f64().unwrap() >= report[kind]["p50_us"].as_f64().unwrap()
+        );
+    }
+    assert!(report["throughput"].as_f64().unwrap() > 0.0);
+}
+
+// A read ratio outside [0, 1] should be refused.
+#[test]
+fn bench_invalid_read_ratio() {
+    Command::cargo_bin("kvs-bench")
+        .unwrap()
+        .args(["--read-ratio", "1.5"])
+        .assert()
+        .failure();
+}
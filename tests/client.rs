@@ -0,0 +1,123 @@
+use networked_kv_store::{
+    ClientOptions, KvStore, KvsClient, KvsClientPool, KvsError, KvsServer, Result,
+};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// Returns an address nothing listens on.
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn options(retries: u32) -> ClientOptions {
+    ClientOptions {
+        read_timeout: Some(Duration::from_millis(200)),
+        retries,
+        backoff: Duration::from_millis(20),
+        ..ClientOptions::default()
+    }
+}
+
+// Dead servers and silent servers should be reported as different errors.
+#[test]
+fn refused_and_timed_out() -> Result<()> {
+    assert!(matches!(
+        KvsClient::connect_with(free_addr(), &options(0)),
+        Err(KvsError::ConnectionRefused)
+    ));
+
+    // accepts connections but never answers
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let mut client = KvsClient::connect_with(listener.local_addr()?, &options(0))?;
+    let start = Instant::now();
+    assert!(matches!(
+        client.get("key1".to_owned()),
+        Err(KvsError::Timeout)
+    ));
+    assert!(start.elapsed() < Duration::from_secs(2));
+    Ok(())
+}
+
+// The pool should reuse a single connection for sequential requests.
+#[test]
+fn pool_reuses_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let store = KvStore::open(temp_dir.path())?;
+    thread::spawn(move || KvsServer::new(store).run(addr).unwrap());
+
+    let pool = KvsClientPool::new(addr, options(10))?;
+    for key_id in 0..10 {
+        pool.set(format!("key{key_id}"), "value".to_owned())?;
+    }
+    assert_eq!(pool.idle_connections(), 1);
+    assert!(matches!(
+        pool.remove("missing".to_owned()),
+        Err(KvsError::KeyNotFound)
+    ));
+    // errors answered by the server leave the connection usable
+    assert_eq!(pool.idle_connections(), 1);
+    assert_eq!(pool.scan("key".to_owned())?.len(), 10);
+    Ok(())
+}
+
+// Requests should be retried until a late server comes up, or give up after the retries.
+#[test]
+fn pool_retries_with_backoff() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let store = KvStore::open(temp_dir.path())?;
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        KvsServer::new(store).run(addr).unwrap()
+    });
+
+    let pool = KvsClientPool::new(addr, options(10))?;
+    pool.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(pool.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    let start = Instant::now();
+    let pool = KvsClientPool::new(free_addr(), options(3))?;
+    assert!(matches!(
+        pool.get("key1".to_owned()),
+        Err(KvsError::ConnectionRefused)
+    ));
+    // 20 + 40 + 80 ms of backoff
+    assert!(start.elapsed() >= Duration::from_millis(140));
+    Ok(())
+}
+
+// A remove that could not be written should be retried, since it never ran.
+#[test]
+fn pool_retries_unsent_requests() -> Result<()> {
+    // accepts connections but never reads from them
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let (accepted, connections) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let _ = accepted.send(stream);
+        }
+    });
+    let options = ClientOptions {
+        write_timeout: Some(Duration::from_millis(200)),
+        ..options(2)
+    };
+    let pool = KvsClientPool::new(addr, options)?;
+
+    // larger than the socket buffers, so the write times out
+    let key = "k".repeat(8 << 20);
+    assert!(matches!(pool.remove(key), Err(KvsError::Timeout)));
+    let timeout = Duration::from_secs(1);
+    let connections: Vec<_> = (0..3)
+        .map_while(|_| connections.recv_timeout(timeout).ok())
+        .collect();
+    assert_eq!(connections.len(), 3);
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{
+    Durability, KvStore, KvsClient, KvsError, Result, ServerConfig, ServerMode,
+};
+use std::fs;
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn connect(addr: SocketAddr) -> KvsClient {
+    for _ in 0..100 {
+        if let Ok(client) = KvsClient::connect(addr) {
+            return client;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// Writes `text` to a config file in `temp_dir` and loads it.
+fn load(temp_dir: &TempDir, text: &str) -> Result<ServerConfig> {
+    let path = temp_dir.path().join("kvs.toml");
+    fs::write(&path, text)?;
+    ServerConfig::load(path)
+}
+
+// Keys left out of the file should keep their defaults.
+#[test]
+fn load_config_file() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = load(
+        &temp_dir,
+        r#"
+addr = "127.0.0.1:4100"
+data_dir = "/var/lib/kvs"
+
+[server]
+mode = "shared-queue"
+threads = 4
+
+[storage]
+durability = "sync"
+
+[limits]
+max_value_bytes = 1024
+"#,
+    )?;
+    assert_eq!(config.addr, "127.0.0.1:4100".parse().unwrap());
+    assert_eq!(config.server.mode, ServerMode::SharedQueue);
+    assert_eq!(config.server.threads, Some(4));
+    assert_eq!(config.server.drain_timeout_secs, 10);
+    assert_eq!(config.storage.durability, Durability::Sync);
+    assert_eq!(config.limits.max_value_bytes, Some(1024));
+    assert_eq!(config.limits.max_key_bytes, None);
+    assert_eq!(config.engine, "kvs");
+    assert_eq!(load(&temp_dir, "")?, ServerConfig::default());
+    Ok(())
+}
+
+// Errors should point at the key that is wrong.
+#[test]
+fn invalid_config_names_the_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let invalid = |text: &str| match load(&temp_dir, text) {
+        Err(KvsError::InvalidConfig(message)) => message,
+        other => panic!("expected an invalid config, got {other:?}"),
+    };
+
+    let message = invalid("[server]\nthread = 4\n");
+    assert!(message.contains("thread"), "{message}");
+    assert!(message.contains("line 2"), "{message}");
+
+    let message = invalid("[storage]\ndurability = \"always\"\n");
+    assert!(message.contains("durability"), "{message}");
+
+    let message = invalid("[server]\nthreads = 0\n");
+    assert!(message.contains("server.threads"), "{message}");
+
+    let message = invalid("engine = \"lmdb\"\n");
+    assert!(message.contains("engine"), "{message}");
+
+    let message = invalid("[tls]\ncert = \"cert.pem\"\n");
+    assert!(message.contains("tls.key"), "{message}");
+
+    let message = invalid("[auth]\nprimary_user = \"replica\"\n");
+    assert!(message.contains("auth.primary_password"), "{message}");
+
+    let message = invalid("[[cluster.peers]]\nid = 2\naddr = \"127.0.0.1:4001\"\n");
+    assert!(message.contains("cluster.peers"), "{message}");
+    Ok(())
+}
+
+// Applied settings should bound writes and sync them to disk.
+#[test]
+fn apply_store_settings() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = load(
+        &temp_dir,
+        "[storage]\ndurability = \"sync\"\nslowlog_threshold_ms = 0\n\n[limits]\nmax_key_bytes = 4\nmax_value_bytes = 8\n",
+    )?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    config.apply(&store.settings());
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert!(store.slow_log(1)[0].synced);
+    assert!(matches!(
+        store.set("key10".to_owned(), "value1".to_owned()),
+        Err(KvsError::LimitExceeded(_))
+    ));
+    assert!(matches!(
+        store.set("key1".to_owned(), "value1000".to_owned()),
+        Err(KvsError::LimitExceeded(_))
+    ));
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// SIGHUP should apply new limits, and warn about settings needing a restart.
+#[test]
+fn reload_on_sighup() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let path = temp_dir.path().join("kvs.toml");
+    let write_config = |max_value_bytes: u64, threads: u32| {
+        let text = format!(
+            "addr = \"{addr}\"\n\n[server]\nthreads = {threads}\n\n[limits]\nmax_value_bytes = {max_value_bytes}\n"
+        );
+        fs::write(&path, text)
+    };
+    write_config(4, 2)?;
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .arg("--config")
+        .arg(&path)
+        .env_remove("RUST_LOG")
+        .current_dir(&temp_dir)
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut client = connect(addr);
+    assert!(matches!(
+        client.set("key1".to_owned(), "value1".to_owned()),
+        Err(KvsError::LimitExceeded(_))
+    ));
+
+    write_config(1024, 3)?;
+    let status = Command::new("kill")
+        .args(["-HUP", &server.id().to_string()])
+        .status()?;
+    assert!(status.success());
+    thread::sleep(Duration::from_millis(200));
+    client.set("key1".to_owned(), "value1".to_owned())?;
+
+    client.shutdown()?;
+    assert!(server.wait()?.success());
+    let mut stderr = String::new();
+    server.stderr.take().unwrap().read_to_string(&mut stderr)?;
+    assert!(stderr.contains("reloaded configuration"), "{stderr}");
+    assert!(stderr.contains("server.threads"), "{stderr}");
+    Ok(())
+}
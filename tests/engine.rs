@@ -0,0 +1,105 @@
+use assert_cmd::prelude::*;
+#[cfg(feature = "sled")]
+use networked_kv_store::SledKvsEngine;
+use networked_kv_store::{KvStore, KvsEngine, KvsError, MemoryKvsEngine, Result, verify};
+use predicates::str::contains;
+use std::fs;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// Opening a directory should record the engine, and refuse one recorded by another.
+#[test]
+fn engine_marker() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let marker = temp_dir.path().join("engine");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    assert_eq!(fs::read_to_string(&marker)?.trim(), "kvs");
+    assert!(verify(temp_dir.path())?.is_ok());
+
+    // directories written before the marker existed still open
+    fs::remove_file(&marker)?;
+    let store = KvStore::open(temp_dir.path())?;
+    drop(store);
+    assert_eq!(fs::read_to_string(&marker)?.trim(), "kvs");
+
+    fs::write(&marker, "sled\n")?;
+    match KvStore::open(temp_dir.path()) {
+        Err(KvsError::EngineMismatch { found, requested }) => {
+            assert_eq!(found, "sled");
+            assert_eq!(requested, "kvs");
+        }
+        other => panic!("expected an engine mismatch, got {:?}", other.err()),
+    }
+    assert!(matches!(
+        verify(temp_dir.path()),
+        Err(KvsError::EngineMismatch { .. })
+    ));
+    Ok(())
+}
+
+// The server should not start on a directory of another engine.
+#[test]
+fn server_refuses_other_engine() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(temp_dir.path().join("engine"), "sled\n").unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:0"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("sled"));
+    assert!(!temp_dir.path().join("1.log").exists());
+}
+
+// The directory should only open with the engine that created it.
+#[cfg(feature = "sled")]
+#[test]
+fn engines_keep_their_directories() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(SledKvsEngine::open(temp_dir.path())?);
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(KvsError::EngineMismatch { .. })
+    ));
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    drop(KvStore::open(temp_dir.path())?);
+    assert!(matches!(
+        SledKvsEngine::open(temp_dir.path()),
+        Err(KvsError::EngineMismatch { .. })
+    ));
+    Ok(())
+}
+
+// The memory engine should snapshot its entries periodically and when
+// dropped, and only be opened once.
+#[test]
+fn memory_engine_snapshots() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let snapshot = temp_dir.path().join("snapshot.jsonl");
+    let interval = Duration::from_millis(50);
+    let mut store = MemoryKvsEngine::open(temp_dir.path(), interval)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !fs::read_to_string(&snapshot).is_ok_and(|snapshot| snapshot.contains("value1")) {
+        assert!(Instant::now() < deadline, "no periodic snapshot was taken");
+        thread::sleep(interval);
+    }
+
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key1".to_owned())?;
+    assert!(matches!(
+        MemoryKvsEngine::open(temp_dir.path(), interval),
+        Err(KvsError::DirectoryLocked(_))
+    ));
+    drop(store);
+    let mut store = MemoryKvsEngine::open(temp_dir.path(), interval)?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
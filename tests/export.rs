@@ -0,0 +1,103 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{DataFormat, KvStore, Result};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::process::Command;
+use tempfile::TempDir;
+
+// Exported JSON Lines should only hold live keys matching the prefix.
+#[test]
+fn export_json_lines_with_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("user:1".to_owned(), "alice".to_owned())?;
+    store.set("user:2".to_owned(), "bob".to_owned())?;
+    store.set("order:1".to_owned(), "book".to_owned())?;
+    store.remove("user:2".to_owned())?;
+
+    let mut out = Vec::new();
+    assert_eq!(
+        store.export(&mut out, DataFormat::JsonLines, Some("user:"))?,
+        1
+    );
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"key\":\"user:1\",\"value\":\"alice\"}\n"
+    );
+    Ok(())
+}
+
+// Pairs exported from one store should import into another unchanged.
+#[test]
+fn export_import_round_trip() -> Result<()> {
+    for format in [DataFormat::JsonLines, DataFormat::Csv] {
+        let source_dir = TempDir::new().expect("unable to create temporary working directory");
+        let mut source = KvStore::open(source_dir.path())?;
+        for key_id in 0..100 {
+            source.set(format!("key{key_id}"), format!("value, \"{key_id}\"\n"))?;
+        }
+        let mut out = Vec::new();
+        assert_eq!(source.export(&mut out, format, None)?, 100);
+
+        let target_dir = TempDir::new().expect("unable to create temporary working directory");
+        let mut target = KvStore::open(target_dir.path())?;
+        assert_eq!(target.import(out.as_slice(), format)?, 100);
+
+        // Open from disk again and check persistent data.
+        drop(target);
+        let mut target = KvStore::open(target_dir.path())?;
+        for key_id in 0..100 {
+            assert_eq!(
+                target.get(format!("key{key_id}"))?,
+                Some(format!("value, \"{key_id}\"\n"))
+            );
+        }
+    }
+    Ok(())
+}
+
+// `kvs import` followed by `kvs export` should reproduce the CSV input.
+#[test]
+fn cli_import_export_csv() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let input = temp_dir.path().join("input.csv");
+    std::fs::write(&input, "key,value\nkey1,value1\nkey2,value2\n").unwrap();
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["import", "--format", "csv", "input.csv"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["export", "--format", "csv"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(eq("key,value\nkey1,value1\nkey2,value2").trim());
+}
+
+// A malformed record should fail the import without writing any pair.
+#[test]
+fn import_is_all_or_nothing() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let watcher = store.watch("");
+    let input = "{\"key\":\"key1\",\"value\":\"value1\"}\n{\"key\":\"key2\"}\n";
+    assert!(
+        store
+            .import(input.as_bytes(), DataFormat::JsonLines)
+            .is_err()
+    );
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(watcher.try_recv().is_err());
+
+    // Open from disk again and check nothing was written.
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.sequence(), 0);
+    Ok(())
+}
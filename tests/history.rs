@@ -0,0 +1,132 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{AsOf, KvStore, Result, Snapshot, StoreOptions};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::process::Command;
+use tempfile::TempDir;
+
+// Sequence numbers should keep increasing across reopens.
+#[test]
+fn sequence_survives_reopen() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.sequence(), 0);
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.sequence(), 3);
+
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.sequence(), 3);
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    assert_eq!(store.sequence(), 4);
+    Ok(())
+}
+
+// Logs written before records carried a sequence should still load.
+#[test]
+fn load_unsequenced_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    std::fs::write(
+        temp_dir.path().join("1.log"),
+        r#"{"Set":{"key":"key1","value":"value1"}}{"Remove":{"key":"key1"}}{"Set":{"key":"key2","value":"value2"}}"#,
+    )?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+// A snapshot should show the state at a sequence, even across compaction.
+#[test]
+fn snapshot_as_of_sequence_after_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions {
+        retain_history: true,
+    };
+    let mut store = KvStore::open_with_options(temp_dir.path(), options)?;
+
+    let mut checkpoint_seq = 0;
+    for iter in 0..100 {
+        for key_id in 0..1000 {
+            store.set(format!("key{key_id}"), format!("{iter}"))?;
+        }
+        if iter == 2 {
+            checkpoint_seq = store.sequence();
+            store.remove("key0".to_owned())?;
+        }
+        if temp_dir.path().join("history").is_dir() {
+            break;
+        }
+    }
+    assert!(
+        temp_dir.path().join("history").is_dir(),
+        "No compaction detected"
+    );
+
+    let snapshot = Snapshot::open(temp_dir.path(), AsOf::Sequence(checkpoint_seq))?;
+    assert_eq!(snapshot.sequence(), checkpoint_seq);
+    assert_eq!(snapshot.get("key0"), Some("2"));
+    assert_eq!(snapshot.get("key999"), Some("2"));
+
+    let snapshot = Snapshot::open(temp_dir.path(), AsOf::Sequence(checkpoint_seq + 1))?;
+    assert_eq!(snapshot.get("key0"), None);
+    Ok(())
+}
+
+// A snapshot by timestamp should exclude later writes.
+#[test]
+fn snapshot_as_of_timestamp() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "before".to_owned())?;
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    let before = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    store.set("key1".to_owned(), "after".to_owned())?;
+
+    let snapshot = Snapshot::open(temp_dir.path(), AsOf::Timestamp(before))?;
+    assert_eq!(snapshot.get("key1"), Some("before"));
+    Ok(())
+}
+
+// `kvs export --as-of-seq` should export the state after that record.
+#[test]
+fn cli_export_as_of_sequence() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["export", "--format", "csv", "--as-of-seq", "1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(eq("key,value\nkey1,value1").trim());
+    Ok(())
+}
+
+// Sequence numbers should keep increasing after compaction dropped the last records.
+#[test]
+fn sequence_survives_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.settings().set_compaction_threshold(0);
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.remove("key1".to_owned())?;
+    let before = store.sequence();
+
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.sequence(), before);
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert!(store.sequence() > before);
+    Ok(())
+}
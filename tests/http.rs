@@ -0,0 +1,117 @@
+use networked_kv_store::{HttpServer, KvStore, Result};
+use serde_json::{Value, json};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn spawn_server(temp_dir: &TempDir) -> Result<SocketAddr> {
+    let addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let server = HttpServer::new(KvStore::open(temp_dir.path())?);
+    thread::spawn(move || server.run(addr).unwrap());
+    for _ in 0..100 {
+        if TcpStream::connect(addr).is_ok() {
+            return Ok(addr);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// Sends a request on a fresh connection, returns the status and the JSON body if any.
+fn call(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = serde_json::from_str(body).unwrap_or(Value::Null);
+    (status, body)
+}
+
+// Keys should be set, read and removed through their resource, with 404 for missing ones.
+#[test]
+fn keys_roundtrip() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(&temp_dir)?;
+
+    let (status, _) = call(addr, "PUT", "/keys/a%2Fb", Some(json!({ "value": "v1" })));
+    assert_eq!(status, 204);
+    let (status, body) = call(addr, "GET", "/keys/a%2Fb", None);
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "key": "a/b", "value": "v1" }));
+
+    assert_eq!(call(addr, "DELETE", "/keys/a%2Fb", None).0, 204);
+    let (status, body) = call(addr, "GET", "/keys/a%2Fb", None);
+    assert_eq!(status, 404);
+    assert_eq!(body["error"]["code"], "key_not_found");
+    assert_eq!(call(addr, "DELETE", "/keys/a%2Fb", None).0, 404);
+
+    assert_eq!(call(addr, "PUT", "/keys/k", Some(json!([1]))).0, 400);
+    assert_eq!(call(addr, "POST", "/keys/k", None).0, 405);
+    assert_eq!(call(addr, "GET", "/nowhere", None).0, 404);
+    Ok(())
+}
+
+// Scans should page through the keys under a prefix with a cursor.
+#[test]
+fn scan_pages() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(&temp_dir)?;
+    for key in ["p1", "p2", "p3", "q1"] {
+        call(
+            addr,
+            "PUT",
+            &format!("/keys/{key}"),
+            Some(json!({ "value": key })),
+        );
+    }
+
+    let (status, body) = call(addr, "GET", "/keys?prefix=p&limit=2", None);
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({
+            "entries": [{ "key": "p1", "value": "p1" }, { "key": "p2", "value": "p2" }],
+            "next_cursor": "p2",
+        })
+    );
+    let (_, body) = call(addr, "GET", "/keys?prefix=p&limit=2&cursor=p2", None);
+    assert_eq!(
+        body,
+        json!({ "entries": [{ "key": "p3", "value": "p3" }], "next_cursor": null })
+    );
+    assert_eq!(call(addr, "GET", "/keys?limit=0", None).0, 400);
+    Ok(())
+}
+
+// A batch should run every operation and report each result on its own.
+#[test]
+fn batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(&temp_dir)?;
+
+    let operations = json!({ "operations": [
+        { "op": "set", "key": "k", "value": "v" },
+        { "op": "get", "key": "k" },
+        { "op": "remove", "key": "missing" },
+        { "op": "get", "key": "missing" },
+    ]});
+    let (status, body) = call(addr, "POST", "/batch", Some(operations));
+    assert_eq!(status, 200);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[0], json!({ "value": null }));
+    assert_eq!(results[1], json!({ "value": "v" }));
+    assert_eq!(results[2]["error"]["code"], "key_not_found");
+    assert_eq!(results[3], json!({ "value": null }));
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{KvsClient, Result};
+use serde_json::Value;
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn connect(addr: SocketAddr) -> KvsClient {
+    for _ in 0..100 {
+        if let Ok(client) = KvsClient::connect(addr) {
+            return client;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// Runs kvs-server with `args` through a set and a shutdown, returns what it logged.
+fn server_log(temp_dir: &TempDir, args: &[&str]) -> Result<String> {
+    let addr = free_addr();
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", &addr.to_string()])
+        .args(args)
+        .env_remove("RUST_LOG")
+        .current_dir(temp_dir)
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut client = connect(addr);
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    client.shutdown()?;
+    assert!(server.wait()?.success());
+    let mut stderr = String::new();
+    server.stderr.take().unwrap().read_to_string(&mut stderr)?;
+    Ok(stderr)
+}
+
+// JSON output should carry the fields of startup, store and connection events.
+#[test]
+fn json_events() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let stderr = server_log(&temp_dir, &["--log-format", "json"])?;
+    let events: Vec<Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|_| panic!("not JSON: {line}")))
+        .collect();
+    let event = |message: &str| {
+        events
+            .iter()
+            .find(|event| event["message"] == message)
+            .unwrap_or_else(|| panic!("no {message} event in {stderr}"))
+    };
+
+    let startup = event("starting kvs-server");
+    assert_eq!(startup["level"], "INFO");
+    assert_eq!(startup["engine"], "kvs");
+    assert_eq!(startup["mode"], "naive");
+    assert_eq!(event("opened store")["keys"], 0);
+    assert!(event("connection opened")["peer"].is_string());
+    event("Shut down cleanly");
+    Ok(())
+}
+
+// Events below the configured level should be left out.
+#[test]
+fn level_filters_events() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let stderr = server_log(&temp_dir, &["--log-level", "warn"])?;
+    assert!(!stderr.contains("connection opened"), "{stderr}");
+
+    let stderr = server_log(&temp_dir, &["--log-level", "debug"])?;
+    assert!(stderr.contains("replayed generation"), "{stderr}");
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use networked_kv_store::{KvStore, KvsClient, KvsServer, MetricsServer, Result};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+// Returns the value of the sample `series` in a rendering of the metrics.
+fn sample(metrics: &str, series: &str) -> f64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no sample {series}"))
+        .parse()
+        .unwrap()
+}
+
+// Operations should be counted by outcome and timed, and compactions reported.
+#[test]
+fn store_metrics() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let metrics = store.metrics();
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.get("key1".to_owned())?;
+    store.get("missing".to_owned())?;
+    assert!(store.remove("missing".to_owned()).is_err());
+    let rendered = metrics.render();
+    assert_eq!(
+        sample(&rendered, r#"kvs_operations_total{op="get",outcome="ok"}"#),
+        1.0
+    );
+    assert_eq!(
+        sample(
+            &rendered,
+            r#"kvs_operations_total{op="get",outcome="not_found"}"#
+        ),
+        1.0
+    );
+    assert_eq!(
+        sample(
+            &rendered,
+            r#"kvs_operations_total{op="remove",outcome="not_found"}"#
+        ),
+        1.0
+    );
+    assert_eq!(
+        sample(
+            &rendered,
+            r#"kvs_operation_duration_seconds_count{op="get"}"#
+        ),
+        2.0
+    );
+    assert_eq!(
+        sample(
+            &rendered,
+            r#"kvs_operation_duration_seconds_bucket{op="set",le="+Inf"}"#
+        ),
+        1.0
+    );
+    assert!(sample(&rendered, "kvs_bytes_written_total") > 0.0);
+    assert_eq!(
+        sample(&rendered, "kvs_compaction_duration_seconds_count"),
+        0.0
+    );
+
+    // overwriting a key over and over triggers a compaction
+    let value = "v".repeat(1024);
+    for _ in 0..2000 {
+        store.set("key1".to_owned(), value.clone())?;
+    }
+    let rendered = metrics.render();
+    assert!(sample(&rendered, "kvs_compaction_duration_seconds_count") >= 1.0);
+    assert!(sample(&rendered, "kvs_compaction_reclaimed_bytes_total") > 1e6);
+    assert!(sample(&rendered, "kvs_generations") >= 1.0);
+    Ok(())
+}
+
+// The metrics server should expose open connections to a scrape.
+#[test]
+fn scrape_endpoint() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let (addr, metrics_addr) = (free_addr(), free_addr());
+    let metrics = MetricsServer::new(store.metrics());
+    thread::spawn(move || metrics.run(metrics_addr).unwrap());
+    thread::spawn(move || KvsServer::new(store).run(addr).unwrap());
+    thread::sleep(Duration::from_millis(100));
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+
+    let mut stream = TcpStream::connect(metrics_addr)?;
+    write!(
+        stream,
+        "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("text/plain; version=0.0.4"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    assert_eq!(sample(body, "kvs_open_connections"), 1.0);
+    assert_eq!(
+        sample(body, r#"kvs_operations_total{op="set",outcome="ok"}"#),
+        1.0
+    );
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use networked_kv_store::{AsyncKvsServer, KvStore, KvsClient, KvsError, KvsServer, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Starts a server on a free port, asynchronous or not, and returns its address.
+fn spawn_server(store: KvStore, run_async: bool) -> SocketAddr {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    if run_async {
+        thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(AsyncKvsServer::new(store).run(addr))
+                .unwrap()
+        });
+    } else {
+        thread::spawn(move || KvsServer::new(store).run(addr).unwrap());
+    }
+    for _ in 0..100 {
+        if KvsClient::connect(addr).is_ok() {
+            return addr;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+fn pipelined_requests(run_async: bool) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(KvStore::open(temp_dir.path())?, run_async);
+    let mut client = KvsClient::connect(addr)?;
+
+    let mut pipeline = client.pipeline();
+    for key_id in 0..100 {
+        pipeline = pipeline.set(format!("key{key_id}"), key_id.to_string());
+    }
+    let replies = pipeline.send()?;
+    assert_eq!(replies.len(), 100);
+    assert!(replies.iter().all(|reply| matches!(reply, Ok(None))));
+
+    let replies = client
+        .pipeline()
+        .get("key1".to_owned())
+        .remove("missing".to_owned())
+        .get("missing".to_owned())
+        .remove("key2".to_owned())
+        .send()?;
+    assert!(matches!(&replies[0], Ok(Some(value)) if value == "1"));
+    assert!(matches!(replies[1], Err(KvsError::KeyNotFound)));
+    assert!(matches!(replies[2], Ok(None)));
+    assert!(matches!(replies[3], Ok(None)));
+
+    // the connection keeps working for plain requests afterwards
+    assert_eq!(client.get("key2".to_owned())?, None);
+    let keys = (0..100).map(|key_id| format!("key{key_id}")).collect();
+    let values = client.get_many(keys)?;
+    assert_eq!(values[99], Some("99".to_owned()));
+    assert_eq!(values.iter().flatten().count(), 99);
+    Ok(())
+}
+
+// The threaded server should answer pipelined requests in order.
+#[test]
+fn pipeline_on_server() -> Result<()> {
+    pipelined_requests(false)
+}
+
+// The async server should match the replies it sends out of order to their requests.
+#[test]
+fn pipeline_on_async_server() -> Result<()> {
+    pipelined_requests(true)
+}
+
+// Requests and replies larger than the socket buffers should not stall a long pipeline.
+fn long_pipeline(run_async: bool) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(KvStore::open(temp_dir.path())?, run_async);
+    let mut client = KvsClient::connect(addr)?;
+    let key = "k".repeat(8192);
+    let value = "v".repeat(8192);
+    client.set(key.clone(), value.clone())?;
+
+    let values = client.get_many(vec![key; 2_000])?;
+    assert_eq!(values.len(), 2_000);
+    assert!(
+        values
+            .iter()
+            .all(|reply| reply.as_deref() == Some(value.as_str()))
+    );
+    Ok(())
+}
+
+// The threaded server should keep reading a pipeline it is answering.
+#[test]
+fn long_pipeline_on_server() -> Result<()> {
+    long_pipeline(false)
+}
+
+// The async server should keep reading a pipeline it is answering.
+#[test]
+fn long_pipeline_on_async_server() -> Result<()> {
+    long_pipeline(true)
+}
+
+// A request line longer than the server accepts should be answered with an
+// error instead of being buffered.
+fn oversized_request(run_async: bool) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(KvStore::open(temp_dir.path())?, run_async);
+    let mut stream = TcpStream::connect(addr)?;
+    let mut writer = stream.try_clone()?;
+    // one byte over the limit, all of it read by the server before it gives up
+    let sender = thread::spawn(move || writer.write_all(&vec![b'a'; (64 << 20) + 1]));
+
+    let mut reply = String::new();
+    BufReader::new(&mut stream).read_line(&mut reply)?;
+    assert!(reply.contains("LimitExceeded"), "{reply}");
+    sender.join().unwrap()?;
+    Ok(())
+}
+
+// The threaded server should turn away oversized requests.
+#[test]
+fn oversized_request_on_server() -> Result<()> {
+    oversized_request(false)
+}
+
+// The async server should turn away oversized requests.
+#[test]
+fn oversized_request_on_async_server() -> Result<()> {
+    oversized_request(true)
+}
@@ -0,0 +1,321 @@
+use networked_kv_store::raft::{Command, NodeId, RaftNode};
+use networked_kv_store::{KvsClient, KvsError, KvsServer, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// An in-process cluster exchanging messages over a simulated network
+// that can crash nodes and cut links.
+struct Cluster {
+    dir: TempDir,
+    nodes: BTreeMap<NodeId, RaftNode>,
+    cut: HashSet<(NodeId, NodeId)>,
+}
+
+impl Cluster {
+    fn new(size: u64) -> Result<Cluster> {
+        let dir = TempDir::new().expect("unable to create temporary working directory");
+        let members: Vec<NodeId> = (1..=size).collect();
+        let mut nodes = BTreeMap::new();
+        for &id in &members {
+            nodes.insert(
+                id,
+                RaftNode::open(id, members.clone(), dir.path().join(id.to_string()))?,
+            );
+        }
+        Ok(Cluster {
+            dir,
+            nodes,
+            cut: HashSet::new(),
+        })
+    }
+
+    fn join(&mut self, id: NodeId) -> Result<()> {
+        let node = RaftNode::open(id, Vec::new(), self.dir.path().join(id.to_string()))?;
+        self.nodes.insert(id, node);
+        Ok(())
+    }
+
+    fn crash(&mut self, id: NodeId) {
+        self.nodes.remove(&id);
+    }
+
+    fn restart(&mut self, id: NodeId) -> Result<()> {
+        self.join(id)
+    }
+
+    fn isolate(&mut self, id: NodeId) {
+        for &other in self.nodes.keys() {
+            self.cut.insert((id, other));
+            self.cut.insert((other, id));
+        }
+    }
+
+    fn heal(&mut self) {
+        self.cut.clear();
+    }
+
+    // Ticks every node once and delivers messages until the network is quiet.
+    fn tick(&mut self) -> Result<()> {
+        for node in self.nodes.values_mut() {
+            node.tick()?;
+        }
+        self.deliver()
+    }
+
+    fn deliver(&mut self) -> Result<()> {
+        loop {
+            let mut in_flight = Vec::new();
+            for (&from, node) in self.nodes.iter_mut() {
+                for (to, message) in node.take_messages() {
+                    in_flight.push((from, to, message));
+                }
+            }
+            if in_flight.is_empty() {
+                return Ok(());
+            }
+            for (from, to, message) in in_flight {
+                if self.cut.contains(&(from, to)) {
+                    continue;
+                }
+                if let Some(node) = self.nodes.get_mut(&to) {
+                    node.step(from, message)?;
+                }
+            }
+        }
+    }
+
+    fn run(&mut self, ticks: usize) -> Result<()> {
+        for _ in 0..ticks {
+            self.tick()?;
+        }
+        Ok(())
+    }
+
+    fn leaders(&self) -> Vec<NodeId> {
+        let term = self.nodes.values().map(RaftNode::term).max().unwrap_or(0);
+        self.nodes
+            .values()
+            .filter(|node| node.is_leader() && node.term() == term)
+            .map(RaftNode::id)
+            .collect()
+    }
+
+    // Proposes `command` like a client would, following leader redirections,
+    // and waits until it is applied on the leader.
+    fn propose(&mut self, command: Command) -> Result<NodeId> {
+        let mut target = *self.nodes.keys().next().unwrap();
+        for _ in 0..200 {
+            let node = self.nodes.get_mut(&target).unwrap();
+            match node.propose(command.clone()) {
+                Ok(index) => {
+                    self.deliver()?;
+                    for _ in 0..50 {
+                        if self.nodes[&target].applied_index() >= index {
+                            return Ok(target);
+                        }
+                        self.tick()?;
+                    }
+                }
+                Err(KvsError::NotLeader(Some(leader))) if self.nodes.contains_key(&leader) => {
+                    target = leader;
+                    continue;
+                }
+                Err(KvsError::NotLeader(_)) => {}
+                Err(e) => return Err(e),
+            }
+            self.tick()?;
+        }
+        panic!("{command:?} was never committed");
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<NodeId> {
+        self.propose(Command::Set {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<String>> {
+        let leader = self.leaders()[0];
+        self.nodes.get_mut(&leader).unwrap().get(key.to_owned())
+    }
+}
+
+// A cluster should elect exactly one leader and followers should redirect to it.
+#[test]
+fn elects_single_leader() -> Result<()> {
+    let mut cluster = Cluster::new(3)?;
+    cluster.run(50)?;
+    let leaders = cluster.leaders();
+    assert_eq!(leaders.len(), 1);
+
+    let follower = (1..=3).find(|id| *id != leaders[0]).unwrap();
+    let node = cluster.nodes.get_mut(&follower).unwrap();
+    assert!(matches!(
+        node.propose(Command::Noop),
+        Err(KvsError::NotLeader(Some(leader))) if leader == leaders[0]
+    ));
+    Ok(())
+}
+
+// Committed writes should reach every node and survive the loss of the leader.
+#[test]
+fn replicates_and_fails_over() -> Result<()> {
+    let mut cluster = Cluster::new(3)?;
+    cluster.run(50)?;
+    for key_id in 0..20 {
+        cluster.set(&format!("key{key_id}"), "value")?;
+    }
+    let old_leader = cluster.leaders()[0];
+
+    cluster.crash(old_leader);
+    cluster.run(50)?;
+    let leaders = cluster.leaders();
+    assert_eq!(leaders.len(), 1);
+    assert_ne!(leaders[0], old_leader);
+    for key_id in 0..20 {
+        assert_eq!(
+            cluster.get(&format!("key{key_id}"))?,
+            Some("value".to_owned())
+        );
+    }
+
+    cluster.set("key0", "new")?;
+    cluster.restart(old_leader)?;
+    cluster.run(50)?;
+    let applied: Vec<_> = cluster
+        .nodes
+        .values()
+        .map(RaftNode::applied_index)
+        .collect();
+    assert!(applied.windows(2).all(|pair| pair[0] == pair[1]));
+    assert_eq!(cluster.get("key0")?, Some("new".to_owned()));
+    Ok(())
+}
+
+// A minority partition should not commit writes and should converge once healed.
+#[test]
+fn minority_cannot_commit() -> Result<()> {
+    let mut cluster = Cluster::new(5)?;
+    cluster.run(50)?;
+    let old_leader = cluster.leaders()[0];
+    cluster.isolate(old_leader);
+
+    let node = cluster.nodes.get_mut(&old_leader).unwrap();
+    let index = node.propose(Command::Set {
+        key: "lost".to_owned(),
+        value: "value".to_owned(),
+    })?;
+    cluster.run(50)?;
+    assert!(cluster.nodes[&old_leader].commit_index() < index);
+    cluster.set("kept", "value")?;
+
+    cluster.heal();
+    cluster.run(50)?;
+    assert_eq!(cluster.leaders().len(), 1);
+    assert_eq!(cluster.get("lost")?, None);
+    assert_eq!(cluster.get("kept")?, Some("value".to_owned()));
+    Ok(())
+}
+
+// A node missing entries truncated from the leader's log should catch up from a snapshot.
+#[test]
+fn lagging_node_installs_snapshot() -> Result<()> {
+    let mut cluster = Cluster::new(3)?;
+    cluster.run(50)?;
+    let lagging = (1..=3).find(|id| *id != cluster.leaders()[0]).unwrap();
+    cluster.crash(lagging);
+    for key_id in 0..600 {
+        cluster.set(&format!("key{key_id}"), &key_id.to_string())?;
+    }
+
+    cluster.restart(lagging)?;
+    cluster.run(50)?;
+    let leader = cluster.leaders()[0];
+    assert_eq!(
+        cluster.nodes[&lagging].applied_index(),
+        cluster.nodes[&leader].applied_index()
+    );
+
+    // the caught up node must be able to lead with the snapshot's data
+    cluster.crash(leader);
+    cluster.run(100)?;
+    assert_eq!(cluster.get("key599")?, Some("599".to_owned()));
+    Ok(())
+}
+
+// Members added and removed through the log should take part in the quorum.
+#[test]
+fn membership_changes() -> Result<()> {
+    let mut cluster = Cluster::new(3)?;
+    cluster.run(50)?;
+    cluster.set("key1", "value1")?;
+
+    cluster.join(4)?;
+    cluster.propose(Command::AddMember(4))?;
+    cluster.run(20)?;
+    assert_eq!(cluster.nodes[&4].members(), vec![1, 2, 3, 4]);
+
+    let leader = cluster.leaders()[0];
+    cluster.propose(Command::RemoveMember(leader))?;
+    cluster.crash(leader);
+    cluster.run(100)?;
+    let leaders = cluster.leaders();
+    assert_eq!(leaders.len(), 1);
+    assert!(!cluster.nodes[&leaders[0]].members().contains(&leader));
+    cluster.set("key2", "value2")?;
+    assert_eq!(cluster.get("key1")?, Some("value1".to_owned()));
+    Ok(())
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+// Retries `f` until it succeeds, while the cluster elects a leader.
+fn eventually<T>(mut f: impl FnMut() -> Result<T>) -> T {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match f() {
+            Ok(value) => return value,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
+
+// Servers of a cluster should commit writes sent to any member by
+// redirecting clients to the leader.
+#[test]
+fn serves_cluster_over_tcp() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let addrs: HashMap<NodeId, SocketAddr> = (1..=3).map(|id| (id, free_addr())).collect();
+    for (&id, &addr) in &addrs {
+        let peers = addrs
+            .iter()
+            .filter(|&(&peer, _)| peer != id)
+            .map(|(&peer, &addr)| (peer, addr))
+            .collect();
+        let node = RaftNode::open(id, vec![1, 2, 3], dir.path().join(id.to_string()))?;
+        let server = KvsServer::cluster(node, peers);
+        thread::spawn(move || server.run(addr).unwrap());
+    }
+
+    for (&id, &addr) in &addrs {
+        eventually(|| KvsClient::connect(addr)?.set(format!("key{id}"), format!("value{id}")));
+    }
+    for &addr in addrs.values() {
+        let mut client = KvsClient::connect(addr)?;
+        for id in 1..=3 {
+            assert_eq!(client.get(format!("key{id}"))?, Some(format!("value{id}")));
+        }
+        assert_eq!(client.scan("key".to_owned())?.len(), 3);
+    }
+    Ok(())
+}
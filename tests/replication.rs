@@ -0,0 +1,143 @@
+use networked_kv_store::{
+    AsOf, DataFormat, KvStore, KvsClient, KvsError, KvsServer, Result, Snapshot, StoreOptions,
+};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+// Starts a server on a free port and returns its address.
+fn spawn_server(server: KvsServer) -> SocketAddr {
+    let addr = free_addr();
+    thread::spawn(move || server.run(addr).unwrap());
+    for _ in 0..100 {
+        if KvsClient::connect(addr).is_ok() {
+            return addr;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// Polls `key` on the server at `addr` until it holds `expected`.
+fn wait_for(addr: SocketAddr, key: &str, expected: Option<&str>) -> Result<()> {
+    let mut client = KvsClient::connect(addr)?;
+    for _ in 0..200 {
+        if client.get(key.to_owned())?.as_deref() == expected {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("{key} never became {expected:?}");
+}
+
+// A follower should receive existing and new writes and reject its own.
+#[test]
+fn follower_applies_primary_log() -> Result<()> {
+    let primary_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(primary_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let primary = spawn_server(KvsServer::new(store));
+    let follower = spawn_server(KvsServer::replica(
+        KvStore::open(follower_dir.path())?,
+        primary,
+    ));
+
+    wait_for(follower, "key1", Some("value1"))?;
+    let mut client = KvsClient::connect(primary)?;
+    client.set("key2".to_owned(), "value2".to_owned())?;
+    client.remove("key1".to_owned())?;
+    wait_for(follower, "key2", Some("value2"))?;
+    wait_for(follower, "key1", None)?;
+
+    let mut client = KvsClient::connect(follower)?;
+    assert!(matches!(
+        client.set("key3".to_owned(), "value3".to_owned()),
+        Err(KvsError::ReadOnly)
+    ));
+    Ok(())
+}
+
+// A follower behind compaction should catch up from a snapshot.
+#[test]
+fn follower_catches_up_after_compaction() -> Result<()> {
+    let primary_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    let mut follower_store = KvStore::open(follower_dir.path())?;
+    follower_store.set("stale".to_owned(), "value".to_owned())?;
+    drop(follower_store);
+
+    let mut store = KvStore::open(primary_dir.path())?;
+    for iter in 0..20 {
+        for key_id in 0..1000 {
+            store.set(format!("key{key_id}"), format!("{iter}"))?;
+        }
+    }
+    let caught_up = store.sequence();
+    let primary = spawn_server(KvsServer::new(store));
+    let follower = spawn_server(KvsServer::replica(
+        KvStore::open(follower_dir.path())?,
+        primary,
+    ));
+
+    wait_for(follower, "key999", Some("19"))?;
+    wait_for(follower, "key0", Some("19"))?;
+    wait_for(follower, "stale", None)?;
+
+    // Every key of the snapshot should be visible in the follower history.
+    let snapshot = Snapshot::open(follower_dir.path(), AsOf::Sequence(caught_up))?;
+    assert_eq!(snapshot.sequence(), caught_up);
+    assert_eq!(snapshot.get("key0"), Some("19"));
+    assert_eq!(snapshot.get("key999"), Some("19"));
+    assert_eq!(snapshot.get("stale"), None);
+    let mut out = Vec::new();
+    assert_eq!(
+        snapshot.export(&mut out, DataFormat::JsonLines, None)?,
+        1000
+    );
+
+    KvsClient::connect(primary)?.set("key0".to_owned(), "new".to_owned())?;
+    wait_for(follower, "key0", Some("new"))?;
+    Ok(())
+}
+
+// A primary retaining its history should replay it to followers, even after a restart.
+#[test]
+fn follower_replays_retained_history() -> Result<()> {
+    let primary_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions {
+        retain_history: true,
+    };
+
+    let mut store = KvStore::open_with_options(primary_dir.path(), options.clone())?;
+    for iter in 0..20 {
+        for key_id in 0..1000 {
+            store.set(format!("key{key_id}"), format!("{iter}"))?;
+        }
+    }
+    assert!(primary_dir.path().join("history").is_dir());
+    drop(store);
+
+    let store = KvStore::open_with_options(primary_dir.path(), options.clone())?;
+    let primary = spawn_server(KvsServer::new(store));
+    let follower = spawn_server(KvsServer::replica(
+        KvStore::open_with_options(follower_dir.path(), options)?,
+        primary,
+    ));
+    wait_for(follower, "key999", Some("19"))?;
+
+    let snapshot = Snapshot::open(follower_dir.path(), AsOf::Sequence(1000))?;
+    assert_eq!(snapshot.get("key0"), Some("0"));
+    assert_eq!(snapshot.get("key999"), Some("0"));
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use networked_kv_store::{
+    HashRing, KvStore, KvsClient, KvsServer, Result, Shard, ShardedKvsClient, Topology, rebalance,
+};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Starts a server on a free port and returns its address.
+fn spawn_server(store: KvStore) -> SocketAddr {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    thread::spawn(move || KvsServer::new(store).run(addr).unwrap());
+    for _ in 0..100 {
+        if KvsClient::connect(addr).is_ok() {
+            return addr;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+fn shard(name: &str, addr: SocketAddr) -> Shard {
+    Shard {
+        name: name.to_owned(),
+        addr,
+    }
+}
+
+// Adding a shard should only move the keys it takes over.
+#[test]
+fn ring_moves_few_keys() -> Result<()> {
+    let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+    let three = Topology::new(vec![shard("a", addr), shard("b", addr), shard("c", addr)]);
+    let mut shards = three.shards.clone();
+    shards.push(shard("d", addr));
+    let four = Topology::new(shards);
+
+    let (before, after) = (HashRing::new(&three)?, HashRing::new(&four)?);
+    let mut moved = 0;
+    for key_id in 0..10_000 {
+        let key = format!("key{key_id}");
+        let (old, new) = (before.owner(&key), after.owner(&key));
+        if old != new {
+            assert_eq!(new.name, "d");
+            moved += 1;
+        }
+    }
+    // the new shard should take about a quarter of the keys
+    assert!((1500..3500).contains(&moved), "{moved} keys moved");
+    Ok(())
+}
+
+// A topology naming a shard twice should be rejected.
+#[test]
+fn duplicate_shard_rejected() {
+    let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+    let topology = Topology::new(vec![shard("a", addr), shard("a", addr)]);
+    assert!(HashRing::new(&topology).is_err());
+}
+
+// Writes should land on the owning server and be readable after a rebalance.
+#[test]
+fn route_and_rebalance() -> Result<()> {
+    let dirs: Vec<_> = (0..3)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let mut shards = Vec::new();
+    for (dir, name) in dirs.iter().zip(["a", "b", "c"]) {
+        shards.push(shard(name, spawn_server(KvStore::open(dir.path())?)));
+    }
+    let old = Topology::new(shards[..2].to_vec());
+    let new = Topology::new(shards.clone());
+
+    let mut client = ShardedKvsClient::new(&old)?;
+    for key_id in 0..200 {
+        client.set(format!("key{key_id}"), key_id.to_string())?;
+    }
+    let ring = HashRing::new(&old)?;
+    let mut direct = KvsClient::connect(shards[0].addr)?;
+    for key_id in 0..200 {
+        let key = format!("key{key_id}");
+        let expected = (ring.owner(&key).name == "a").then(|| key_id.to_string());
+        assert_eq!(direct.get(key)?, expected);
+    }
+
+    let moved = rebalance(&old, &new)?;
+    assert!(moved > 0);
+    let mut client = ShardedKvsClient::new(&new)?;
+    for key_id in 0..200 {
+        assert_eq!(
+            client.get(format!("key{key_id}"))?,
+            Some(key_id.to_string())
+        );
+    }
+    assert_eq!(rebalance(&new, &new)?, 0);
+    Ok(())
+}
+
+// A scan should gather the matching keys of every shard in key order.
+#[test]
+fn scan_across_shards() -> Result<()> {
+    let dirs: Vec<_> = (0..3)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let mut shards = Vec::new();
+    for (dir, name) in dirs.iter().zip(["a", "b", "c"]) {
+        shards.push(shard(name, spawn_server(KvStore::open(dir.path())?)));
+    }
+    let topology = Topology::new(shards);
+
+    let mut client = ShardedKvsClient::new(&topology)?;
+    for key_id in 0..100 {
+        client.set(format!("key{key_id:03}"), key_id.to_string())?;
+        client.set(format!("other{key_id}"), key_id.to_string())?;
+    }
+
+    let entries = client.scan("key".to_owned())?;
+    let expected: Vec<_> = (0..100)
+        .map(|key_id| (format!("key{key_id:03}"), key_id.to_string()))
+        .collect();
+    assert_eq!(entries, expected);
+    Ok(())
+}
+
+// A shard renamed in place should keep its keys, one moved to another
+// address under the same name should get them back.
+#[test]
+fn rebalance_follows_addresses() -> Result<()> {
+    let dirs: Vec<_> = (0..3)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let addrs: Vec<_> = dirs
+        .iter()
+        .map(|dir| Ok(spawn_server(KvStore::open(dir.path())?)))
+        .collect::<Result<_>>()?;
+    let old = Topology::new(vec![shard("a", addrs[0]), shard("b", addrs[1])]);
+    let mut client = ShardedKvsClient::new(&old)?;
+    for key_id in 0..200 {
+        client.set(format!("key{key_id}"), key_id.to_string())?;
+    }
+
+    let renamed = Topology::new(vec![shard("a", addrs[0]), shard("c", addrs[1])]);
+    rebalance(&old, &renamed)?;
+    let moved = Topology::new(vec![shard("a", addrs[2]), shard("c", addrs[1])]);
+    assert!(rebalance(&renamed, &moved)? > 0);
+    assert!(
+        KvsClient::connect(addrs[0])?
+            .scan(String::new())?
+            .is_empty()
+    );
+
+    let mut client = ShardedKvsClient::new(&moved)?;
+    for key_id in 0..200 {
+        assert_eq!(
+            client.get(format!("key{key_id}"))?,
+            Some(key_id.to_string())
+        );
+    }
+    Ok(())
+}
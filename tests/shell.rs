@@ -0,0 +1,104 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{KvStore, KvsClient, KvsServer, Result};
+use std::fs;
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Starts a server on a free port and returns its address.
+fn spawn_server(store: KvStore) -> SocketAddr {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    thread::spawn(move || KvsServer::new(store).run(addr).unwrap());
+    for _ in 0..100 {
+        if KvsClient::connect(addr).is_ok() {
+            return addr;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// Runs `command` with `input` piped to its stdin, HOME pointing at `home`.
+fn run_with_input(mut command: Command, home: &TempDir, input: &str) -> Result<Output> {
+    let mut child = command
+        .env("HOME", home.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    Ok(child.wait_with_output()?)
+}
+
+// A session should run every command against one store and save its history.
+#[test]
+fn kvs_shell_session() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().expect("unable to create temporary working directory");
+    let mut command = Command::cargo_bin("kvs").unwrap();
+    command.arg("shell").current_dir(&temp_dir);
+    let output = run_with_input(
+        command,
+        &home,
+        "set key1 value1\nset \"key 2\" \"two words\"\nget \"key 2\"\nscan key\nrm key3\nbogus\nrm key1\nstats\nexit\nget key1\n",
+    )?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().take(8).collect::<Vec<_>>(),
+        [
+            "OK",
+            "OK",
+            "two words",
+            "key 2 two words",
+            "key1 value1",
+            "(2 keys)",
+            "Key not found",
+            "OK",
+        ]
+    );
+    assert!(stdout.contains("set: 2 calls, 0 errors"), "{stdout}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown command `bogus`"), "{stderr}");
+    // nothing after exit runs
+    assert!(!stdout.trim_end().ends_with("Key not found"), "{stdout}");
+
+    let history = fs::read_to_string(home.path().join(".kvs_history"))?;
+    assert!(history.contains("set key1 value1"), "{history}");
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key 2".to_owned())?, Some("two words".to_owned()));
+    Ok(())
+}
+
+// The client shell should keep one connection to the server.
+#[test]
+fn client_shell_session() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let home = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(KvStore::open(temp_dir.path())?);
+
+    let mut command = Command::cargo_bin("kvs-client").unwrap();
+    command.args(["shell", "--addr", &addr.to_string()]);
+    let output = run_with_input(
+        command,
+        &home,
+        "set key1 value1\nget key1\nget key2\nstats\n",
+    )?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().take(4).collect::<Vec<_>>(),
+        ["OK", "value1", "Key not found", &format!("server: {addr}")]
+    );
+    assert!(stdout.contains("get: 2 calls, 0 errors"), "{stdout}");
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{AsyncKvsServer, KvStore, KvsClient, KvsError, KvsServer, Result};
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn connect(addr: SocketAddr) -> KvsClient {
+    for _ in 0..100 {
+        if let Ok(client) = KvsClient::connect(addr) {
+            return client;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// A second store on the same directory should be refused until the first is gone.
+#[test]
+fn directory_lock() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(KvsError::DirectoryLocked(_))
+    ));
+    drop(store);
+    KvStore::open(temp_dir.path())?;
+    Ok(())
+}
+
+// An admin shutdown should close idle connections, sync the store and release it.
+#[test]
+fn admin_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let server = KvsServer::new(KvStore::open(temp_dir.path())?);
+    let (done, stopped) = mpsc::channel();
+    thread::spawn(move || done.send(server.run(addr)).unwrap());
+
+    let mut idle = connect(addr);
+    idle.set("key1".to_owned(), "value1".to_owned())?;
+    connect(addr).shutdown()?;
+    stopped.recv_timeout(Duration::from_secs(5)).unwrap()?;
+
+    assert!(idle.get("key1".to_owned()).is_err());
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// A shutdown handle should stop the async server the same way.
+#[test]
+fn async_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let server = AsyncKvsServer::new(KvStore::open(temp_dir.path())?);
+    let shutdown = server.shutdown_handle();
+    let (done, stopped) = mpsc::channel();
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        done.send(runtime.block_on(server.run(addr))).unwrap()
+    });
+
+    let mut idle = connect(addr);
+    idle.set("key1".to_owned(), "value1".to_owned())?;
+    shutdown.shutdown();
+    stopped.recv_timeout(Duration::from_secs(5)).unwrap()?;
+
+    assert!(idle.get("key1".to_owned()).is_err());
+    KvStore::open(temp_dir.path())?;
+    Ok(())
+}
+
+// A replica serving a watcher should stop following its primary, end the
+// subscription and release its store once shut down.
+fn releases_replica_store(run_async: bool) -> Result<()> {
+    let primary_dir = TempDir::new().expect("unable to create temporary working directory");
+    let replica_dir = TempDir::new().expect("unable to create temporary working directory");
+    let primary = free_addr();
+    let server = KvsServer::new(KvStore::open(primary_dir.path())?);
+    thread::spawn(move || server.run(primary).unwrap());
+    connect(primary);
+
+    let replica = free_addr();
+    let store = KvStore::open(replica_dir.path())?;
+    let (done, stopped) = mpsc::channel();
+    let shutdown = if run_async {
+        let server = AsyncKvsServer::replica(store, primary);
+        let shutdown = server.shutdown_handle();
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            done.send(runtime.block_on(server.run(replica))).unwrap()
+        });
+        shutdown
+    } else {
+        let server = KvsServer::replica(store, primary);
+        let shutdown = server.shutdown_handle();
+        thread::spawn(move || done.send(server.run(replica)).unwrap());
+        shutdown
+    };
+    // replayed in case the replica applies the write before watching
+    let mut events = connect(replica).watch(String::new(), Some(1))?;
+    connect(primary).set("key1".to_owned(), "value1".to_owned())?;
+    assert!(events.next().is_some());
+
+    shutdown.shutdown();
+    stopped.recv_timeout(Duration::from_secs(5)).unwrap()?;
+    assert!(events.next().is_none());
+    let mut store = KvStore::open(replica_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// The threaded server should release the store of a replica.
+#[test]
+fn replica_shutdown() -> Result<()> {
+    releases_replica_store(false)
+}
+
+// The async server should release the store of a replica.
+#[test]
+fn async_replica_shutdown() -> Result<()> {
+    releases_replica_store(true)
+}
+
+// kvs-server should exit successfully on SIGTERM.
+#[test]
+fn server_exits_on_sigterm() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", &addr.to_string()])
+        .current_dir(&temp_dir)
+        .stderr(Stdio::piped())
+        .spawn()?;
+    connect(addr).set("key1".to_owned(), "value1".to_owned())?;
+
+    Command::new("kill")
+        .args(["-TERM", &server.id().to_string()])
+        .assert()
+        .success();
+    assert!(server.wait()?.success());
+    let mut stderr = String::new();
+    server.stderr.take().unwrap().read_to_string(&mut stderr)?;
+    assert!(stderr.contains("Shut down cleanly"), "{stderr}");
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use networked_kv_store::{KvStore, KvsClient, KvsServer, Result, StoreOp};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Operations past the threshold should be recorded, newest first, with what they did.
+#[test]
+fn records_slow_operations() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("ignored".to_owned(), "value".to_owned())?;
+    assert!(store.slow_log(10).is_empty());
+
+    // every operation is slow with a zero threshold
+    store.set_slow_threshold(Some(Duration::ZERO));
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.get("key1".to_owned())?;
+    store.sync()?;
+    let entries = store.slow_log(10);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].op, StoreOp::Sync);
+    assert!(entries[0].synced);
+    assert_eq!(entries[0].key, None);
+    assert_eq!(entries[1].op, StoreOp::Get);
+    assert_eq!(entries[1].key.as_deref(), Some("key1"));
+    assert_eq!(entries[2].op, StoreOp::Set);
+    assert!(!entries[2].compacted);
+    assert!(entries[0].id > entries[1].id);
+    assert_eq!(store.slow_log(1), entries[..1]);
+
+    // overwriting a key over and over eventually compacts inline
+    let value = "v".repeat(1024);
+    for _ in 0..2000 {
+        store.set("key1".to_owned(), value.clone())?;
+    }
+    assert!(store.slow_log(128).iter().any(|entry| entry.compacted));
+    assert_eq!(store.slow_log(1000).len(), 128);
+
+    store.reset_slow_log();
+    assert!(store.slow_log(10).is_empty());
+    store.set_slow_threshold(Some(Duration::from_secs(60)));
+    store.get("key1".to_owned())?;
+    assert!(store.slow_log(10).is_empty());
+    Ok(())
+}
+
+// Clients should read and reset the slow log of a server.
+#[test]
+fn slow_log_over_the_network() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set_slow_threshold(Some(Duration::ZERO));
+    thread::spawn(move || KvsServer::new(store).run(addr).unwrap());
+    thread::sleep(Duration::from_millis(100));
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    client.get("missing".to_owned())?;
+    let entries = client.slow_log(10)?;
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].op, StoreOp::Get);
+    assert_eq!(entries[0].key.as_deref(), Some("missing"));
+
+    client.reset_slow_log()?;
+    assert!(client.slow_log(10)?.is_empty());
+    Ok(())
+}
@@ -1,8 +1,20 @@
+// the CLI tests pass their arguments as borrowed arrays
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::prelude::*;
-use networked_kv_store::{KvStore, Result};
+#[cfg(feature = "sled")]
+use networked_kv_store::SledKvsEngine;
+use networked_kv_store::{
+    KvStore, KvsClient, KvsEngine, KvsError, KvsServer, MemoryKvsEngine, Result,
+    SharedQueueThreadPool, ThreadPool,
+};
 use predicates::ord::eq;
 use predicates::str::{PredicateStrExt, contains, is_empty};
-use std::process::Command;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -177,11 +189,79 @@ fn cli_invalid_subcommand() {
         .failure();
 }
 
+// Runs the engine tests below on the engine `$engine`, opened in a
+// directory by `$open` and named `$name` on the command line.
+macro_rules! engine_tests {
+    ($module:ident, $engine:ty, $open:expr, $name:expr) => {
+        mod $module {
+            use super::*;
+
+            fn open(path: &Path) -> Result<$engine> {
+                ($open)(path)
+            }
+
+            #[test]
+            fn get_stored_value() -> Result<()> {
+                super::get_stored_value(open)
+            }
+
+            #[test]
+            fn overwrite_value() -> Result<()> {
+                super::overwrite_value(open)
+            }
+
+            #[test]
+            fn get_non_existent_value() -> Result<()> {
+                super::get_non_existent_value(open)
+            }
+
+            #[test]
+            fn remove_non_existent_key() -> Result<()> {
+                super::remove_non_existent_key(open)
+            }
+
+            #[test]
+            fn remove_key() -> Result<()> {
+                super::remove_key(open)
+            }
+
+            #[test]
+            fn scan_prefix() -> Result<()> {
+                super::scan_prefix(open)
+            }
+
+            #[test]
+            fn many_overwrites() -> Result<()> {
+                super::many_overwrites(open)
+            }
+
+            #[test]
+            fn serve_concurrent_clients() -> Result<()> {
+                super::serve_concurrent_clients(open)
+            }
+
+            #[test]
+            fn cli_serve_engine() -> Result<()> {
+                super::cli_serve_engine($name)
+            }
+        }
+    };
+}
+
+engine_tests!(kvs, KvStore, KvStore::open, "kvs");
+engine_tests!(
+    memory,
+    MemoryKvsEngine,
+    |path: &Path| MemoryKvsEngine::open(path, Duration::from_secs(60)),
+    "memory"
+);
+#[cfg(feature = "sled")]
+engine_tests!(sled_engine, SledKvsEngine, SledKvsEngine::open, "sled");
+
 // Should get previously stored value.
-#[test]
-fn get_stored_value() -> Result<()> {
+fn get_stored_value<E: KvsEngine>(open: fn(&Path) -> Result<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     store.set("key2".to_owned(), "value2".to_owned())?;
@@ -191,7 +271,7 @@ fn get_stored_value() -> Result<()> {
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
     assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
 
@@ -199,10 +279,9 @@ fn get_stored_value() -> Result<()> {
 }
 
 // Should overwrite existent value.
-#[test]
-fn overwrite_value() -> Result<()> {
+fn overwrite_value<E: KvsEngine>(open: fn(&Path) -> Result<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
@@ -211,7 +290,7 @@ fn overwrite_value() -> Result<()> {
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
     store.set("key1".to_owned(), "value3".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
@@ -220,40 +299,174 @@ fn overwrite_value() -> Result<()> {
 }
 
 // Should get `None` when getting a non-existent key.
-#[test]
-fn get_non_existent_value() -> Result<()> {
+fn get_non_existent_value<E: KvsEngine>(open: fn(&Path) -> Result<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     Ok(())
 }
 
-#[test]
-fn remove_non_existent_key() -> Result<()> {
+fn remove_non_existent_key<E: KvsEngine>(open: fn(&Path) -> Result<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
-    assert!(store.remove("key1".to_owned()).is_err());
+    let mut store = open(temp_dir.path())?;
+    assert!(matches!(
+        store.remove("key1".to_owned()),
+        Err(KvsError::KeyNotFound)
+    ));
     Ok(())
 }
 
-#[test]
-fn remove_key() -> Result<()> {
+fn remove_key<E: KvsEngine>(open: fn(&Path) -> Result<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert!(store.remove("key1".to_owned()).is_ok());
     assert_eq!(store.get("key1".to_owned())?, None);
+
+    // Open from disk again and check the key stays removed.
+    drop(store);
+    let mut store = open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    Ok(())
+}
+
+// Should list the keys starting with a prefix in order.
+fn scan_prefix<E: KvsEngine>(open: fn(&Path) -> Result<E>) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open(temp_dir.path())?;
+    for key in ["user:2", "order:1", "user:1", "users"] {
+        store.set(key.to_owned(), format!("{key} value"))?;
+    }
+    assert_eq!(
+        store.scan("user:")?,
+        vec![
+            ("user:1".to_owned(), "user:1 value".to_owned()),
+            ("user:2".to_owned(), "user:2 value".to_owned()),
+        ]
+    );
+    assert_eq!(store.scan("")?.len(), 4);
+    Ok(())
+}
+
+// Overwriting every key many times, enough for the log of a KvStore to be
+// compacted, should keep the last values across a reopen.
+fn many_overwrites<E: KvsEngine>(open: fn(&Path) -> Result<E>) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = open(temp_dir.path())?;
+    for iter in 0..40 {
+        for key_id in 0..1000 {
+            store.set(format!("key{key_id}"), format!("{iter}"))?;
+        }
+    }
+    store.remove("key0".to_owned())?;
+
+    drop(store);
+    let mut store = open(temp_dir.path())?;
+    assert_eq!(store.get("key0".to_owned())?, None);
+    for key_id in 1..1000 {
+        assert_eq!(store.get(format!("key{key_id}"))?, Some("39".to_owned()));
+    }
+    Ok(())
+}
+
+// A server should answer concurrent clients, and leave their writes in the
+// store once shut down.
+fn serve_concurrent_clients<E: KvsEngine>(open: fn(&Path) -> Result<E>) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let server = KvsServer::new(open(temp_dir.path())?).with_pool(SharedQueueThreadPool::new(4)?);
+    let shutdown = server.shutdown_handle();
+    let server = thread::spawn(move || server.run(addr));
+    connect(addr);
+
+    let clients: Vec<_> = (0..8)
+        .map(|client_id| {
+            thread::spawn(move || -> Result<()> {
+                let mut client = KvsClient::connect(addr)?;
+                for key_id in 0..20 {
+                    let key = format!("key{client_id}-{key_id}");
+                    client.set(key.clone(), key_id.to_string())?;
+                    assert_eq!(client.get(key)?, Some(key_id.to_string()));
+                }
+                client.remove(format!("key{client_id}-0"))?;
+                assert!(matches!(
+                    client.remove(format!("key{client_id}-0")),
+                    Err(KvsError::KeyNotFound)
+                ));
+                Ok(())
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap()?;
+    }
+    shutdown.shutdown();
+    server.join().unwrap()?;
+
+    let mut store = open(temp_dir.path())?;
+    assert_eq!(store.scan("key")?.len(), 8 * 19);
+    assert_eq!(store.get("key7-19".to_owned())?, Some("19".to_owned()));
+    Ok(())
+}
+
+// `kvs-server --engine <ENGINE>` should serve the engine to `kvs-client`.
+fn cli_serve_engine(engine: &str) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr().to_string();
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", engine, "--addr", &addr])
+        .current_dir(&temp_dir)
+        .stderr(Stdio::null())
+        .spawn()?;
+    connect(addr.parse().unwrap());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", &addr])
+        .assert()
+        .success()
+        .stdout(is_empty());
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", &addr])
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["shutdown", "--addr", &addr])
+        .assert()
+        .success();
+    assert!(server.wait()?.success());
     Ok(())
 }
 
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn connect(addr: SocketAddr) -> KvsClient {
+    for _ in 0..100 {
+        if let Ok(client) = KvsClient::connect(addr) {
+            return client;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
 // Insert data until total size of the directory decreases.
 // Test data correctness after compaction.
 #[test]
@@ -299,3 +512,24 @@ fn compaction() -> Result<()> {
 
     panic!("No compaction detected");
 }
+
+// Pages of a scan should follow their cursor and stop at the limit.
+#[test]
+fn scan_pages() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for key in ["user:3", "order:1", "user:1", "user:2", "users"] {
+        store.set(key.to_owned(), format!("{key} value"))?;
+    }
+    let keys = |page: Vec<(String, String)>| page.into_iter().map(|(key, _)| key).collect();
+
+    let page: Vec<String> = keys(store.scan_page("user:", None, 2)?);
+    assert_eq!(page, ["user:1", "user:2"]);
+    let page: Vec<String> = keys(store.scan_page("user:", Some("user:2"), 2)?);
+    assert_eq!(page, ["user:3"]);
+    // a cursor before the prefix starts from its first key
+    let page: Vec<String> = keys(store.scan_page("user:", Some("a"), 1)?);
+    assert_eq!(page, ["user:1"]);
+    assert!(store.scan_page("user:", Some("z"), 10)?.is_empty());
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use networked_kv_store::{
+    KvStore, KvsClient, KvsEngine, KvsServer, MemoryKvsEngine, Metrics, NaiveThreadPool,
+    RayonThreadPool, Result, SharedQueueThreadPool, StoreSettings, ThreadPool,
+};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Runs jobs on a pool and waits until every one of them has run.
+fn spawn_counter<P: ThreadPool>(pool: &P) {
+    const JOBS: usize = 64;
+    let counter = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..JOBS {
+        let counter = Arc::clone(&counter);
+        let sender = sender.clone();
+        pool.spawn(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            sender.send(()).unwrap();
+        });
+    }
+    for _ in 0..JOBS {
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), JOBS);
+}
+
+// Every pool should run all the jobs it is given.
+#[test]
+fn pools_run_jobs() -> Result<()> {
+    spawn_counter(&NaiveThreadPool::new(4)?);
+    spawn_counter(&SharedQueueThreadPool::new(4)?);
+    spawn_counter(&RayonThreadPool::new(4)?);
+    Ok(())
+}
+
+// Panicking jobs should not leave the pools without workers.
+#[test]
+fn pools_survive_panics() -> Result<()> {
+    let shared_queue = SharedQueueThreadPool::new(4)?;
+    let rayon = RayonThreadPool::new(4)?;
+    for _ in 0..8 {
+        shared_queue.spawn(|| panic!("job failed"));
+        rayon.spawn(|| panic!("job failed"));
+    }
+    spawn_counter(&shared_queue);
+    spawn_counter(&rayon);
+    Ok(())
+}
+
+// A server on a fixed size pool should serve concurrent clients.
+#[test]
+fn server_on_pool() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let server =
+        KvsServer::new(KvStore::open(temp_dir.path())?).with_pool(SharedQueueThreadPool::new(4)?);
+    thread::spawn(move || server.run(addr).unwrap());
+    thread::sleep(Duration::from_millis(100));
+
+    let clients: Vec<_> = (0..8)
+        .map(|client_id| {
+            thread::spawn(move || -> Result<()> {
+                let mut client = KvsClient::connect(addr)?;
+                for key_id in 0..20 {
+                    let key = format!("key{client_id}-{key_id}");
+                    client.set(key.clone(), key_id.to_string())?;
+                    assert_eq!(client.get(key)?, Some(key_id.to_string()));
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap()?;
+    }
+    Ok(())
+}
+
+// An engine panicking when the key `panic` is set.
+struct PanickingEngine(MemoryKvsEngine);
+
+impl KvsEngine for PanickingEngine {
+    const NAME: &'static str = "panicking";
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        assert_ne!(key, "panic", "engine failed");
+        self.0.set(key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.0.get(key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.0.remove(key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.0.scan(prefix)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.0.sync()
+    }
+
+    fn metrics(&self) -> Arc<Metrics> {
+        self.0.metrics()
+    }
+
+    fn settings(&self) -> StoreSettings {
+        self.0.settings()
+    }
+}
+
+// A job panicking with the store locked should not stop the server from
+// answering the next requests.
+#[test]
+fn server_survives_panicking_job() -> Result<()> {
+    let addr: SocketAddr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let server = KvsServer::new(PanickingEngine(MemoryKvsEngine::new()))
+        .with_pool(SharedQueueThreadPool::new(2)?);
+    thread::spawn(move || server.run(addr).unwrap());
+    thread::sleep(Duration::from_millis(100));
+
+    let mut client = KvsClient::connect(addr)?;
+    assert!(client.set("panic".to_owned(), "value".to_owned()).is_err());
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use networked_kv_store::{
+    AsyncKvsClient, AsyncKvsServer, ClientOptions, ClientTls, KvStore, KvsClient, KvsServer,
+    Result, ServerTls,
+};
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair, KeyUsagePurpose};
+use std::fs;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// A certificate authority with a server and a client certificate signed
+// by it, written as PEM files.
+struct Pki {
+    dir: TempDir,
+}
+
+impl Pki {
+    fn new() -> Pki {
+        let dir = TempDir::new().expect("unable to create temporary working directory");
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+        let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+        fs::write(dir.path().join("ca.pem"), ca.pem()).unwrap();
+
+        for (name, names) in [
+            (
+                "server",
+                vec!["localhost".to_owned(), "127.0.0.1".to_owned()],
+            ),
+            ("client", vec!["client".to_owned()]),
+        ] {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(names)
+                .unwrap()
+                .signed_by(&key, &ca)
+                .unwrap();
+            fs::write(dir.path().join(format!("{name}.pem")), cert.pem()).unwrap();
+            fs::write(dir.path().join(format!("{name}.key")), key.serialize_pem()).unwrap();
+        }
+
+        // an unrelated authority
+        let other = rcgen::generate_simple_self_signed(vec!["other".to_owned()]).unwrap();
+        fs::write(dir.path().join("other.pem"), other.cert.pem()).unwrap();
+        Pki { dir }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn server_tls(&self, verify_clients: bool) -> ServerTls {
+        let ca = self.path("ca.pem");
+        let client_ca = verify_clients.then_some(ca.as_path());
+        ServerTls::load(self.path("server.pem"), self.path("server.key"), client_ca).unwrap()
+    }
+
+    fn client_tls(&self, ca: &str) -> ClientTls {
+        ClientTls::load(self.path(ca), "localhost").unwrap()
+    }
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn options(tls: Option<ClientTls>) -> ClientOptions {
+    ClientOptions {
+        read_timeout: Some(Duration::from_secs(2)),
+        tls,
+        ..ClientOptions::default()
+    }
+}
+
+fn spawn_server(store_dir: &Path, tls: ServerTls) -> Result<SocketAddr> {
+    let addr = free_addr();
+    let server = KvsServer::new(KvStore::open(store_dir)?).with_tls(tls);
+    thread::spawn(move || server.run(addr).unwrap());
+    for _ in 0..100 {
+        if std::net::TcpStream::connect(addr).is_ok() {
+            return Ok(addr);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// Clients trusting the server's authority should talk to it, others should fail.
+#[test]
+fn tls_roundtrip() -> Result<()> {
+    let pki = Pki::new();
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(temp_dir.path(), pki.server_tls(false))?;
+
+    let mut client = KvsClient::connect_with(addr, &options(Some(pki.client_tls("ca.pem"))))?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    let mut plain = KvsClient::connect_with(addr, &options(None))?;
+    assert!(plain.get("key1".to_owned()).is_err());
+    // the handshake fails while connecting
+    let untrusting = KvsClient::connect_with(addr, &options(Some(pki.client_tls("other.pem"))));
+    assert!(untrusting.is_err());
+    Ok(())
+}
+
+// A server verifying clients should only accept certificates from its authority.
+#[test]
+fn mutual_tls() -> Result<()> {
+    let pki = Pki::new();
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(temp_dir.path(), pki.server_tls(true))?;
+
+    let mut anonymous = KvsClient::connect_with(addr, &options(Some(pki.client_tls("ca.pem"))))?;
+    assert!(
+        anonymous
+            .set("key1".to_owned(), "value1".to_owned())
+            .is_err()
+    );
+
+    let tls = pki
+        .client_tls("ca.pem")
+        .with_identity(pki.path("client.pem"), pki.path("client.key"))?;
+    let mut client = KvsClient::connect_with(addr, &options(Some(tls)))?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// Requests and replies spanning many records should survive a pipeline over TLS.
+#[test]
+fn tls_pipeline() -> Result<()> {
+    let pki = Pki::new();
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(temp_dir.path(), pki.server_tls(false))?;
+    let mut client = KvsClient::connect_with(addr, &options(Some(pki.client_tls("ca.pem"))))?;
+    let key = "k".repeat(8192);
+    let value = "v".repeat(8192);
+    client.set(key.clone(), value.clone())?;
+
+    let values = client.get_many(vec![key; 500])?;
+    assert_eq!(values.len(), 500);
+    assert!(
+        values
+            .iter()
+            .all(|reply| reply.as_deref() == Some(value.as_str()))
+    );
+    Ok(())
+}
+
+// The async server and client should speak TLS too, watches included.
+#[tokio::test(flavor = "multi_thread")]
+async fn async_tls() -> Result<()> {
+    let pki = Pki::new();
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = free_addr();
+    let server =
+        AsyncKvsServer::new(KvStore::open(temp_dir.path())?).with_tls(pki.server_tls(false));
+    tokio::spawn(server.run(addr));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let tls = pki.client_tls("ca.pem");
+    let mut events = AsyncKvsClient::connect_tls(addr, &tls)
+        .await?
+        .watch("user:".to_owned(), None)
+        .await?;
+    let mut client = AsyncKvsClient::connect_tls(addr, &tls).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    client.set("user:1".to_owned(), "alice".to_owned()).await?;
+    assert_eq!(
+        client.get("user:1".to_owned()).await?,
+        Some("alice".to_owned())
+    );
+    assert_eq!(events.next_event().await.unwrap()?.key(), "user:1");
+    Ok(())
+}
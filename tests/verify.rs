@@ -0,0 +1,143 @@
+use assert_cmd::prelude::*;
+use networked_kv_store::{KvStore, Result, repair, verify};
+use predicates::str::contains;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::Command;
+use tempfile::TempDir;
+
+// Appends garbage followed by a valid record to the newest log file.
+fn corrupt_store(temp_dir: &TempDir) {
+    let newest = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("log".as_ref()))
+        .filter(|path| std::fs::metadata(path).unwrap().len() > 0)
+        .max()
+        .expect("no log file");
+    let mut file = OpenOptions::new().append(true).open(newest).unwrap();
+    file.write_all(br#"{"Set":{"key":"broken","val"#).unwrap();
+    file.write_all(br#"{"Set":{"key":"key3","value":"value3"}}"#)
+        .unwrap();
+}
+
+// A store written through the API should verify cleanly.
+#[test]
+fn verify_clean_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key1".to_owned())?;
+    drop(store);
+
+    let report = verify(temp_dir.path())?;
+    assert!(report.is_ok());
+    assert_eq!(report.records, 3);
+    assert_eq!(report.live_keys, 1);
+    Ok(())
+}
+
+// Corrupt ranges and dangling removals should be reported.
+#[test]
+fn verify_reports_damage() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    corrupt_store(&temp_dir);
+    std::fs::write(
+        temp_dir.path().join("100.log"),
+        br#"{"Remove":{"key":"ghost"}}"#,
+    )?;
+    std::fs::write(temp_dir.path().join("notes.txt"), "hello")?;
+
+    let report = verify(temp_dir.path())?;
+    assert!(!report.is_ok());
+    assert_eq!(report.corrupt.len(), 1);
+    assert_eq!(report.dangling.len(), 1);
+    assert_eq!(report.dangling[0].key, "ghost");
+    assert_eq!(report.unexpected_files.len(), 1);
+    Ok(())
+}
+
+// Damage to the generations retained in history/ should be reported too.
+#[test]
+fn verify_checks_history() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    let history = temp_dir.path().join("history");
+    std::fs::create_dir(&history)?;
+    std::fs::write(
+        history.join("1.log"),
+        br#"{"Set":{"key":"key1","value":"old"}}{"Set":{"key":"broken","val"#,
+    )?;
+
+    let report = verify(temp_dir.path())?;
+    assert!(!report.is_ok());
+    assert_eq!(report.history_generations, vec![1]);
+    assert_eq!(report.history_records, 1);
+    assert_eq!(report.history_corrupt.len(), 1);
+    assert!(report.corrupt.is_empty());
+    assert_eq!(report.live_keys, 1);
+    Ok(())
+}
+
+// Repair should keep every readable record and leave a clean store.
+#[test]
+fn repair_salvages_records() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+    corrupt_store(&temp_dir);
+    assert!(KvStore::open(temp_dir.path()).is_err());
+
+    let report = repair(temp_dir.path())?;
+    assert_eq!(report.dropped.len(), 1);
+    assert_eq!(report.live_keys, 3);
+    assert!(verify(temp_dir.path())?.is_ok());
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+// `kvs verify` should exit with non-zero code on a damaged store and succeed after `kvs repair`.
+#[test]
+fn cli_verify_and_repair() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    corrupt_store(&temp_dir);
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["verify"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stdout(contains("corrupt: generation"));
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["repair"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("2 live keys"));
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["verify"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Ok(())
+}
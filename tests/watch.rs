@@ -0,0 +1,124 @@
+use networked_kv_store::{KvStore, KvsClient, KvsError, KvsServer, Result, WatchEvent};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Starts a server on a free port and returns its address.
+fn spawn_server(store: KvStore) -> SocketAddr {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    thread::spawn(move || KvsServer::new(store).run(addr).unwrap());
+    for _ in 0..100 {
+        if KvsClient::connect(addr).is_ok() {
+            return addr;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("server did not start");
+}
+
+// Watchers should only see changes under their prefix.
+#[test]
+fn watch_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let events = store.watch("user:");
+
+    store.set("user:1".to_owned(), "alice".to_owned())?;
+    store.set("order:1".to_owned(), "book".to_owned())?;
+    store.remove("user:1".to_owned())?;
+
+    let events: Vec<_> = events.try_iter().collect();
+    assert_eq!(
+        events,
+        vec![
+            WatchEvent::Set {
+                seq: 1,
+                key: "user:1".to_owned(),
+                value: "alice".to_owned()
+            },
+            WatchEvent::Remove {
+                seq: 3,
+                key: "user:1".to_owned()
+            },
+        ]
+    );
+    Ok(())
+}
+
+// Watching from a sequence should replay the logged changes first.
+#[test]
+fn watch_from_sequence() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..5 {
+        store.set(format!("key{key_id}"), "value".to_owned())?;
+    }
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    let events = store.watch_from("", 4)?;
+    store.set("key5".to_owned(), "value".to_owned())?;
+
+    let seqs: Vec<_> = events.try_iter().map(|event| event.seq()).collect();
+    assert_eq!(seqs, vec![4, 5, 6]);
+    Ok(())
+}
+
+// A watcher falling too far behind should be disconnected rather than
+// have every change buffered for it.
+#[test]
+fn lagging_watcher_disconnected() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let events = store.watch("");
+    for key_id in 0..2000 {
+        store.set(format!("key{key_id}"), "value".to_owned())?;
+    }
+
+    // what was buffered is still delivered, then the channel is closed
+    let seqs: Vec<_> = events.iter().map(|event| event.seq()).collect();
+    assert_eq!(seqs, (1..=1024).collect::<Vec<_>>());
+    Ok(())
+}
+
+// Basic operations and errors should work over the network.
+#[test]
+fn client_server_roundtrip() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = spawn_server(KvStore::open(temp_dir.path())?);
+
+    let mut client = KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(client.get("key2".to_owned())?, None);
+    client.remove("key1".to_owned())?;
+    assert!(matches!(
+        client.remove("key1".to_owned()),
+        Err(KvsError::KeyNotFound)
+    ));
+    Ok(())
+}
+
+// A remote watcher should receive changes made by other clients.
+#[test]
+fn watch_over_network() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("user:0".to_owned(), "zero".to_owned())?;
+    let addr = spawn_server(store);
+
+    let mut events = KvsClient::connect(addr)?.watch("user:".to_owned(), Some(1))?;
+    let mut client = KvsClient::connect(addr)?;
+    client.set("order:1".to_owned(), "book".to_owned())?;
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+
+    assert_eq!(events.next().unwrap()?.key(), "user:0");
+    let event = events.next().unwrap()?;
+    assert_eq!(event.key(), "user:1");
+    assert_eq!(event.seq(), 3);
+    Ok(())
+}